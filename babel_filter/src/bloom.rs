@@ -0,0 +1,75 @@
+use ahash::RandomState;
+
+/// A Bloom filter of strings. Membership checks never return false negatives, but return false
+/// positives at roughly the false-positive rate the filter was sized for.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    hasher: RandomState,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized to hold `capacity` items at false-positive rate `fp_rate`
+    /// (between 0 and 1, exclusive)
+    pub fn new(capacity: usize, fp_rate: f64) -> BloomFilter {
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(capacity * fp_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / capacity) * ln2).round().clamp(1.0, 32.0) as u32;
+
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            // fixed seeds so the hashes don't depend on anything but the item
+            hasher: RandomState::with_seeds(0x243F6A88, 0x85A308D3, 0x13198A2E, 0x03707344),
+        }
+    }
+
+    /// Returns the bit indices for an item, using double hashing to derive `num_hashes` indices
+    /// from one 64-bit hash
+    fn indices(&self, item: &str) -> impl Iterator<Item = u64> {
+        let hash = self.hasher.hash_one(item);
+        let (h1, h2) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        for index in self.indices(item) {
+            self.bits[(index / 64) as usize] |= 1 << (index % 64);
+        }
+    }
+
+    /// Returns true if the item may have been inserted, false if it definitely wasn't
+    pub fn contains(&self, item: &str) -> bool {
+        self.indices(item)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    /// Returns the size of the bit array, in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BloomFilter;
+
+    #[test]
+    fn no_false_negatives() {
+        let mut bloom = BloomFilter::new(10_000, 0.01);
+        for i in 0..10_000 {
+            bloom.insert(&format!("NCBIGene:{i}"));
+        }
+
+        assert!((0..10_000).all(|i| bloom.contains(&format!("NCBIGene:{i}"))));
+
+        let false_positives = (0..10_000)
+            .filter(|i| bloom.contains(&format!("MONDO:{i}")))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+}
@@ -0,0 +1,153 @@
+use ahash::AHashMap;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, time::UNIX_EPOCH};
+
+use crate::file::{reader::Reader, writer::Writer};
+use crate::filter_set;
+use crate::{Config, NodeListJson, BUF_CAPACITY};
+
+/// Identifies a version of one filter file
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct FileStamp {
+    path: String,
+    modified_nanos: u128,
+    size: u64,
+}
+
+/// Identifies the filter files (and the options) a cached filter set was built from. Anything
+/// that changes which nodes end up in the filter set has to be part of the key.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct CacheKey {
+    filter_files: Vec<FileStamp>,
+    exclude_category: Option<Vec<String>>,
+    include_category: Option<Vec<String>>,
+    ignore_category_case: bool,
+    category_regex: bool,
+    validate_equivalents: bool,
+    merge_duplicate_filter_records: bool,
+    filter_tolerant_parse: bool,
+    filter_tsv: Option<(usize, usize)>,
+    no_non_babel_nodes: bool,
+    match_equivalents: bool,
+    comment_prefix: Option<Vec<String>>,
+    require_category: bool,
+    exclude_curie_regex: Option<String>,
+}
+
+/// A cache of the parsed filter set, stored as JSONL in a cache directory. The first line is the
+/// `CacheKey` and every following line is a `NodeListJson` from the filter set.
+pub struct FilterCache {
+    path: Utf8PathBuf,
+    key: CacheKey,
+}
+
+impl FilterCache {
+    /// Creates the cache handle for the configured filter files, reading their current
+    /// modification times and sizes. The cache is named after the first filter file and a hash of
+    /// every filter file's path, so runs over different lists of filter files keep separate
+    /// caches.
+    ///
+    /// Returns `Err` if a filter file's metadata can't be read
+    pub fn new(cache_dir: &Utf8Path, args: &Config) -> io::Result<FilterCache> {
+        let mut filter_files = Vec::with_capacity(args.filter_file.len());
+        let mut paths_hasher = crc32fast::Hasher::new();
+        for filter_file in &args.filter_file {
+            let metadata = fs::metadata(filter_file)?;
+            let modified_nanos = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_nanos());
+            let path = filter_file
+                .canonicalize_utf8()
+                .unwrap_or_else(|_| filter_file.to_path_buf())
+                .into_string();
+            // the separator keeps `["ab", "c"]` and `["a", "bc"]` apart
+            paths_hasher.update(path.as_bytes());
+            paths_hasher.update(b"\0");
+            filter_files.push(FileStamp {
+                path,
+                modified_nanos,
+                size: metadata.len(),
+            });
+        }
+        let file_name = args
+            .filter_file
+            .first()
+            .and_then(|filter_file| filter_file.file_name())
+            .unwrap_or("filter");
+
+        Ok(FilterCache {
+            path: cache_dir.join(format!(
+                "{file_name}.{:08x}.filter-cache.jsonl",
+                paths_hasher.finalize()
+            )),
+            key: CacheKey {
+                filter_files,
+                exclude_category: args.exclude_category.clone(),
+                include_category: args.include_category.clone(),
+                ignore_category_case: args.ignore_category_case,
+                category_regex: args.category_regex,
+                validate_equivalents: args.validate_equivalents,
+                merge_duplicate_filter_records: args.merge_duplicate_filter_records,
+                filter_tolerant_parse: args.filter_tolerant_parse,
+                filter_tsv: args.filter_tsv.then(|| filter_set::tsv_columns(args)),
+                no_non_babel_nodes: args.no_non_babel_nodes,
+                match_equivalents: args.match_equivalents,
+                comment_prefix: args.comment_prefix.clone(),
+                require_category: args.require_category,
+                exclude_curie_regex: args.exclude_curie_regex.clone(),
+            },
+        })
+    }
+
+    /// Loads the cached filter set, or `None` if there is no cache or it was built from a different
+    /// version of the filter file
+    pub fn load(&self) -> Option<AHashMap<String, NodeListJson>> {
+        let mut lines = Reader::new(&self.path, BUF_CAPACITY).ok()?.lines();
+        let key: CacheKey = serde_json::from_str(&lines.next()?.ok()?).ok()?;
+        if key != self.key {
+            return None;
+        }
+
+        let mut filter_set = AHashMap::new();
+        for line in lines {
+            let node: NodeListJson = serde_json::from_str(&line.ok()?).ok()?;
+            filter_set.insert(node.id.clone(), node);
+        }
+        Some(filter_set)
+    }
+
+    /// Writes the filter set to the cache, replacing any previous cache for these filter files.
+    /// It's written to a temporary file that's renamed into place once complete, so a run that
+    /// stops partway never leaves a truncated cache to be loaded later.
+    ///
+    /// Returns `Err` if there is a problem writing the cache file
+    pub fn save(&self, filter_set: &AHashMap<String, NodeListJson>) -> io::Result<()> {
+        if let Some(cache_dir) = self.path.parent() {
+            fs::create_dir_all(cache_dir)?;
+        }
+        let file_name = self.path.file_name().unwrap_or_default();
+        let temp = self.path.with_file_name(format!(".writing.{file_name}"));
+        match self.write(&temp, filter_set) {
+            Ok(()) => fs::rename(&temp, &self.path),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        path: &Utf8Path,
+        filter_set: &AHashMap<String, NodeListJson>,
+    ) -> io::Result<()> {
+        let mut writer = Writer::new(path, BUF_CAPACITY)?;
+        writer.write_line(&serde_json::to_string(&self.key)?)?;
+        for node in filter_set.values() {
+            writer.write_line(&serde_json::to_string(node)?)?;
+        }
+        writer.finish()
+    }
+}
@@ -1,14 +1,141 @@
 use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
 
+#[derive(Default, Clone, Serialize)]
 pub struct Config {
   pub babel_directory: Utf8PathBuf,
-  pub filter_file: Utf8PathBuf,
+  pub stdin: bool,
+  pub stdout: bool,
+  pub filter_file: Vec<Utf8PathBuf>,
   pub output_directory: Utf8PathBuf,
   pub exclude_category: Option<Vec<String>>,
+  pub include_category: Option<Vec<String>>,
+  pub ignore_category_case: bool,
+  pub category_regex: bool,
   pub output_format: Option<OutputFormat>,
+  pub compression_level: Option<u32>,
+  pub zstd_level: i32,
+  pub list_delimiter: String,
+  pub per_prefix_stats: bool,
+  pub count_distinct_types: bool,
+  pub list_types: bool,
+  pub nonbabel_prefix: Option<Vec<String>>,
+  pub leftovers_as_ids: Option<Utf8PathBuf>,
+  pub two_pass: bool,
+  pub where_pointer: Option<Vec<String>>,
+  pub max_output_names: Option<usize>,
+  pub dedup_taxa: bool,
+  pub sort_names: bool,
+  pub filter_cache_dir: Option<Utf8PathBuf>,
+  pub expected_counts: Option<Utf8PathBuf>,
+  pub tolerance: f64,
+  pub fail_on_error: bool,
+  pub meta_sidecar: bool,
+  pub seed_filter_from_output: Option<Utf8PathBuf>,
+  pub progress_to_stderr: bool,
+  pub no_progress: bool,
+  pub summary_table: bool,
+  pub report_json: Option<Utf8PathBuf>,
+  pub quiet: bool,
+  pub progress_every: usize,
+  pub keep_largest_per_prefix: bool,
+  pub group_by_prefix: bool,
+  pub bloom_filter: bool,
+  pub bloom_fp_rate: f64,
+  pub ids_only: bool,
+  pub chunk_lines: Option<usize>,
+  pub chunk_bytes: Option<usize>,
+  pub preallocate: Option<u64>,
+  pub validate_equivalents: bool,
+  pub merge_duplicate_filter_records: bool,
+  pub match_equivalents: bool,
+  pub ignore_curie_case: bool,
+  pub filter_tolerant_parse: bool,
+  pub filter_tsv: bool,
+  pub tsv_id_column: Option<usize>,
+  pub tsv_category_column: Option<usize>,
+  pub provenance_header: bool,
+  pub concurrency: usize,
+  pub per_file_matches: bool,
+  pub concat_inputs: Option<Vec<String>>,
+  pub include_glob: Option<Vec<String>>,
+  pub exclude_curie_regex: Option<String>,
+  pub filter_set_shards: usize,
+  pub max_filter_set_size: Option<usize>,
+  pub strip_empty_fields: bool,
+  pub empty_taxa_null: bool,
+  pub normalize_unicode: Option<UnicodeForm>,
+  pub verify_roundtrip: bool,
+  pub preserve_key_order: bool,
+  pub report_coverage: bool,
+  pub report_throughput: bool,
+  pub curie_map: Option<Utf8PathBuf>,
+  pub progress_from_counts: Option<Utf8PathBuf>,
+  pub rewrite_mapped_curie: bool,
+  pub max_nonbabel_fraction: Option<f64>,
+  pub preserve_mtime: bool,
+  pub baseline_dir: Option<Utf8PathBuf>,
+  pub only_new_curies: bool,
+  pub read_ahead: Option<usize>,
+  pub decode_buffer: Option<usize>,
+  pub decode_threads: bool,
+  pub names_index: Option<Utf8PathBuf>,
+  pub comment_prefix: Option<Vec<String>>,
+  pub nonbabel_threads: usize,
+  pub non_babel_nodes_name: Option<String>,
+  pub no_non_babel_nodes: bool,
+  pub stable_order: bool,
+  pub require_all_types: Option<Vec<String>>,
+  pub require_category: bool,
+  pub taxa: Option<Vec<String>>,
+  pub keep_untaxed: bool,
+  pub curie_numeric_range: Option<Vec<String>>,
+  pub keep_non_numeric: bool,
+  pub debug_drop_reasons: Option<Utf8PathBuf>,
+  pub failed_lines_output: Option<Utf8PathBuf>,
+  pub shrink_filter_set: bool,
+  pub filter_set_memory_report: bool,
+  pub record_separator: Option<u8>,
+  pub preferred_name_in_file: Option<Utf8PathBuf>,
+  pub exclude_curie_file: Option<Utf8PathBuf>,
+  pub global_dedup: bool,
+  pub max_errors_per_file: Option<usize>,
+  pub repair_json: bool,
+  pub dry_run: bool,
+  pub output_subdir_per_file: bool,
+  pub assert_no_duplicates: bool,
+  pub report_duplicates: bool,
+  pub keep_duplicates: bool,
+  pub merge_into: Option<Utf8PathBuf>,
+  pub on_conflict: ConflictPolicy,
+  pub prune_orphans: bool,
+  pub min_name_length: Option<usize>,
+  pub max_name_length: Option<usize>,
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
   Gzipped,
+  Bzip2,
   Plaintext,
+  Zstd,
+  Csv,
+  Tsv,
+}
+
+/// A Unicode normalization form for names
+#[derive(Clone, Copy, Serialize)]
+pub enum UnicodeForm {
+  Nfc,
+  Nfkc,
+}
+
+/// What to do with a new node whose curie is already in the directory being merged into
+#[derive(Default, Clone, Copy, Serialize)]
+pub enum ConflictPolicy {
+  #[default]
+  KeepExisting,
+  KeepNew,
+  Error,
 }
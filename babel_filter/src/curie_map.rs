@@ -0,0 +1,61 @@
+use ahash::AHashMap;
+use std::path::Path;
+
+use crate::file::reader::Reader;
+use crate::BUF_CAPACITY;
+
+/// A crosswalk from Babel curies to the equivalent ids used in the filter file
+pub struct CurieMap {
+    map: AHashMap<String, String>,
+}
+
+impl CurieMap {
+    /// Loads a TSV of `from\tto` lines, skipping blank lines. It may be compressed, like the
+    /// Babel files.
+    ///
+    /// Returns `Err` with a message if the file can't be read or a line has no tab
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CurieMap, String> {
+        let lines = Reader::new(path, BUF_CAPACITY).map_err(|e| e.to_string())?.lines();
+        let mut map = AHashMap::new();
+        for (line_index, line) in lines.enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (from, to) = line
+                .split_once('\t')
+                .ok_or_else(|| format!("line {} isn't a tab-separated pair", line_index + 1))?;
+            map.insert(String::from(from.trim()), String::from(to.trim()));
+        }
+        Ok(CurieMap { map })
+    }
+
+    /// Returns the id a curie maps to, or `None` if it isn't in the map
+    pub fn get(&self, curie: &str) -> Option<&str> {
+        self.map.get(curie).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use tempfile::tempdir;
+
+    use super::CurieMap;
+    use crate::file::writer::Writer;
+
+    #[test]
+    fn loads_gzipped_curie_map() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("curie_map.tsv.gz");
+        Writer::new(&path, 32_000)?.write_line("MONDO:1\tDOID:1")?.write_line("")?;
+
+        let curie_map = CurieMap::load(&path).unwrap();
+
+        assert_eq!(curie_map.get("MONDO:1"), Some("DOID:1"));
+        assert_eq!(curie_map.get("MONDO:2"), None);
+
+        dir.close()
+    }
+}
@@ -0,0 +1,316 @@
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use super::writer::{CompressionLevels, Writer};
+
+/// Returns the path of a numbered part of `path`, inserting `.partNNNN` before the first extension
+/// so compression is preserved, e.g. `Gene.txt.gz` -> `Gene.part0002.txt.gz`
+pub fn part_path<P: AsRef<Path>>(path: P, part: usize) -> PathBuf {
+    let path = path.as_ref();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let part_name = match file_name.split_once('.') {
+        Some((stem, extensions)) => format!("{stem}.part{part:04}.{extensions}"),
+        None => format!("{file_name}.part{part:04}"),
+    };
+    path.with_file_name(part_name)
+}
+
+/// Returns the file name a part was split from, i.e. `file_name` without its `.partNNNN`
+/// segment, or `None` if it isn't a part
+pub fn unpart_name(file_name: &str) -> Option<String> {
+    let (stem, rest) = file_name.split_once(".part")?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    if digits == 0 {
+        return None;
+    }
+    match &rest[digits..] {
+        "" => Some(String::from(stem)),
+        extensions if extensions.starts_with('.') => Some(format!("{stem}{extensions}")),
+        _ => None,
+    }
+}
+
+/// How big a part of a `ChunkedWriter` can get. A limit of 0 is treated as no limit.
+#[derive(Clone, Copy, Default)]
+pub struct ChunkLimits {
+    /// The maximum number of lines per part
+    pub lines: Option<usize>,
+    /// The approximate maximum number of uncompressed bytes per part
+    pub bytes: Option<usize>,
+}
+
+impl ChunkLimits {
+    fn is_set(&self) -> bool {
+        self.lines.is_some() || self.bytes.is_some()
+    }
+}
+
+/// A `Writer` that rolls over to a new numbered part file (see `part_path`) every `chunk_lines`
+/// lines, or before a line that would take a part past `chunk_bytes` uncompressed bytes. A line
+/// larger than `chunk_bytes` still goes in one part, on its own. Without a chunk size it writes a
+/// single file at the given path. An optional header line starts every part; it doesn't count
+/// towards the part's lines, but does towards its bytes.
+pub struct ChunkedWriter {
+    path: PathBuf,
+    limits: ChunkLimits,
+    buffer_capacity: usize,
+    levels: CompressionLevels,
+    header: Option<String>,
+    separator: u8,
+    /// Bytes to preallocate each part with
+    preallocate: Option<u64>,
+    writer: Writer,
+    lines_in_part: usize,
+    parts: usize,
+    /// Whether the lines go to the standard output, which is never split (see `to_stdout`)
+    stdout: bool,
+}
+
+impl ChunkedWriter {
+    /// Creates the writer and its first file. A chunk size of 0 is treated as no chunking.
+    ///
+    /// Returns `Err` if there is a problem creating the file
+    pub fn new<P>(
+        path: P,
+        chunk_lines: Option<usize>,
+        buffer_capacity: usize,
+    ) -> io::Result<ChunkedWriter>
+    where
+        P: AsRef<Path>,
+    {
+        let limits = ChunkLimits {
+            lines: chunk_lines,
+            bytes: None,
+        };
+        ChunkedWriter::with_levels(path, limits, buffer_capacity, CompressionLevels::default())
+    }
+
+    /// Like `new`, with both chunk limits, compressing every part at `levels` instead of the
+    /// defaults
+    ///
+    /// Returns `Err` if there is a problem creating the file
+    pub fn with_levels<P>(
+        path: P,
+        limits: ChunkLimits,
+        buffer_capacity: usize,
+        levels: CompressionLevels,
+    ) -> io::Result<ChunkedWriter>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let limits = ChunkLimits {
+            lines: limits.lines.filter(|&lines| lines > 0),
+            bytes: limits.bytes.filter(|&bytes| bytes > 0),
+        };
+        let first_path = if limits.is_set() {
+            part_path(&path, 1)
+        } else {
+            path.clone()
+        };
+
+        Ok(ChunkedWriter {
+            writer: Writer::with_levels(first_path, buffer_capacity, levels)?,
+            path,
+            limits,
+            buffer_capacity,
+            levels,
+            header: None,
+            separator: b'\n',
+            preallocate: None,
+            lines_in_part: 0,
+            parts: 1,
+            stdout: false,
+        })
+    }
+
+    /// Creates a writer to the standard output, as one uncompressed part with no file
+    pub fn to_stdout(buffer_capacity: usize) -> ChunkedWriter {
+        ChunkedWriter {
+            writer: Writer::to_stdout(buffer_capacity),
+            path: PathBuf::new(),
+            limits: ChunkLimits::default(),
+            buffer_capacity,
+            levels: CompressionLevels::default(),
+            header: None,
+            separator: b'\n',
+            preallocate: None,
+            lines_in_part: 0,
+            parts: 1,
+            stdout: true,
+        }
+    }
+
+    /// Ends lines with `separator` instead of a newline, in every part
+    pub fn with_separator(mut self, separator: u8) -> ChunkedWriter {
+        self.separator = separator;
+        self.writer = self.writer.with_separator(separator);
+        self
+    }
+
+    /// Preallocates `bytes` for the current part and every later one (see
+    /// `Writer::with_preallocation`)
+    ///
+    /// Returns `Err` if there is a problem extending the current part
+    pub fn with_preallocation(mut self, bytes: Option<u64>) -> io::Result<ChunkedWriter> {
+        if let Some(bytes) = bytes {
+            self.writer = self.writer.with_preallocation(bytes)?;
+        }
+        self.preallocate = bytes;
+        Ok(self)
+    }
+
+    /// Writes a header line to the current part and to the start of every later part
+    ///
+    /// Returns `Err` if there is a problem writing to the current part
+    pub fn set_header(&mut self, header: String) -> io::Result<&mut ChunkedWriter> {
+        self.writer.write_line(&header)?;
+        self.header = Some(header);
+        Ok(self)
+    }
+
+    /// Appends a line, first starting a new part if the current one is full
+    ///
+    /// Returns `Err` if there is a problem creating a part or writing to it
+    pub fn write_line(&mut self, line: &str) -> io::Result<&mut ChunkedWriter> {
+        self.write_line_bytes(line.as_bytes())
+    }
+
+    /// Like `write_line`, but for a line that's already encoded
+    ///
+    /// Returns `Err` if there is a problem creating a part or writing to it
+    pub fn write_line_bytes(&mut self, line: &[u8]) -> io::Result<&mut ChunkedWriter> {
+        let full_of_lines = self.limits.lines == Some(self.lines_in_part);
+        // an empty part takes the line however large it is
+        let full_of_bytes = self.limits.bytes.is_some_and(|bytes| {
+            let bytes_after = self.writer.bytes_written() + line.len() as u64 + 1;
+            self.lines_in_part > 0 && bytes_after > bytes as u64
+        });
+        if full_of_lines || full_of_bytes {
+            self.parts += 1;
+            let part = part_path(&self.path, self.parts);
+            let mut writer = Writer::with_levels(part, self.buffer_capacity, self.levels)?
+                .with_separator(self.separator);
+            if let Some(bytes) = self.preallocate {
+                writer = writer.with_preallocation(bytes)?;
+            }
+            mem::replace(&mut self.writer, writer).finish()?;
+            if let Some(ref header) = self.header {
+                self.writer.write_line(header)?;
+            }
+            self.lines_in_part = 0;
+        }
+        self.writer.write_line_bytes(line)?;
+        self.lines_in_part += 1;
+        Ok(self)
+    }
+
+    /// Finishes the current part (see `Writer::finish`). Earlier parts were finished as they
+    /// filled up.
+    ///
+    /// Returns `Err` if there is a problem writing the rest of the part
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finish()
+    }
+
+    /// Returns the number of files written so far
+    pub fn parts(&self) -> usize {
+        self.parts
+    }
+
+    /// Returns the paths of the files written so far, which is none for the standard output
+    pub fn paths(&self) -> Vec<PathBuf> {
+        if self.stdout {
+            Vec::new()
+        } else if self.limits.is_set() {
+            (1..=self.parts).map(|part| part_path(&self.path, part)).collect()
+        } else {
+            vec![self.path.clone()]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io};
+    use tempfile::tempdir;
+
+    use super::{part_path, unpart_name, ChunkLimits, ChunkedWriter};
+    use crate::file::writer::CompressionLevels;
+
+    #[test]
+    fn names_parts_before_extensions() {
+        assert_eq!(part_path("out/Gene.txt.gz", 2).to_str(), Some("out/Gene.part0002.txt.gz"));
+        assert_eq!(part_path("out/Gene", 12).to_str(), Some("out/Gene.part0012"));
+        assert_eq!(unpart_name("Gene.part0002.txt.gz").as_deref(), Some("Gene.txt.gz"));
+        assert_eq!(unpart_name("Gene.part0012").as_deref(), Some("Gene"));
+        assert_eq!(unpart_name("Gene.txt.gz"), None);
+        assert_eq!(unpart_name("Gene.partial.txt"), None);
+    }
+
+    #[test]
+    fn rolls_over_every_chunk_lines() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt");
+
+        let mut writer = ChunkedWriter::new(&path, Some(2), 32_000)?;
+        for line in ["a", "b", "c", "d", "e"] {
+            writer.write_line(line)?;
+        }
+        assert_eq!(writer.parts(), 3);
+        drop(writer);
+
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0001.txt"))?, "a\nb\n");
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0002.txt"))?, "c\nd\n");
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0003.txt"))?, "e\n");
+        assert!(!path.exists());
+
+        dir.close()
+    }
+
+    #[test]
+    fn rolls_over_before_chunk_bytes() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt");
+        let limits = ChunkLimits {
+            lines: None,
+            bytes: Some(6),
+        };
+
+        let mut writer =
+            ChunkedWriter::with_levels(&path, limits, 32_000, CompressionLevels::default())?;
+        for line in ["a", "bb", "oversized", "c", "dd", "e"] {
+            writer.write_line(line)?;
+        }
+        assert_eq!(writer.parts(), 4);
+        drop(writer);
+
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0001.txt"))?, "a\nbb\n");
+        // a line over the cap gets a part to itself
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0002.txt"))?, "oversized\n");
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0003.txt"))?, "c\ndd\n");
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0004.txt"))?, "e\n");
+        assert!(!path.exists());
+
+        dir.close()
+    }
+
+    #[test]
+    fn writes_header_to_every_part() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt");
+
+        let mut writer = ChunkedWriter::new(&path, Some(1), 32_000)?;
+        writer.set_header(String::from("header"))?;
+        for line in ["a", "b"] {
+            writer.write_line(line)?;
+        }
+        drop(writer);
+
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0001.txt"))?, "header\na\n");
+        assert_eq!(fs::read_to_string(dir.path().join("test.part0002.txt"))?, "header\nb\n");
+
+        dir.close()
+    }
+}
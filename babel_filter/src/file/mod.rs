@@ -1,2 +1,4 @@
+pub mod chunked;
+pub mod prefetch;
 pub mod reader;
 pub mod writer;
\ No newline at end of file
@@ -0,0 +1,85 @@
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// A reader that reads its source ahead of the consumer on a background thread, holding up to
+/// roughly `read_ahead` bytes in a bounded queue of blocks. This overlaps disk I/O with parsing,
+/// which only helps when the source is slow, sequential storage such as a spinning disk or a
+/// network mount; on fast storage it just adds a copy.
+pub struct PrefetchReader {
+    blocks: Receiver<io::Result<Vec<u8>>>,
+    block: Vec<u8>,
+    position: usize,
+}
+
+impl PrefetchReader {
+    /// Starts reading `source` in blocks of `block_size` bytes on a new thread. The thread stops at
+    /// the end of the source, on the first read error, or once the reader is dropped.
+    pub fn new<R>(mut source: R, block_size: usize, read_ahead: usize) -> PrefetchReader
+    where
+        R: Read + Send + 'static,
+    {
+        let block_size = block_size.max(1);
+        let (sender, blocks) = mpsc::sync_channel(read_ahead.div_ceil(block_size).max(1));
+        thread::spawn(move || loop {
+            let mut block = vec![0; block_size];
+            match source.read(&mut block) {
+                Ok(0) => break,
+                Ok(n) => {
+                    block.truncate(n);
+                    if sender.send(Ok(block)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    break;
+                }
+            }
+        });
+
+        PrefetchReader {
+            blocks,
+            block: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for PrefetchReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.block.len() {
+            match self.blocks.recv() {
+                Ok(block) => {
+                    self.block = block?;
+                    self.position = 0;
+                }
+                // the thread hung up, so the source is exhausted
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.position);
+        buf[..n].copy_from_slice(&self.block[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::PrefetchReader;
+
+    #[test]
+    fn reads_source_in_order() -> io::Result<()> {
+        let source: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut read = Vec::new();
+        PrefetchReader::new(io::Cursor::new(source.clone()), 7, 64).read_to_end(&mut read)?;
+
+        assert_eq!(read, source);
+        Ok(())
+    }
+}
@@ -1,44 +1,224 @@
-use flate2::read::GzDecoder;
-use std::ffi::OsStr;
+use bzip2::bufread::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
 
-/// Buffered file reader that supports gzipped files
+use super::prefetch::PrefetchReader;
+
+/// The first two bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// The first three bytes of every bzip2 stream, before its block size
+const BZIP2_MAGIC: [u8; 3] = *b"BZh";
+/// The first four bytes of every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Buffered file reader that supports gzipped, bzip2 and zstd files
 pub struct Reader {
   reader: Box<dyn BufRead>,
+  separator: u8,
 }
  
 impl Reader {
-  /// Creates a buffered file reader given a `Path`. It checks the file extension for `.gz`
-  /// to determine whether to decompress with `flate2` as it reads. Note that this only checks
-  /// the file extension so it's up to the user to ensure files have the proper extensions.
+  /// Creates a buffered file reader given a `Path`. It checks the start of the file for the gzip,
+  /// bzip2 or zstd magic number to determine whether to decompress (with `flate2`, `bzip2` or
+  /// `zstd`) as it reads, so compressed files are decompressed whatever their extension.
   /// 
-  /// Returns `Err` if there is an issue opening the file.
+  /// Returns `Err` if there is an issue opening or reading the file.
   pub fn new<P>(path: P, buffer_capacity: usize) -> io::Result<Reader>
+  where
+      P: AsRef<Path>,
+  {
+      Reader::with_options(path, buffer_capacity, None, None, false)
+  }
+
+  /// Like `new`, with tuning options for slow or compressed inputs:
+  /// - `decode_buffer` sets the capacity of the buffer over the decompressed stream of a
+  ///   compressed file, while `buffer_capacity` still buffers the compressed bytes. Defaults to
+  ///   `buffer_capacity`.
+  /// - `read_ahead` reads the file ahead on a background thread, keeping up to that many bytes
+  ///   queued (see `PrefetchReader`). Decompression still happens on the calling thread.
+  /// - `decode_thread` decompresses a compressed file on its own background thread, so the calling
+  ///   thread only splits and parses records. This only helps when a spare core is free to
+  ///   decompress while the caller parses.
+  ///
+  /// Returns `Err` if there is an issue opening or reading the file.
+  pub fn with_options<P>(
+      path: P,
+      buffer_capacity: usize,
+      decode_buffer: Option<usize>,
+      read_ahead: Option<usize>,
+      decode_thread: bool,
+  ) -> io::Result<Reader>
   where
       P: AsRef<Path>,
   {
       let file = File::open(&path)?;
+      let decode_buffer = decode_buffer.unwrap_or(buffer_capacity);
+      let buffers = (buffer_capacity, decode_buffer);
+      match read_ahead {
+          Some(read_ahead) => {
+              let prefetch = PrefetchReader::new(file, buffer_capacity, read_ahead);
+              Reader::from_source(prefetch, buffers, decode_thread)
+          }
+          None => Reader::from_source(file, buffers, decode_thread),
+      }
+  }
+
+  /// Like `new`, reading the standard input instead of a file. It's checked for a magic number
+  /// the same way.
+  ///
+  /// Returns `Err` if there is an issue reading the standard input.
+  pub fn from_stdin(buffer_capacity: usize) -> io::Result<Reader> {
+      Reader::from_reader(io::stdin(), buffer_capacity)
+  }
+
+  /// Like `new`, reading any source of bytes (such as one in memory) instead of a file. It's
+  /// checked for a magic number the same way.
+  ///
+  /// Returns `Err` if there is an issue reading the source.
+  pub fn from_reader<R>(source: R, buffer_capacity: usize) -> io::Result<Reader>
+  where
+      R: Read + Send + 'static,
+  {
+      Reader::from_source(source, (buffer_capacity, buffer_capacity), false)
+  }
+
+  /// Reads the files at `paths` one after another as a single stream, each opened (and
+  /// decompressed) as by `with_options`. A file that doesn't end with a separator runs its last
+  /// record into the first record of the next one.
+  ///
+  /// Returns `Err` if there is an issue opening or reading any of the files.
+  pub fn concat<P>(
+      paths: &[P],
+      buffer_capacity: usize,
+      decode_buffer: Option<usize>,
+      read_ahead: Option<usize>,
+      decode_thread: bool,
+  ) -> io::Result<Reader>
+  where
+      P: AsRef<Path>,
+  {
+      let mut readers = paths.iter().map(|path| {
+          Reader::with_options(path, buffer_capacity, decode_buffer, read_ahead, decode_thread)
+              .map(|reader| reader.reader)
+      });
+      let first = readers.next().unwrap_or_else(|| Ok(Box::new(io::empty())))?;
+      let reader = readers.try_fold(first, |chained, next| {
+          Ok::<Box<dyn BufRead>, io::Error>(Box::new(chained.chain(next?)))
+      })?;
 
-      let reader: Box<dyn BufRead> = if path.as_ref().extension() == Some(&OsStr::new("gz")) {
-          Box::new(BufReader::with_capacity(buffer_capacity, GzDecoder::new(file)))
+      Ok(Reader { reader, separator: b'\n' })
+  }
+
+  /// Wraps the raw bytes of a file in a buffer, decompressing if they start with the gzip, bzip2
+  /// or zstd magic number. `buffers` are the compressed and decompressed buffer capacities.
+  fn from_source<R>(source: R, buffers: (usize, usize), decode_thread: bool) -> io::Result<Reader>
+  where
+      R: Read + Send + 'static,
+  {
+      let (buffer_capacity, decode_buffer) = buffers;
+      let mut source = source;
+      // a pipe can deliver less than the longest magic number per read, so read until there's
+      // all of it or the source ends, and put those bytes back in front of the rest
+      let mut start = Vec::with_capacity(ZSTD_MAGIC.len());
+      (&mut source).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut start)?;
+      let (is_gzip, is_zstd) = (start.starts_with(&GZIP_MAGIC), start.starts_with(&ZSTD_MAGIC));
+      let is_bzip2 = start.starts_with(&BZIP2_MAGIC);
+      let buffered =
+          BufReader::with_capacity(buffer_capacity, io::Cursor::new(start).chain(source));
+      let reader: Box<dyn BufRead> = if is_gzip {
+          // a plain decoder would stop after the first of several concatenated gzip streams
+          Reader::decoded(MultiGzDecoder::new(buffered), decode_buffer, decode_thread)
+      } else if is_bzip2 {
+          // like gzip, parallel compressors write one bzip2 stream per block of input
+          Reader::decoded(MultiBzDecoder::new(buffered), decode_buffer, decode_thread)
+      } else if is_zstd {
+          let decoder = zstd::stream::read::Decoder::with_buffer(buffered)?;
+          Reader::decoded(decoder, decode_buffer, decode_thread)
       } else {
-          Box::new(BufReader::with_capacity(buffer_capacity, file))
+          Box::new(buffered)
       };
 
-      Ok(Reader { reader })
+      Ok(Reader { reader, separator: b'\n' })
+  }
+
+  /// Buffers a decompressed stream, decompressing on a background thread if `decode_thread`
+  fn decoded<D>(decoder: D, decode_buffer: usize, decode_thread: bool) -> Box<dyn BufRead>
+  where
+      D: Read + Send + 'static,
+  {
+      if decode_thread {
+          // a few decoded blocks queued lets decoding run ahead of parsing
+          let decoded = PrefetchReader::new(decoder, decode_buffer, 4 * decode_buffer);
+          Box::new(BufReader::with_capacity(decode_buffer, decoded))
+      } else {
+          Box::new(BufReader::with_capacity(decode_buffer, decoder))
+      }
+  }
+
+  /// Splits records on `separator` instead of newlines
+  pub fn with_separator(mut self, separator: u8) -> Reader {
+      self.separator = separator;
+      self
   }
 
-  /// Returns an iterator of lines on this reader
-  pub fn lines(self) -> std::io::Lines<Box<dyn BufRead>> {
-      self.reader.lines()
+  /// Returns the buffered (and decompressed) stream, for parsers that don't split it into records
+  pub fn into_inner(self) -> Box<dyn BufRead> {
+      self.reader
+  }
+
+  /// Returns an iterator of lines (records) on this reader
+  pub fn lines(self) -> Lines {
+      Lines {
+          reader: self.reader,
+          separator: self.separator,
+          bytes_read: 0,
+      }
+  }
+}
+
+/// An iterator over the records of a `Reader`, without their separators. Like
+/// `BufRead::lines`, newline-separated records also have a trailing `\r` removed.
+pub struct Lines {
+  reader: Box<dyn BufRead>,
+  separator: u8,
+  bytes_read: u64,
+}
+
+impl Lines {
+  /// The number of bytes read so far, including separators. For gzipped files this counts the
+  /// decompressed bytes.
+  pub fn bytes_read(&self) -> u64 {
+      self.bytes_read
+  }
+}
+
+impl Iterator for Lines {
+  type Item = io::Result<String>;
+
+  fn next(&mut self) -> Option<io::Result<String>> {
+      let mut buf = Vec::new();
+      match self.reader.read_until(self.separator, &mut buf) {
+          Ok(0) => None,
+          Ok(n) => {
+              self.bytes_read += n as u64;
+              if buf.last() == Some(&self.separator) {
+                  buf.pop();
+                  if self.separator == b'\n' && buf.last() == Some(&b'\r') {
+                      buf.pop();
+                  }
+              }
+              Some(String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+          }
+          Err(e) => Some(Err(e)),
+      }
   }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::{self, Write}};
+    use std::{fs::File, io::{self, Read, Write}};
 
     use tempfile::tempdir;
 
@@ -67,6 +247,31 @@ mod tests {
         dir.close()
     }
 
+    #[test]
+    fn splits_on_custom_separator() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt");
+        crate::file::writer::Writer::new(&path, 32_000)?
+            .with_separator(0x1e)
+            .write_line("read\nmy")?
+            .write_line("records")?;
+
+        assert_eq!(std::fs::read(&path)?, b"read\nmy\x1erecords\x1e");
+
+        let lines: Vec<String> = Reader::new(&path, 32_000)?
+            .with_separator(0x1e)
+            .lines()
+            .collect::<io::Result<_>>()?;
+
+        assert_eq!(lines, vec!["read\nmy", "records"]);
+
+        let mut lines = Reader::new(&path, 32_000)?.with_separator(0x1e).lines();
+        lines.by_ref().for_each(drop);
+        assert_eq!(lines.bytes_read(), 16);
+
+        dir.close()
+    }
+
     #[test]
     fn read_lines_from_gzip() -> io::Result<()> {
         let dir = tempdir()?;
@@ -96,4 +301,209 @@ mod tests {
 
         dir.close()
     }
-}
\ No newline at end of file
+
+    /// Delivers one byte per read, like a slow pipe
+    struct OneByteAtATime<R>(R);
+
+    impl<R: Read> Read for OneByteAtATime<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn detects_magic_number_split_across_reads() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt.gz");
+        crate::file::writer::Writer::new(&path, 32_000)?
+            .write_line("gzipped")?
+            .write_line("lines")?;
+        let source = OneByteAtATime(io::Cursor::new(std::fs::read(&path)?));
+
+        let lines: Vec<String> = Reader::from_reader(source, 32_000)?
+            .lines()
+            .collect::<io::Result<_>>()?;
+
+        assert_eq!(lines, vec!["gzipped", "lines"]);
+
+        // a source shorter than the longest magic number is read as it is
+        let source = OneByteAtATime(io::Cursor::new(b"a\n".to_vec()));
+        let lines: Vec<String> = Reader::from_reader(source, 32_000)?
+            .lines()
+            .collect::<io::Result<_>>()?;
+        assert_eq!(lines, vec!["a"]);
+
+        dir.close()
+    }
+
+    #[test]
+    fn detects_gzip_without_gz_extension() -> io::Result<()> {
+        let dir = tempdir()?;
+        let gz_path = dir.path().join("compendia.txt.gz");
+        crate::file::writer::Writer::new(&gz_path, 32_000)?
+            .write_line("gzipped")?
+            .write_line("lines")?;
+        let path = dir.path().join("compendia.txt");
+        std::fs::rename(&gz_path, &path)?;
+
+        let lines: Vec<String> = Reader::new(&path, 32_000)?.lines().collect::<io::Result<_>>()?;
+
+        assert_eq!(lines, vec!["gzipped", "lines"]);
+
+        dir.close()
+    }
+
+    #[test]
+    fn reads_every_gzip_member() -> io::Result<()> {
+        let dir = tempdir()?;
+        let (first, second) = (dir.path().join("part1.txt.gz"), dir.path().join("part2.txt.gz"));
+        crate::file::writer::Writer::new(&first, 32_000)?.write_line("read")?.write_line("my")?;
+        crate::file::writer::Writer::new(&second, 32_000)?.write_line("lines")?;
+        let path = dir.path().join("test.txt.gz");
+        std::fs::write(&path, [std::fs::read(&first)?, std::fs::read(&second)?].concat())?;
+
+        let lines: Vec<String> = Reader::new(&path, 32_000)?.lines().collect::<io::Result<_>>()?;
+
+        assert_eq!(lines, vec!["read", "my", "lines"]);
+
+        dir.close()
+    }
+
+    #[test]
+    fn reads_every_bzip2_stream() -> io::Result<()> {
+        let dir = tempdir()?;
+        let (first, second) = (dir.path().join("part1.txt.bz2"), dir.path().join("part2.txt.bz2"));
+        crate::file::writer::Writer::new(&first, 32_000)?.write_line("read")?.write_line("my")?;
+        crate::file::writer::Writer::new(&second, 32_000)?.write_line("lines")?;
+        let path = dir.path().join("test.txt.bz2");
+        std::fs::write(&path, [std::fs::read(&first)?, std::fs::read(&second)?].concat())?;
+
+        let lines: Vec<String> = Reader::new(&path, 32_000)?.lines().collect::<io::Result<_>>()?;
+
+        assert_eq!(lines, vec!["read", "my", "lines"]);
+
+        dir.close()
+    }
+
+    #[test]
+    fn concatenates_plaintext_and_gzip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let (first, second) = (dir.path().join("part1.txt"), dir.path().join("part2.txt.gz"));
+        std::fs::write(&first, "read\nmy\n")?;
+        crate::file::writer::Writer::new(&second, 32_000)?.write_line("lines")?;
+
+        let lines: Vec<String> = Reader::concat(&[first, second], 32_000, None, None, false)?
+            .lines()
+            .collect::<io::Result<_>>()?;
+
+        assert_eq!(lines, vec!["read", "my", "lines"]);
+
+        dir.close()
+    }
+
+    #[test]
+    fn reads_gzip_on_decode_thread() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt.gz");
+        let mut writer = crate::file::writer::Writer::new(&path, 32_000)?;
+        for i in 0..10_000 {
+            writer.write_line(&format!("line {i}"))?;
+        }
+        drop(writer);
+
+        let lines: Vec<String> = Reader::with_options(&path, 32_000, Some(64), None, true)?
+            .lines()
+            .collect::<io::Result<_>>()?;
+
+        assert_eq!(lines.len(), 10_000);
+        assert_eq!(lines[9_999], "line 9999");
+
+        dir.close()
+    }
+
+    /// Compares read throughput with and without read-ahead. Run with
+    /// `cargo test --release -- --ignored --nocapture read_ahead_throughput`
+    #[test]
+    #[ignore]
+    fn read_ahead_throughput() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt");
+        let mut file: File = File::create(&path)?;
+        let line = r#"{"curie":"NCBIGene:1","names":["a gene name"],"types":["Gene"],"taxa":[]}"#;
+        for _ in 0..2_000_000 {
+            writeln!(file, "{line}")?;
+        }
+        drop(file);
+
+        let t0 = std::time::Instant::now();
+        let plain = Reader::new(&path, 32_000)?.lines().count();
+        let plain_elapsed = t0.elapsed();
+
+        let t0 = std::time::Instant::now();
+        let read_ahead = Reader::with_options(&path, 32_000, None, Some(4_000_000), false)?
+            .lines()
+            .count();
+        let read_ahead_elapsed = t0.elapsed();
+
+        assert_eq!(plain, read_ahead);
+        println!("BufReader: {plain_elapsed:.2?}, read-ahead: {read_ahead_elapsed:.2?}");
+
+        dir.close()
+    }
+
+    /// Compares gzip decode throughput at a few decode buffer sizes. Run with
+    /// `cargo test --release -- --ignored --nocapture decode_buffer_throughput`
+    #[test]
+    #[ignore]
+    fn decode_buffer_throughput() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt.gz");
+        let mut writer = crate::file::writer::Writer::new(&path, 32_000)?;
+        let line = r#"{"curie":"NCBIGene:1","names":["a gene name"],"types":["Gene"],"taxa":[]}"#;
+        for _ in 0..2_000_000 {
+            writer.write_line(line)?;
+        }
+        drop(writer);
+
+        for decode_buffer in [8_000, 32_000, 128_000, 1_000_000] {
+            let t0 = std::time::Instant::now();
+            let num_lines = Reader::with_options(&path, 32_000, Some(decode_buffer), None, false)?
+                .lines()
+                .count();
+            assert_eq!(num_lines, 2_000_000);
+            println!("decode buffer {decode_buffer} bytes: {:.2?}", t0.elapsed());
+        }
+
+        dir.close()
+    }
+
+    /// Compares reading and parsing a gzipped file with decompression on the parsing thread and
+    /// on its own thread. Run with
+    /// `cargo test --release -- --ignored --nocapture decode_thread_throughput`
+    #[test]
+    #[ignore]
+    fn decode_thread_throughput() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt.gz");
+        let mut writer = crate::file::writer::Writer::new(&path, 32_000)?;
+        let line = r#"{"curie":"NCBIGene:1","names":["a gene name"],"types":["Gene"],"taxa":[]}"#;
+        for _ in 0..2_000_000 {
+            writer.write_line(line)?;
+        }
+        drop(writer);
+
+        for decode_thread in [false, true] {
+            let t0 = std::time::Instant::now();
+            let num_nodes = Reader::with_options(&path, 32_000, None, None, decode_thread)?
+                .lines()
+                .map_while(Result::ok)
+                .filter(|line| serde_json::from_str::<crate::BabelJson>(line).is_ok())
+                .count();
+            assert_eq!(num_nodes, 2_000_000);
+            println!("decode thread {decode_thread}: {:.2?}", t0.elapsed());
+        }
+
+        dir.close()
+    }
+}
@@ -1,54 +1,234 @@
+use bzip2::write::BzEncoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{self, BufWriter, Write};
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, IntoInnerError, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
 
-/// Buffered file writer supporting optional gzip compression
+/// The zstd level used unless another is configured, zstd's own default
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Compression levels for the formats a `Writer` can compress to
+#[derive(Clone, Copy)]
+pub struct CompressionLevels {
+    pub gzip: Compression,
+    pub bzip2: bzip2::Compression,
+    pub zstd: i32,
+}
+
+impl Default for CompressionLevels {
+    fn default() -> CompressionLevels {
+        CompressionLevels {
+            gzip: Compression::default(),
+            bzip2: bzip2::Compression::default(),
+            zstd: DEFAULT_ZSTD_LEVEL,
+        }
+    }
+}
+
+/// The buffered file a `Writer` writes to, through its encoder if it's compressed
+enum Encoder {
+    Plain(BufWriter<File>),
+    Gzip(BufWriter<GzEncoder<File>>),
+    Bzip2(BufWriter<BzEncoder<File>>),
+    Zstd(BufWriter<zstd::stream::write::Encoder<'static, File>>),
+    Stdout(BufWriter<io::Stdout>),
+    /// Left behind once the stream has been ended
+    Finished,
+}
+
+impl Encoder {
+    fn get_mut(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Plain(writer) => writer,
+            Encoder::Gzip(writer) => writer,
+            Encoder::Bzip2(writer) => writer,
+            Encoder::Zstd(writer) => writer,
+            Encoder::Stdout(writer) => writer,
+            Encoder::Finished => unreachable!("a finished writer can't be written to"),
+        }
+    }
+
+    /// Flushes the buffer and writes the end of the compressed stream. The buffer is unwrapped
+    /// rather than flushed, since flushing a gzip stream also writes an empty block.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(writer) => writer.into_inner().map_err(IntoInnerError::into_error)?,
+            Encoder::Gzip(writer) => {
+                writer.into_inner().map_err(IntoInnerError::into_error)?.finish()?
+            }
+            Encoder::Bzip2(writer) => {
+                writer.into_inner().map_err(IntoInnerError::into_error)?.finish()?
+            }
+            Encoder::Zstd(writer) => {
+                writer.into_inner().map_err(IntoInnerError::into_error)?.finish()?
+            }
+            Encoder::Stdout(writer) => {
+                return writer.into_inner().map_err(IntoInnerError::into_error)?.flush()
+            }
+            Encoder::Finished => return Ok(()),
+        }
+        .flush()
+    }
+}
+
+/// Buffered file writer supporting optional gzip, bzip2 or zstd compression
 pub struct Writer {
-    writer: Box<dyn Write>,
+    writer: Encoder,
+    separator: u8,
+    bytes_written: u64,
+    /// The path of an uncompressed file, the only kind that can be preallocated
+    uncompressed_path: Option<PathBuf>,
+    /// A handle to the preallocated file, to truncate it to what was written once it's dropped
+    preallocated: Option<File>,
 }
 
 impl Writer {
     /// Creates a new file writer given a `Path`. If the path ends in `.gz`, it will encode as
-    /// a gzipped file using `flate2`.
+    /// a gzipped file using `flate2`, if it ends in `.bz2`, as a bzip2 file, and if it ends in
+    /// `.zst`, as a zstd file.
     ///
     /// Returns `Err` if there is a problem creating the file
     pub fn new<P>(path: P, buffer_capacity: usize) -> io::Result<Writer>
+    where
+        P: AsRef<Path>,
+    {
+        Writer::with_levels(path, buffer_capacity, CompressionLevels::default())
+    }
+
+    /// Like `new`, compressing at `levels` instead of the defaults
+    ///
+    /// Returns `Err` if there is a problem creating the file
+    pub fn with_levels<P>(
+        path: P,
+        buffer_capacity: usize,
+        levels: CompressionLevels,
+    ) -> io::Result<Writer>
     where
         P: AsRef<Path>,
     {
         let file = File::create(&path)?;
 
-        let writer: Box<dyn Write> = if path.as_ref().extension() == Some(&OsStr::new("gz")) {
-            Box::new(BufWriter::with_capacity(
+        let extension = path.as_ref().extension();
+        let compressed = ["gz", "bz2", "zst"].map(|ext| Some(OsStr::new(ext))).contains(&extension);
+        let writer = if extension == Some(OsStr::new("gz")) {
+            Encoder::Gzip(BufWriter::with_capacity(
+                buffer_capacity,
+                GzEncoder::new(file, levels.gzip),
+            ))
+        } else if extension == Some(OsStr::new("bz2")) {
+            Encoder::Bzip2(BufWriter::with_capacity(
                 buffer_capacity,
-                GzEncoder::new(file, Compression::default()),
+                BzEncoder::new(file, levels.bzip2),
             ))
+        } else if extension == Some(OsStr::new("zst")) {
+            let encoder = zstd::stream::write::Encoder::new(file, levels.zstd)?;
+            Encoder::Zstd(BufWriter::with_capacity(buffer_capacity, encoder))
         } else {
-            Box::new(BufWriter::with_capacity(buffer_capacity, file))
+            Encoder::Plain(BufWriter::with_capacity(buffer_capacity, file))
         };
 
-        Ok(Writer { writer })
+        Ok(Writer {
+            writer,
+            separator: b'\n',
+            bytes_written: 0,
+            uncompressed_path: (!compressed).then(|| path.as_ref().to_path_buf()),
+            preallocated: None,
+        })
     }
 
-    /// Appends a string `line` with a newline character (`\n`) at the end
+    /// Creates a writer to the standard output, which is never compressed
+    pub fn to_stdout(buffer_capacity: usize) -> Writer {
+        Writer {
+            writer: Encoder::Stdout(BufWriter::with_capacity(buffer_capacity, io::stdout())),
+            separator: b'\n',
+            bytes_written: 0,
+            uncompressed_path: None,
+            preallocated: None,
+        }
+    }
+
+    /// Extends an uncompressed file to `bytes` before anything is written, to hint its final
+    /// size to the filesystem. It's truncated back to what was written once the writer is dropped.
+    /// This is ignored for compressed files. It only sets the file's length, which filesystems
+    /// with sparse files don't back with disk space until it's written.
+    ///
+    /// Returns `Err` if there is a problem extending the file
+    pub fn with_preallocation(mut self, bytes: u64) -> io::Result<Writer> {
+        if let Some(ref path) = self.uncompressed_path {
+            let file = OpenOptions::new().write(true).open(path)?;
+            file.set_len(bytes)?;
+            self.preallocated = Some(file);
+        }
+        Ok(self)
+    }
+
+    /// Ends lines with `separator` instead of a newline
+    pub fn with_separator(mut self, separator: u8) -> Writer {
+        self.separator = separator;
+        self
+    }
+
+    /// Appends a string `line` with a newline character (`\n`), or the configured separator, at
+    /// the end
     ///
     /// Returns `Err` if there is a problem writing to the file
     pub fn write_line(&mut self, line: &str) -> io::Result<&mut Writer> {
-        self.writer.write_all(line.as_bytes())?;
-        self.writer.write_all(b"\n")?;
+        self.write_line_bytes(line.as_bytes())
+    }
+
+    /// Like `write_line`, but for a line that's already encoded
+    ///
+    /// Returns `Err` if there is a problem writing to the file
+    pub fn write_line_bytes(&mut self, line: &[u8]) -> io::Result<&mut Writer> {
+        let writer = self.writer.get_mut();
+        writer.write_all(line)?;
+        writer.write_all(&[self.separator])?;
+        self.bytes_written += line.len() as u64 + 1;
         Ok(self)
     }
+
+    /// The number of bytes written so far, including separators. For compressed files this
+    /// counts the uncompressed bytes.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Flushes every buffered line and ends the compressed stream, so the output is complete.
+    /// Dropping the writer does the same, but can't report an error, which would leave a silently
+    /// truncated file.
+    ///
+    /// Returns `Err` if there is a problem writing the rest of the file
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_in_place()
+    }
+
+    fn finish_in_place(&mut self) -> io::Result<()> {
+        // the buffered lines have to be in the file before it's cut down to them
+        mem::replace(&mut self.writer, Encoder::Finished).finish()?;
+        if let Some(file) = self.preallocated.take() {
+            file.set_len(self.bytes_written)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let _ = self.finish_in_place();
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{io, fs, path::PathBuf};
+    use std::{io::{self, Read}, fs, path::PathBuf};
     use tempfile::tempdir;
 
-    use super::Writer;
+    use flate2::{read::GzDecoder, Compression};
+
+    use super::{CompressionLevels, Writer};
 
     #[test]
     fn writes_line_to_plaintext() -> io::Result<()> {
@@ -83,4 +263,104 @@ mod tests {
         
         dir.close()
     }
+
+    #[test]
+    fn finishes_gzip_stream() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt.gz");
+
+        let mut writer = Writer::new(&path, 32_000)?;
+        for line in ["first", "second", "third"] {
+            writer.write_line(line)?;
+        }
+        writer.finish()?;
+
+        let mut contents = String::new();
+        GzDecoder::new(fs::File::open(&path)?).read_to_string(&mut contents)?;
+        assert_eq!(contents, "first\nsecond\nthird\n");
+
+        dir.close()
+    }
+
+    #[test]
+    fn truncates_preallocated_file_to_lines_written() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("test.txt");
+        let gz_path = dir.path().join("test.txt.gz");
+
+        let mut writer = Writer::new(&path, 32_000)?.with_preallocation(4_096)?;
+        writer.write_line("hello")?.write_line("world")?;
+        assert_eq!(fs::metadata(&path)?.len(), 4_096);
+        drop(writer);
+        // compressed files aren't preallocated
+        Writer::new(&gz_path, 32_000)?.with_preallocation(4_096)?.write_line("gzipped")?;
+
+        assert_eq!(fs::read_to_string(&path)?, "hello\nworld\n");
+        assert!(fs::metadata(&gz_path)?.len() < 4_096);
+
+        dir.close()
+    }
+
+    #[test]
+    fn compresses_gzip_at_level() -> io::Result<()> {
+        let dir = tempdir()?;
+        let fast_path = dir.path().join("fast.txt.gz");
+        let best_path = dir.path().join("best.txt.gz");
+        let line = "a fairly repetitive line of text, a fairly repetitive line of text";
+        for (path, level) in [(&fast_path, 0), (&best_path, 9)] {
+            let levels = CompressionLevels {
+                gzip: Compression::new(level),
+                ..CompressionLevels::default()
+            };
+            let mut writer = Writer::with_levels(path, 32_000, levels)?;
+            for _ in 0..1_000 {
+                writer.write_line(line)?;
+            }
+        }
+
+        // level 0 only stores the data
+        assert!(fs::metadata(&best_path)?.len() < fs::metadata(&fast_path)?.len() / 10);
+
+        dir.close()
+    }
+
+    #[test]
+    fn writes_line_to_bzip2() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path: PathBuf = dir.path().join("test.txt.bz2");
+
+        Writer::new(&path, 32_000)?
+            .write_line("compressed")?
+            .write_line("lines")?;
+
+        let contents = fs::read(&path)?;
+        assert_eq!(&contents[..3], b"BZh");
+
+        let lines: Vec<String> = crate::file::reader::Reader::new(&path, 32_000)?
+            .lines()
+            .collect::<io::Result<_>>()?;
+        assert_eq!(lines, vec!["compressed", "lines"]);
+
+        dir.close()
+    }
+
+    #[test]
+    fn writes_line_to_zstd() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path: PathBuf = dir.path().join("test.txt.zst");
+
+        Writer::new(&path, 32_000)?
+            .write_line("compressed")?
+            .write_line("lines")?;
+
+        let contents = fs::read(&path)?;
+        assert_eq!(&contents[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+
+        let lines: Vec<String> = crate::file::reader::Reader::new(&path, 32_000)?
+            .lines()
+            .collect::<io::Result<_>>()?;
+        assert_eq!(lines, vec!["compressed", "lines"]);
+
+        dir.close()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use ahash::AHashSet;
+use camino::Utf8Path;
+use std::{io, process::ExitCode, time::Instant};
+
+use crate::file::reader::Reader;
+use crate::output;
+use crate::{NodeListJson, BUF_CAPACITY};
+
+/// The ids added and removed between two filter files
+#[derive(Default, Debug, PartialEq)]
+struct FilterDiff {
+    /// Ids only in the new filter file, sorted
+    added: Vec<String>,
+    /// Ids only in the old filter file, sorted
+    removed: Vec<String>,
+    num_common: usize,
+    /// Lines in either file that aren't a filter node
+    num_invalid: usize,
+}
+
+/// Prints how many ids were added to, removed from, and kept in common between the `old` and `new`
+/// filter files, optionally writing the added and removed ids to files, one per line
+pub fn filter_diff(
+    old: &Utf8Path,
+    new: &Utf8Path,
+    added_path: Option<&Utf8Path>,
+    removed_path: Option<&Utf8Path>,
+) -> ExitCode {
+    let start = Instant::now();
+
+    let diff = match diff(old, new) {
+        Ok(diff) => diff,
+        Err(e) => {
+            eprintln!("Error reading the filter files: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("{} ids added", diff.added.len());
+    println!("{} ids removed", diff.removed.len());
+    println!("{} ids in common", diff.num_common);
+    if diff.num_invalid > 0 {
+        println!("{} lines weren't valid filter nodes", diff.num_invalid);
+    }
+
+    for (path, ids, label) in [
+        (added_path, &diff.added, "added"),
+        (removed_path, &diff.removed, "removed"),
+    ] {
+        if let Some(path) = path {
+            if let Err(e) = output::write_ids(path, ids) {
+                eprintln!("Error writing the {label} ids: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    println!("Program took {:.2?}", start.elapsed());
+    ExitCode::SUCCESS
+}
+
+/// Compares the ids of two filter files
+fn diff(old: &Utf8Path, new: &Utf8Path) -> io::Result<FilterDiff> {
+    let mut num_invalid = 0;
+    let old_ids = read_filter_ids(old, &mut num_invalid)?;
+    let new_ids = read_filter_ids(new, &mut num_invalid)?;
+
+    let mut added: Vec<String> = new_ids.difference(&old_ids).cloned().collect();
+    let mut removed: Vec<String> = old_ids.difference(&new_ids).cloned().collect();
+    added.sort_unstable();
+    removed.sort_unstable();
+    Ok(FilterDiff {
+        num_common: new_ids.len() - added.len(),
+        added,
+        removed,
+        num_invalid,
+    })
+}
+
+/// Reads the set of ids in a filter file, counting lines that don't parse in `num_invalid`
+fn read_filter_ids(path: &Utf8Path, num_invalid: &mut usize) -> io::Result<AHashSet<String>> {
+    let mut ids = AHashSet::new();
+    for line in Reader::new(path, BUF_CAPACITY)?.lines() {
+        match serde_json::from_str::<NodeListJson>(&line?) {
+            Ok(node) => {
+                ids.insert(node.id);
+            }
+            Err(_) => *num_invalid += 1,
+        }
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use std::{fs, io};
+    use tempfile::tempdir;
+
+    use super::{diff, FilterDiff};
+
+    #[test]
+    fn diffs_filter_files() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let (old, new) = (root.join("old.jsonl"), root.join("new.jsonl"));
+        fs::write(
+            &old,
+            concat!(
+                r#"{"id":"MONDO:1","name":"a","category":[]}"#,
+                "\n",
+                r#"{"id":"MONDO:2","name":"b","category":[]}"#,
+                "\n",
+            ),
+        )?;
+        fs::write(
+            &new,
+            concat!(
+                r#"{"id":"MONDO:2","name":"b","category":[]}"#,
+                "\n",
+                r#"{"id":"MONDO:4","name":"d","category":[]}"#,
+                "\n",
+                r#"{"id":"MONDO:3","name":"c","category":[]}"#,
+                "\n",
+                "not json\n",
+            ),
+        )?;
+
+        assert_eq!(
+            diff(&old, &new)?,
+            FilterDiff {
+                added: vec![String::from("MONDO:3"), String::from("MONDO:4")],
+                removed: vec![String::from("MONDO:1")],
+                num_common: 1,
+                num_invalid: 1,
+            }
+        );
+
+        dir.close()
+    }
+}
@@ -0,0 +1,884 @@
+use ahash::{AHashMap, AHashSet, RandomState};
+use camino::Utf8Path;
+use regex::Regex;
+use std::{collections::hash_map::Entry, fs, io, path::PathBuf, sync::RwLock};
+
+use crate::bloom::BloomFilter;
+use crate::file::reader::Reader;
+use crate::filters;
+use crate::progress::{ErrorLog, FailedLines};
+use crate::{BabelJson, Config, NodeListJson, BUF_CAPACITY};
+
+/// Counts from reading the filter files
+#[derive(Default)]
+pub struct FilterFileStats {
+    /// Nodes parsed from all the filter files, before any are skipped or their ids deduplicated
+    pub num_read: usize,
+    /// Nodes skipped for having an excluded category
+    pub num_excluded: usize,
+    /// Nodes skipped for having none of the included categories
+    pub num_not_included: usize,
+    /// Nodes whose `equivalent_identifiers` had self-references or duplicates removed
+    pub num_equivalents_fixed: usize,
+    /// Nodes skipped for having no category, with `require_category`
+    pub num_missing_category: usize,
+    /// Records merged into an earlier record with the same id, with
+    /// `merge_duplicate_filter_records`
+    pub num_merged: usize,
+    /// Nodes skipped because their id matches `exclude_curie_regex`
+    pub num_curie_regex_excluded: usize,
+}
+
+/// The filter set split into shards by curie hash, each behind its own read-write lock. Lookups
+/// only take a read lock, so threads filtering different files only wait on each other when one
+/// removes a node from the shard another is looking in.
+pub struct ShardedFilterSet {
+    shards: Vec<RwLock<AHashMap<String, NodeListJson>>>,
+    hasher: RandomState,
+    /// Equivalent identifiers of the nodes, mapped to their node's id
+    aliases: AHashMap<String, String>,
+    /// Whether the ids (and aliases) are lowercased
+    lowercase: bool,
+}
+
+impl ShardedFilterSet {
+    /// Splits a filter set into `num_shards` shards. A shard count of 0 is treated as 1.
+    pub fn new(filter_set: AHashMap<String, NodeListJson>, num_shards: usize) -> ShardedFilterSet {
+        let num_shards = num_shards.max(1);
+        let hasher = RandomState::new();
+        let mut shards: Vec<AHashMap<String, NodeListJson>> = (0..num_shards)
+            .map(|_| AHashMap::with_capacity(filter_set.len() / num_shards))
+            .collect();
+        for (curie, node) in filter_set {
+            let shard = hasher.hash_one(&curie) as usize % num_shards;
+            shards[shard].insert(curie, node);
+        }
+        ShardedFilterSet {
+            shards: shards.into_iter().map(RwLock::new).collect(),
+            hasher,
+            aliases: AHashMap::new(),
+            lowercase: false,
+        }
+    }
+
+    /// Lowercases every id and equivalent identifier, so curies have to be lowercased before
+    /// they're looked up. Ids that only differ by case collide and only one of their nodes is kept,
+    /// with no guarantee which.
+    pub fn with_lowercase_ids(mut self) -> ShardedFilterSet {
+        let num_shards = self.shards.len();
+        let aliases = std::mem::take(&mut self.aliases);
+        let filter_set = self
+            .into_inner()
+            .into_iter()
+            .map(|(id, node)| (id.to_lowercase(), node))
+            .collect();
+        let mut sharded = ShardedFilterSet::new(filter_set, num_shards);
+        sharded.aliases = aliases
+            .into_iter()
+            .map(|(equivalent, id)| (equivalent.to_lowercase(), id.to_lowercase()))
+            .collect();
+        sharded.lowercase = true;
+        sharded
+    }
+
+    /// Also matches each node by the curies in its `equivalent_identifiers`. An equivalent that's
+    /// another node's id still matches that node, and one shared by several nodes matches the
+    /// first of them.
+    pub fn with_equivalents(mut self) -> ShardedFilterSet {
+        for shard in &mut self.shards {
+            for (id, node) in shard.get_mut().unwrap().iter() {
+                for equivalent in node.equivalent_identifiers.iter().flatten() {
+                    let equivalent = if self.lowercase {
+                        equivalent.to_lowercase()
+                    } else {
+                        equivalent.clone()
+                    };
+                    if equivalent != *id {
+                        self.aliases.entry(equivalent).or_insert_with(|| id.clone());
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns the id, as it's keyed in the filter set, of the node with this curie or with this
+    /// curie among its equivalent identifiers, if that node is still in the filter set. Once a
+    /// node is removed none of its curies match again.
+    pub fn find(&self, curie: &str) -> Option<String> {
+        if self.contains_id(curie) {
+            return Some(String::from(curie));
+        }
+        self.aliases.get(curie).filter(|id| self.contains_id(id)).cloned()
+    }
+
+    /// Removes the node with the id `id`, as returned by `find`
+    pub fn remove_id(&self, id: &str) -> Option<NodeListJson> {
+        self.shard(id).write().unwrap().remove(id)
+    }
+
+    fn contains_id(&self, id: &str) -> bool {
+        self.shard(id).read().unwrap().contains_key(id)
+    }
+
+    fn shard(&self, id: &str) -> &RwLock<AHashMap<String, NodeListJson>> {
+        &self.shards[self.hasher.hash_one(id) as usize % self.shards.len()]
+    }
+
+    /// Joins the shards back into one map, keyed by the nodes' own ids again if they were
+    /// lowercased
+    pub fn into_inner(self) -> AHashMap<String, NodeListJson> {
+        let mut shards = self.shards.into_iter().map(|shard| shard.into_inner().unwrap());
+        let mut filter_set = shards.next().unwrap_or_default();
+        for shard in shards {
+            filter_set.extend(shard);
+        }
+        if self.lowercase {
+            filter_set = filter_set.into_values().map(|node| (node.id.clone(), node)).collect();
+        }
+        filter_set
+    }
+}
+
+/// Reads the filter files into one map of id to node, skipping nodes with an excluded category. A
+/// later record with the same id, from the same or a later filter file, replaces the earlier one,
+/// unless `merge_duplicate_filter_records` is set. With `no_non_babel_nodes` the nodes only keep
+/// their id (and their equivalents, with `match_equivalents`), since the rest is only used to
+/// synthesize NonBabelNodes.
+///
+/// Returns `Err` with a message if the filter set grows past `max_filter_set_size`
+pub fn build_filter_set(
+    args: &Config,
+    failed_lines: Option<&FailedLines>,
+) -> Result<(AHashMap<String, NodeListJson>, FilterFileStats), String> {
+    let mut filter_set: AHashMap<String, NodeListJson> = AHashMap::new();
+    let mut num_merged = 0;
+    let mut stats = read_filter_file(args, failed_lines, |node| {
+        let node = if args.no_non_babel_nodes {
+            // empty strings and vectors don't allocate
+            NodeListJson {
+                id: node.id,
+                name: String::new(),
+                category: vec![],
+                equivalent_identifiers: node
+                    .equivalent_identifiers
+                    .filter(|_| args.match_equivalents),
+                other_names: vec![],
+                taxa: vec![],
+            }
+        } else {
+            node
+        };
+        match filter_set.entry(String::from(&node.id)) {
+            Entry::Occupied(mut existing) if args.merge_duplicate_filter_records => {
+                merge_filter_node(existing.get_mut(), node);
+                num_merged += 1;
+            }
+            Entry::Occupied(mut existing) => {
+                existing.insert(node);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(node);
+            }
+        }
+        filter_set.len()
+    })?;
+    stats.num_merged = num_merged;
+    Ok((filter_set, stats))
+}
+
+/// Keys filter nodes that were read some other way than from the filter files by id, skipping the
+/// ones `build_filter_set` would skip. A later node with the same id replaces the earlier one.
+///
+/// Returns `Err` with a message if a `category_regex` or `exclude_curie_regex` pattern doesn't
+/// compile
+pub fn admit_filter_nodes<I>(
+    args: &Config,
+    nodes: I,
+) -> Result<AHashMap<String, NodeListJson>, String>
+where
+    I: IntoIterator<Item = NodeListJson>,
+{
+    let patterns = FilterPatterns {
+        categories: compile_exclude_patterns(args)?,
+        curie: compile_curie_pattern(args)?,
+    };
+    let mut stats = FilterFileStats::default();
+    Ok(nodes
+        .into_iter()
+        .filter_map(|node| admit_filter_node(args, &patterns, &mut stats, node))
+        .map(|node| (node.id.clone(), node))
+        .collect())
+}
+
+/// Shards a filter set into `filter_set_shards` shards, keyed the way `ignore_curie_case` and
+/// `match_equivalents` look Babel curies up
+pub fn shard_filter_set(
+    args: &Config,
+    filter_set: AHashMap<String, NodeListJson>,
+) -> ShardedFilterSet {
+    let mut filter_set = ShardedFilterSet::new(filter_set, args.filter_set_shards);
+    if args.ignore_curie_case {
+        filter_set = filter_set.with_lowercase_ids();
+    }
+    if args.match_equivalents {
+        filter_set = filter_set.with_equivalents();
+    }
+    filter_set
+}
+
+/// Reads just the filter files' ids into a set, skipping nodes with an excluded category
+///
+/// Returns `Err` with a message if the set grows past `max_filter_set_size`
+pub fn build_id_set(
+    args: &Config,
+    failed_lines: Option<&FailedLines>,
+) -> Result<(AHashSet<String>, FilterFileStats), String> {
+    let mut id_set = AHashSet::new();
+    let stats = read_filter_file(args, failed_lines, |node| {
+        id_set.insert(if args.ignore_curie_case {
+            node.id.to_lowercase()
+        } else {
+            node.id
+        });
+        id_set.len()
+    })?;
+    Ok((id_set, stats))
+}
+
+/// Reads the filter file ids into a Bloom filter sized for the filter files' total line count,
+/// skipping nodes with an excluded category
+///
+/// Returns `Err` with a message if a filter file can't be opened or more than
+/// `max_filter_set_size` ids are inserted
+pub fn build_bloom_filter(
+    args: &Config,
+    failed_lines: Option<&FailedLines>,
+) -> Result<(BloomFilter, FilterFileStats), String> {
+    let mut num_lines = 0;
+    for filter_file in &args.filter_file {
+        num_lines += open_filter_file(filter_file)?.lines().count();
+    }
+    let mut bloom_filter = BloomFilter::new(num_lines, args.bloom_fp_rate);
+    let mut num_inserted = 0;
+    let stats = read_filter_file(args, failed_lines, |node| {
+        if args.ignore_curie_case {
+            bloom_filter.insert(&node.id.to_lowercase());
+        } else {
+            bloom_filter.insert(&node.id);
+        }
+        num_inserted += 1;
+        num_inserted
+    })?;
+    Ok((bloom_filter, stats))
+}
+
+/// Parses each node of every filter file, in order, and passes the ones without an excluded
+/// category, with an included category if there's an include list, and (with `require_category`)
+/// with a category at all to `insert`, which returns how many entries the filter set now has.
+/// With `filter_tolerant_parse` the files are read as streams of JSON values rather than lines,
+/// and with `filter_tsv` each line is a row of tab-separated columns. Lines that don't parse are
+/// written to `failed_lines`, except in a stream, which has no lines.
+///
+/// Returns `Err` with a message if a `category_regex` or `exclude_curie_regex` pattern doesn't
+/// compile, if a filter file can't be opened, or as soon as that's more than
+/// `max_filter_set_size`
+fn read_filter_file<F>(
+    args: &Config,
+    failed_lines: Option<&FailedLines>,
+    mut insert: F,
+) -> Result<FilterFileStats, String>
+where
+    F: FnMut(NodeListJson) -> usize,
+{
+    let patterns = FilterPatterns {
+        categories: compile_exclude_patterns(args)?,
+        curie: compile_curie_pattern(args)?,
+    };
+    let mut stats = FilterFileStats::default();
+    for filter_file in &args.filter_file {
+        read_one_filter_file(args, filter_file, &patterns, failed_lines, &mut stats, &mut insert)?;
+    }
+    Ok(stats)
+}
+
+/// The regexes filter file nodes are checked against
+struct FilterPatterns {
+    /// Replaces `exclude_category`, with `category_regex`
+    categories: Option<Vec<Regex>>,
+    /// From `exclude_curie_regex`
+    curie: Option<Regex>,
+}
+
+/// Opens a filter file, with the error as a message
+fn open_filter_file(filter_file: &Utf8Path) -> Result<Reader, String> {
+    Reader::new(filter_file, BUF_CAPACITY)
+        .map_err(|e| format!("Error opening filter file {filter_file}: {e}"))
+}
+
+/// Reads one of the filter files for `read_filter_file`, adding to its `stats`
+fn read_one_filter_file<F>(
+    args: &Config,
+    filter_file: &Utf8Path,
+    patterns: &FilterPatterns,
+    failed_lines: Option<&FailedLines>,
+    stats: &mut FilterFileStats,
+    insert: &mut F,
+) -> Result<(), String>
+where
+    F: FnMut(NodeListJson) -> usize,
+{
+    let reader = open_filter_file(filter_file)?;
+    let mut errors = ErrorLog::new(args.max_errors_per_file);
+
+    if args.filter_tolerant_parse {
+        let nodes = serde_json::Deserializer::from_reader(reader.into_inner())
+            .into_iter::<NodeListJson>();
+        for (value_index, node) in nodes.enumerate() {
+            match node {
+                Ok(node) => {
+                    stats.num_read += 1;
+                    let node = admit_filter_node(args, patterns, stats, node);
+                    if let Some(node) = node {
+                        let size = insert(node);
+                        check_filter_set_size(args, size, filter_file, "value", value_index + 1)?;
+                    }
+                }
+                // the stream can't resume after a malformed value
+                Err(e) => {
+                    errors.log(format_args!(
+                        "Parse error in filter file {filter_file}, skipping the rest of it: {e}"
+                    ));
+                    break;
+                }
+            }
+        }
+        errors.finish(format_args!("{filter_file}"));
+        return Ok(());
+    }
+
+    let comment_prefixes = args.comment_prefix.as_deref().unwrap_or_default();
+    for (line_index, line) in reader.lines().enumerate() {
+        if let Ok(node_json) = line {
+            if filters::is_comment(&node_json, comment_prefixes) {
+                continue;
+            }
+            let node = if args.filter_tsv {
+                let (id_column, category_column) = tsv_columns(args);
+                parse_tsv_node(&node_json, id_column, category_column)
+            } else {
+                serde_json::from_str::<NodeListJson>(&node_json).map_err(|e| e.to_string())
+            };
+            match node {
+                Ok(node) => {
+                    stats.num_read += 1;
+                    let node = admit_filter_node(args, patterns, stats, node);
+                    if let Some(node) = node {
+                        let size = insert(node);
+                        check_filter_set_size(args, size, filter_file, "line", line_index + 1)?;
+                    }
+                }
+                Err(e) => {
+                    errors.log(format_args!(
+                        "Parse error in filter file {filter_file} line {}: {e}",
+                        line_index + 1
+                    ));
+                    let source = filter_file.file_name().unwrap_or(filter_file.as_str());
+                    let recorded = failed_lines
+                        .map_or(Ok(()), |failed| failed.record(source, line_index + 1, &node_json));
+                    if let Err(e) = recorded {
+                        errors.log(format_args!("Error writing the failed lines file: {e}"));
+                    }
+                }
+            }
+        } else {
+            errors.log(format_args!(
+                "Read error in filter file {filter_file} line {}",
+                line_index + 1
+            ))
+        }
+    }
+    errors.finish(format_args!("{filter_file}"));
+    Ok(())
+}
+
+/// Returns the 1-based id and category columns of a `filter_tsv` filter file, which default to the
+/// first and second
+pub fn tsv_columns(args: &Config) -> (usize, usize) {
+    (args.tsv_id_column.unwrap_or(1), args.tsv_category_column.unwrap_or(2))
+}
+
+/// Reads a filter node from a row of tab-separated columns, taking its id and category from the
+/// given 1-based columns. The node has no name, and no category if that column is empty.
+///
+/// Returns `Err` with a message if the row is missing either column or the id is empty
+fn parse_tsv_node(
+    line: &str,
+    id_column: usize,
+    category_column: usize,
+) -> Result<NodeListJson, String> {
+    let columns: Vec<&str> = line.split('\t').collect();
+    let column = |number: usize| {
+        number
+            .checked_sub(1)
+            .and_then(|index| columns.get(index))
+            .map(|value| value.trim())
+            .ok_or_else(|| format!("there's no column {number}"))
+    };
+    let id = column(id_column)?;
+    if id.is_empty() {
+        return Err(String::from("the id is empty"));
+    }
+    let category = column(category_column)?;
+    Ok(NodeListJson {
+        id: String::from(id),
+        name: String::new(),
+        category: if category.is_empty() { vec![] } else { vec![String::from(category)] },
+        equivalent_identifiers: None,
+        other_names: vec![],
+        taxa: vec![],
+    })
+}
+
+/// Compiles each `exclude_category` entry into a regex that has to match a whole category, if
+/// `category_regex` is set. The regexes ignore case with `ignore_category_case`.
+///
+/// Returns `Err` with a message naming the first pattern that doesn't compile
+fn compile_exclude_patterns(args: &Config) -> Result<Option<Vec<Regex>>, String> {
+    if !args.category_regex {
+        return Ok(None);
+    }
+    let flags = if args.ignore_category_case { "(?i)" } else { "" };
+    args.exclude_category
+        .iter()
+        .flatten()
+        .map(|pattern| {
+            Regex::new(&format!("{flags}^(?:{pattern})$"))
+                .map_err(|e| format!("Invalid category pattern \"{pattern}\": {e}"))
+        })
+        .collect::<Result<Vec<Regex>, String>>()
+        .map(Some)
+}
+
+/// Compiles `exclude_curie_regex`, which drops every curie it matches anywhere unless it's anchored
+///
+/// Returns `Err` with a message if the pattern doesn't compile
+pub fn compile_curie_pattern(args: &Config) -> Result<Option<Regex>, String> {
+    args.exclude_curie_regex
+        .as_deref()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| format!("Invalid curie pattern \"{pattern}\": {e}"))
+        })
+        .transpose()
+}
+
+/// Returns a filter file node if it belongs in the filter set, with its equivalents cleaned up if
+/// `validate_equivalents` is set, or `None` after counting why it was skipped in `stats`. With
+/// category patterns, they're matched against the node's categories instead of comparing them
+/// with `exclude_category`.
+fn admit_filter_node(
+    args: &Config,
+    patterns: &FilterPatterns,
+    stats: &mut FilterFileStats,
+    mut node: NodeListJson,
+) -> Option<NodeListJson> {
+    if patterns.curie.as_ref().is_some_and(|pattern| pattern.is_match(&node.id)) {
+        stats.num_curie_regex_excluded += 1;
+        return None;
+    }
+    if args.require_category && node.category.is_empty() {
+        stats.num_missing_category += 1;
+        return None;
+    }
+    if let Some(ref exclude_cats) = args.exclude_category {
+        let excluded = match patterns.categories.as_deref() {
+            Some(patterns) => node.category.iter().any(|category| {
+                patterns.iter().any(|pattern| pattern.is_match(category))
+            }),
+            None => has_any_category(node.category.iter(), exclude_cats, args.ignore_category_case),
+        };
+        if excluded {
+            stats.num_excluded += 1;
+            return None;
+        }
+    }
+    if let Some(ref include_cats) = args.include_category {
+        if !has_any_category(node.category.iter(), include_cats, args.ignore_category_case) {
+            stats.num_not_included += 1;
+            return None;
+        }
+    }
+    if args.validate_equivalents && clean_equivalents(&mut node) {
+        stats.num_equivalents_fixed += 1;
+    }
+    Some(node)
+}
+
+/// Returns `Err` with a message if the filter set's `size` after reading a filter file's
+/// `position`th line or value (`unit`) is more than `max_filter_set_size`
+fn check_filter_set_size(
+    args: &Config,
+    size: usize,
+    filter_file: &Utf8Path,
+    unit: &str,
+    position: usize,
+) -> Result<(), String> {
+    match args.max_filter_set_size {
+        Some(max_size) if size > max_size => Err(format!(
+            "The filter set reached {size} entries at {unit} {position} of {filter_file}, more \
+            than the maximum of {max_size}"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Merges a duplicate filter file record into the record already in the filter set, adding the
+/// categories and names it doesn't have yet. The first non-empty name stays the node's name.
+fn merge_filter_node(existing: &mut NodeListJson, duplicate: NodeListJson) {
+    for category in duplicate.category {
+        if !existing.category.contains(&category) {
+            existing.category.push(category);
+        }
+    }
+    for taxon in duplicate.taxa {
+        if !existing.taxa.contains(&taxon) {
+            existing.taxa.push(taxon);
+        }
+    }
+    for name in std::iter::once(duplicate.name).chain(duplicate.other_names) {
+        if name.is_empty() || name == existing.name || existing.other_names.contains(&name) {
+            continue;
+        }
+        if existing.name.is_empty() {
+            existing.name = name;
+        } else {
+            existing.other_names.push(name);
+        }
+    }
+}
+
+/// Releases the filter set's excess capacity from growing while it was built, at the cost of a
+/// rehash. Returns the capacity before and after shrinking.
+pub fn shrink_filter_set(filter_set: &mut AHashMap<String, NodeListJson>) -> (usize, usize) {
+    let capacity_before = filter_set.capacity();
+    filter_set.shrink_to_fit();
+    (capacity_before, filter_set.capacity())
+}
+
+/// Removes the node's own id and duplicates from its `equivalent_identifiers`, keeping the first
+/// occurrence of each. Returns true if anything was removed.
+fn clean_equivalents(node: &mut NodeListJson) -> bool {
+    let Some(ref mut equivalents) = node.equivalent_identifiers else {
+        return false;
+    };
+    let num_equivalents = equivalents.len();
+    let mut seen = AHashSet::with_capacity(num_equivalents);
+    equivalents.retain(|equivalent| *equivalent != node.id && seen.insert(equivalent.clone()));
+    equivalents.len() != num_equivalents
+}
+
+/// Adds the curie of every node in a directory of previous outputs to the filter set, skipping
+/// NonBabelNodes and metadata sidecars. Records are split on `separator`. Returns the curies that
+/// weren't already in the filter set.
+///
+/// Returns `Err` if the directory or one of its files can't be read
+pub fn seed_filter_set(
+    filter_set: &mut AHashMap<String, NodeListJson>,
+    seed_directory: &Utf8Path,
+    separator: u8,
+) -> io::Result<AHashSet<String>> {
+    let mut seeded = AHashSet::new();
+    for path in output_files(seed_directory)? {
+        for line in Reader::new(&path, BUF_CAPACITY)?.with_separator(separator).lines() {
+            let Ok(node) = serde_json::from_str::<BabelJson>(&line?) else {
+                continue;
+            };
+            if !filter_set.contains_key(&node.curie) {
+                filter_set.insert(
+                    node.curie.clone(),
+                    NodeListJson {
+                        id: node.curie.clone(),
+                        name: node.preferred_name.unwrap_or_default(),
+                        category: vec![],
+                        equivalent_identifiers: None,
+                        other_names: vec![],
+                        taxa: node.taxa,
+                    },
+                );
+                seeded.insert(node.curie);
+            }
+        }
+    }
+    Ok(seeded)
+}
+
+/// Reads the curie of every node in a directory of previous outputs, skipping NonBabelNodes and
+/// metadata sidecars. Records are split on `separator`.
+///
+/// Returns `Err` if the directory or one of its files can't be read
+pub fn read_output_curies(directory: &Utf8Path, separator: u8) -> io::Result<AHashSet<String>> {
+    let mut curies = AHashSet::new();
+    for path in output_files(directory)? {
+        for line in Reader::new(&path, BUF_CAPACITY)?.with_separator(separator).lines() {
+            if let Ok(node) = serde_json::from_str::<BabelJson>(&line?) {
+                curies.insert(node.curie);
+            }
+        }
+    }
+    Ok(curies)
+}
+
+/// Returns the Babel-derived output files in a directory, i.e. everything but NonBabelNodes and
+/// metadata sidecars
+pub fn output_files(directory: &Utf8Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_file()
+            && !file_name.starts_with("NonBabelNodes")
+            && !file_name.ends_with(".meta.json")
+        {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Returns whether any of a node's categories is in `category_set`, comparing them without regard
+/// to ASCII case if `ignore_case` is set
+fn has_any_category<'a, I>(set: I, category_set: &[String], ignore_case: bool) -> bool
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    if category_set.is_empty() {
+        return false;
+    }
+    for cat in set {
+        for ex_cat in category_set.iter() {
+            if cat == ex_cat || (ignore_case && cat.eq_ignore_ascii_case(ex_cat)) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::AHashMap;
+    use camino::Utf8PathBuf;
+
+    use super::{
+        build_bloom_filter, build_filter_set, clean_equivalents, has_any_category,
+        merge_filter_node, parse_tsv_node, shrink_filter_set, ShardedFilterSet,
+    };
+    use crate::{Config, NodeListJson};
+
+    fn filter_set(num_nodes: usize) -> AHashMap<String, NodeListJson> {
+        (0..num_nodes)
+            .map(|i| {
+                let id = format!("MONDO:{i}");
+                let node = NodeListJson {
+                    id: id.clone(),
+                    name: String::new(),
+                    category: vec![],
+                    equivalent_identifiers: None,
+                    other_names: vec![],
+                    taxa: vec![],
+                };
+                (id, node)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_filter_file_that_cant_be_opened() {
+        let args = Config {
+            filter_file: vec![Utf8PathBuf::from("missing/filter.jsonl")],
+            ..Default::default()
+        };
+
+        let errors = [
+            build_bloom_filter(&args, None).map(|_| ()).unwrap_err(),
+            build_filter_set(&args, None).map(|_| ()).unwrap_err(),
+        ];
+
+        for error in errors {
+            assert!(error.starts_with("Error opening filter file missing/filter.jsonl: "));
+        }
+    }
+
+    #[test]
+    fn parses_tsv_columns() {
+        let node = parse_tsv_node("biolink:Gene\tx\tNCBIGene:1", 3, 1).unwrap();
+        assert_eq!(node.id, "NCBIGene:1");
+        assert_eq!(node.category, vec!["biolink:Gene"]);
+
+        assert!(parse_tsv_node("NCBIGene:1\t", 1, 2).unwrap().category.is_empty());
+        assert!(parse_tsv_node("NCBIGene:1", 1, 2).is_err());
+        assert!(parse_tsv_node("\tbiolink:Gene", 1, 2).is_err());
+        assert!(parse_tsv_node("NCBIGene:1\tbiolink:Gene", 0, 2).is_err());
+    }
+
+    #[test]
+    fn removes_from_shards_and_rejoins() {
+        let sharded = ShardedFilterSet::new(filter_set(100), 8);
+
+        assert_eq!(sharded.find("MONDO:7").as_deref(), Some("MONDO:7"));
+        assert!(sharded.remove_id("MONDO:7").is_some());
+        assert!(sharded.find("MONDO:7").is_none());
+        assert!(sharded.find("MONDO:100").is_none());
+
+        let leftovers = sharded.into_inner();
+        assert_eq!(leftovers.len(), 99);
+        assert!(!leftovers.contains_key("MONDO:7"));
+    }
+
+    /// Compares looking up curies from many threads in a single locked map and in a sharded one.
+    /// Run with `cargo test --release -- --ignored --nocapture sharded_lookup_throughput`
+    #[test]
+    #[ignore]
+    fn sharded_lookup_throughput() {
+        let num_threads = 8;
+        let num_nodes = 2_000_000;
+        for num_shards in [1, 16, 64] {
+            let sharded = ShardedFilterSet::new(filter_set(num_nodes), num_shards);
+            let t0 = std::time::Instant::now();
+            std::thread::scope(|scope| {
+                for thread in 0..num_threads {
+                    let sharded = &sharded;
+                    scope.spawn(move || {
+                        for i in (thread..num_nodes).step_by(num_threads) {
+                            // half of the lookups miss, like Babel nodes that aren't in the filter
+                            for curie in [format!("MONDO:{i}"), format!("DOID:{i}")] {
+                                if let Some(id) = sharded.find(&curie) {
+                                    sharded.remove_id(&id);
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+            assert!(sharded.into_inner().is_empty());
+            println!("{num_shards} shards, {num_threads} threads: {:.2?}", t0.elapsed());
+        }
+    }
+
+    #[test]
+    fn removes_self_references_and_duplicate_equivalents() {
+        let mut node = NodeListJson {
+            id: String::from("MONDO:1"),
+            name: String::from("a"),
+            category: vec![],
+            equivalent_identifiers: Some(vec![
+                String::from("MONDO:1"),
+                String::from("DOID:1"),
+                String::from("UMLS:1"),
+                String::from("DOID:1"),
+            ]),
+            other_names: vec![],
+            taxa: vec![],
+        };
+
+        assert!(clean_equivalents(&mut node));
+        assert_eq!(
+            node.equivalent_identifiers,
+            Some(vec![String::from("DOID:1"), String::from("UMLS:1")])
+        );
+        assert!(!clean_equivalents(&mut node));
+    }
+
+    #[test]
+    fn compares_categories_with_or_without_case() {
+        let categories = [String::from("biolink:gene")];
+        let category_set = [String::from("biolink:Disease"), String::from("biolink:Gene")];
+
+        assert!(!has_any_category(&categories, &category_set, false));
+        assert!(has_any_category(&categories, &category_set, true));
+        assert!(!has_any_category(&categories, &[], true));
+    }
+
+    #[test]
+    fn finds_nodes_by_equivalent() {
+        let mut nodes = filter_set(3);
+        nodes.get_mut("MONDO:1").unwrap().equivalent_identifiers =
+            Some(vec![String::from("DOID:1"), String::from("UMLS:1"), String::from("MONDO:2")]);
+        let sharded = ShardedFilterSet::new(nodes, 4).with_equivalents();
+
+        assert_eq!(sharded.find("DOID:1").as_deref(), Some("MONDO:1"));
+        sharded.remove_id("MONDO:1");
+        // every curie of a removed node is gone
+        assert!(sharded.find("UMLS:1").is_none());
+        assert!(sharded.find("MONDO:1").is_none());
+        // an id matches its own node before any node it's an equivalent of
+        assert_eq!(sharded.find("MONDO:2").as_deref(), Some("MONDO:2"));
+        sharded.remove_id("MONDO:2");
+
+        assert_eq!(sharded.into_inner().len(), 1);
+    }
+
+    #[test]
+    fn finds_lowercased_ids() {
+        let mut nodes = filter_set(3);
+        let equivalents = vec![String::from("DOID:1")];
+        nodes.get_mut("MONDO:1").unwrap().equivalent_identifiers = Some(equivalents);
+        let sharded = ShardedFilterSet::new(nodes, 4).with_lowercase_ids().with_equivalents();
+
+        assert!(sharded.find("MONDO:2").is_none());
+        assert_eq!(sharded.find("mondo:2").as_deref(), Some("mondo:2"));
+        assert_eq!(sharded.find("doid:1").as_deref(), Some("mondo:1"));
+        sharded.remove_id("mondo:2");
+        sharded.remove_id("mondo:1");
+
+        // the leftovers are keyed by their original ids
+        assert_eq!(sharded.into_inner().keys().collect::<Vec<_>>(), vec!["MONDO:0"]);
+    }
+
+    #[test]
+    fn merges_duplicate_records() {
+        let node = |name: &str, category: &[&str]| NodeListJson {
+            id: String::from("MONDO:1"),
+            name: String::from(name),
+            category: category.iter().map(|category| String::from(*category)).collect(),
+            equivalent_identifiers: None,
+            other_names: vec![],
+            taxa: vec![],
+        };
+        let mut existing = node("", &["biolink:Disease"]);
+
+        merge_filter_node(&mut existing, node("a", &["biolink:Disease", "biolink:Entity"]));
+        merge_filter_node(&mut existing, node("b", &[]));
+        merge_filter_node(&mut existing, node("a", &["biolink:Entity"]));
+
+        assert_eq!(existing.name, "a");
+        assert_eq!(existing.other_names, vec!["b"]);
+        assert_eq!(existing.category, vec!["biolink:Disease", "biolink:Entity"]);
+    }
+
+    #[test]
+    fn shrinks_filter_set_capacity() {
+        let mut filter_set = AHashMap::with_capacity(1_000);
+        for i in 0..10 {
+            let id = format!("MONDO:{i}");
+            filter_set.insert(
+                id.clone(),
+                NodeListJson {
+                    id,
+                    name: String::new(),
+                    category: vec![],
+                    equivalent_identifiers: None,
+                    other_names: vec![],
+                    taxa: vec![],
+                },
+            );
+        }
+
+        let (capacity_before, capacity_after) = shrink_filter_set(&mut filter_set);
+
+        assert!(capacity_before >= 1_000);
+        assert!(capacity_after < capacity_before);
+        assert!(capacity_after >= 10);
+        assert_eq!(filter_set.len(), 10);
+    }
+}
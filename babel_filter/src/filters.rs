@@ -0,0 +1,310 @@
+use ahash::AHashSet;
+use serde_json::Value;
+use std::{io, path::Path};
+
+use crate::file::reader::Reader;
+use crate::BUF_CAPACITY;
+
+/// A `<pointer>=<value>` predicate evaluated against a Babel line parsed as a `serde_json::Value`.
+/// The pointer uses JSON Pointer syntax (e.g. `/meta/source`). The expected value is parsed as
+/// JSON if possible (so `true`, `3` and `"x"` compare as JSON values) and otherwise compared as a
+/// plain string.
+pub struct WherePointer {
+    pointer: String,
+    value: Value,
+}
+
+impl WherePointer {
+    /// Parses `<pointer>=<value>`, splitting on the first `=`.
+    ///
+    /// Returns `Err` with a message if there is no `=` or the pointer doesn't start with `/`
+    pub fn parse(arg: &str) -> Result<WherePointer, String> {
+        let (pointer, value) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <pointer>=<value> but got \"{arg}\""))?;
+        if !pointer.starts_with('/') {
+            return Err(format!("JSON pointer \"{pointer}\" must start with '/'"));
+        }
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::from(value));
+        Ok(WherePointer {
+            pointer: String::from(pointer),
+            value,
+        })
+    }
+
+    /// Returns true if the pointed-to field exists and equals the expected value
+    pub fn matches(&self, node: &Value) -> bool {
+        node.pointer(&self.pointer) == Some(&self.value)
+    }
+}
+
+/// Returns true if `line` parses as JSON and every predicate matches it. Parsing a whole line as a
+/// `Value` is considerably slower than the struct-based `BabelJson` parse, so callers should skip
+/// this entirely when there are no predicates.
+pub fn matches_all(pointers: &[WherePointer], line: &str) -> bool {
+    match serde_json::from_str::<Value>(line) {
+        Ok(node) => pointers.iter().all(|pointer| pointer.matches(&node)),
+        Err(_) => false,
+    }
+}
+
+/// A `<prefix>:<lo>-<hi>` inclusive range of the numeric local part of curies with a prefix
+#[derive(PartialEq, Debug)]
+pub struct CurieRange {
+    prefix: String,
+    lo: u64,
+    hi: u64,
+}
+
+impl CurieRange {
+    /// Parses `<prefix>:<lo>-<hi>`, splitting on the last `:`.
+    ///
+    /// Returns `Err` with a message if it isn't in that form or `lo` is greater than `hi`
+    pub fn parse(arg: &str) -> Result<CurieRange, String> {
+        let invalid = || format!("Expected <prefix>:<lo>-<hi> but got \"{arg}\"");
+        let (prefix, range) = arg.rsplit_once(':').ok_or_else(invalid)?;
+        let (lo, hi) = range.split_once('-').ok_or_else(invalid)?;
+        let (Ok(lo), Ok(hi)) = (lo.trim().parse(), hi.trim().parse()) else {
+            return Err(invalid());
+        };
+        if lo > hi {
+            return Err(format!("The range \"{arg}\" ends before it starts"));
+        }
+        Ok(CurieRange {
+            prefix: String::from(prefix),
+            lo,
+            hi,
+        })
+    }
+}
+
+/// Returns true if a curie is in one of the ranges for its prefix, or no range has its prefix. A
+/// curie with a ranged prefix whose local part isn't a number is in range if `keep_non_numeric`
+/// is set.
+pub fn in_numeric_range(curie: &str, ranges: &[CurieRange], keep_non_numeric: bool) -> bool {
+    let Some((prefix, local)) = curie.split_once(':') else {
+        return true;
+    };
+    let mut prefix_ranges = ranges.iter().filter(|range| range.prefix == prefix).peekable();
+    if prefix_ranges.peek().is_none() {
+        return true;
+    }
+    match local.parse::<u64>() {
+        Ok(number) => prefix_ranges.any(|range| (range.lo..=range.hi).contains(&number)),
+        Err(_) => keep_non_numeric,
+    }
+}
+
+/// The first filter that rejected a Babel node, in the order the filters are checked
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DropReason {
+    CurieRegex,
+    RepeatedCurie,
+    NotInFilterSet,
+    Blocklisted,
+    InBaseline,
+    MissingRequiredType,
+    MissingCategory,
+    Taxon,
+    NumericRange,
+    PreferredName,
+    WherePointer,
+    DuplicateCurie,
+    NameLength,
+}
+
+impl DropReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::CurieRegex => "curie_regex",
+            DropReason::RepeatedCurie => "repeated_curie",
+            DropReason::NotInFilterSet => "not_in_filter_set",
+            DropReason::Blocklisted => "blocklisted",
+            DropReason::InBaseline => "in_baseline",
+            DropReason::MissingRequiredType => "missing_required_type",
+            DropReason::MissingCategory => "missing_category",
+            DropReason::Taxon => "taxon",
+            DropReason::NumericRange => "numeric_range",
+            DropReason::PreferredName => "preferred_name",
+            DropReason::WherePointer => "where_pointer",
+            DropReason::DuplicateCurie => "duplicate_curie",
+            DropReason::NameLength => "name_length",
+        }
+    }
+}
+
+/// Returns true if every required type is among `types`, unlike `--exclude-category` which
+/// checks for any overlap. A `biolink:` prefix on either side is ignored.
+pub fn has_all_types(types: &[String], required_types: &[String]) -> bool {
+    fn unprefixed(t: &str) -> &str {
+        t.strip_prefix("biolink:").unwrap_or(t)
+    }
+    required_types.iter().all(|required| {
+        types.iter().any(|t| unprefixed(t) == unprefixed(required))
+    })
+}
+
+/// Returns true if any of a node's taxa is among `wanted_taxa`. A node without taxa matches only
+/// if `keep_untaxed` is set.
+pub fn has_wanted_taxon(taxa: &[String], wanted_taxa: &[String], keep_untaxed: bool) -> bool {
+    if taxa.is_empty() {
+        return keep_untaxed;
+    }
+    taxa.iter().any(|taxon| wanted_taxa.contains(taxon))
+}
+
+/// Returns true if a shortest name length is within the optional inclusive bounds. A node with no
+/// names (`None`) is only within bounds if neither bound is set.
+pub fn name_length_in_bounds(length: Option<usize>, min: Option<usize>, max: Option<usize>) -> bool {
+    match length {
+        Some(length) => length >= min.unwrap_or(0) && length <= max.unwrap_or(usize::MAX),
+        None => min.is_none() && max.is_none(),
+    }
+}
+
+/// Loads a set of lines, such as allowed preferred names or blocklisted curies, one per line,
+/// skipping blank lines. The file may be compressed, like the Babel files.
+///
+/// Returns `Err` if there is a problem reading the file
+pub fn load_line_set<P: AsRef<Path>>(path: P) -> io::Result<AHashSet<String>> {
+    Reader::new(path, BUF_CAPACITY)?
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+        .collect()
+}
+
+/// Returns true if `name` matches a glob `pattern`, where `*` matches any run of characters
+/// (including none) and `?` matches any one character
+pub fn matches_glob(name: &str, pattern: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut n, mut p) = (0, 0);
+    // the position of the last `*` and how much of the name it has matched up to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                n += 1;
+                p += 1;
+            }
+            // let the last `*` match one more character and try again
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Returns true if `line` starts with any of the comment prefixes
+pub fn is_comment(line: &str, comment_prefixes: &[String]) -> bool {
+    comment_prefixes.iter().any(|prefix| line.starts_with(prefix.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::io;
+    use tempfile::tempdir;
+
+    use super::{
+        has_all_types, has_wanted_taxon, in_numeric_range, load_line_set, matches_glob, CurieRange,
+        WherePointer,
+    };
+    use crate::file::writer::Writer;
+
+    #[test]
+    fn matches_globs() {
+        assert!(matches_glob("Gene.txt.gz", "*.txt.gz"));
+        assert!(matches_glob("Gene.txt.gz", "*"));
+        assert!(matches_glob("Gene.txt.gz", "G?ne*.gz"));
+        assert!(matches_glob("Gene.part0001.txt", "*.part*.txt"));
+        assert!(!matches_glob("Gene.txt.gz.md5", "*.txt.gz"));
+        assert!(!matches_glob("README.md", "*.jsonl"));
+        assert!(!matches_glob("Gene.txt", "?Gene.txt"));
+    }
+
+    #[test]
+    fn keeps_curies_in_numeric_range() {
+        let ranges = [
+            CurieRange::parse("NCBIGene:1-100").unwrap(),
+            CurieRange::parse("NCBIGene:500-600").unwrap(),
+        ];
+
+        assert!(in_numeric_range("NCBIGene:1", &ranges, false));
+        assert!(in_numeric_range("NCBIGene:100", &ranges, false));
+        assert!(in_numeric_range("NCBIGene:550", &ranges, false));
+        assert!(!in_numeric_range("NCBIGene:101", &ranges, false));
+        assert!(!in_numeric_range("NCBIGene:0", &ranges, false));
+        // other prefixes aren't ranged
+        assert!(in_numeric_range("MONDO:999999", &ranges, false));
+        assert!(!in_numeric_range("NCBIGene:abc", &ranges, false));
+        assert!(in_numeric_range("NCBIGene:abc", &ranges, true));
+
+        assert!(CurieRange::parse("NCBIGene:100-1").is_err());
+        assert!(CurieRange::parse("NCBIGene:1").is_err());
+        assert!(CurieRange::parse("NCBIGene").is_err());
+    }
+
+    #[test]
+    fn matches_nested_field() {
+        let node = json!({"curie": "MONDO:1", "meta": {"source": "mondo", "version": 2}});
+
+        assert!(WherePointer::parse("/meta/source=mondo").unwrap().matches(&node));
+        assert!(WherePointer::parse("/meta/version=2").unwrap().matches(&node));
+        assert!(!WherePointer::parse("/meta/source=umls").unwrap().matches(&node));
+        assert!(!WherePointer::parse("/meta/missing=mondo").unwrap().matches(&node));
+    }
+
+    #[test]
+    fn requires_every_type() {
+        let types = vec![String::from("Gene"), String::from("biolink:GeneOrGeneProduct")];
+
+        assert!(has_all_types(
+            &types,
+            &[String::from("biolink:Gene"), String::from("GeneOrGeneProduct")]
+        ));
+        assert!(!has_all_types(&types, &[String::from("Gene"), String::from("Protein")]));
+        assert!(has_all_types(&types, &[]));
+    }
+
+    #[test]
+    fn matches_any_wanted_taxon() {
+        let human = [String::from("NCBITaxon:9606")];
+        let taxa = vec![String::from("NCBITaxon:10090"), String::from("NCBITaxon:9606")];
+
+        assert!(has_wanted_taxon(&taxa, &human, false));
+        assert!(!has_wanted_taxon(&[String::from("NCBITaxon:10090")], &human, true));
+        assert!(!has_wanted_taxon(&[], &human, false));
+        assert!(has_wanted_taxon(&[], &human, true));
+    }
+
+    #[test]
+    fn rejects_malformed_predicates() {
+        assert!(WherePointer::parse("/meta/source").is_err());
+        assert!(WherePointer::parse("meta/source=mondo").is_err());
+    }
+
+    #[test]
+    fn loads_gzipped_line_set() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("names.txt.gz");
+        Writer::new(&path, 32_000)?.write_line("Asthma")?.write_line("")?.write_line("Gout")?;
+
+        let line_set = load_line_set(&path)?;
+
+        assert_eq!(line_set.len(), 2);
+        assert!(line_set.contains("Asthma") && line_set.contains("Gout"));
+
+        dir.close()
+    }
+}
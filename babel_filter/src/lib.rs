@@ -1,201 +1,4020 @@
+mod bloom;
+mod cache;
 mod config;
+mod curie_map;
 mod file;
+mod filter_diff;
+mod filter_set;
+mod filters;
+mod merge;
+mod output;
+mod pool;
+mod progress;
+mod provenance;
+mod rechunk;
+mod repair;
+mod stats;
+mod summary;
+mod transform;
 
-use ahash::AHashMap;
-pub use config::{Config, OutputFormat};
-use file::{reader::Reader, writer::Writer};
+use ahash::{AHashMap, AHashSet};
+use bloom::BloomFilter;
+use cache::FilterCache;
+use camino::Utf8Path;
+use curie_map::CurieMap;
+pub use config::{Config, ConflictPolicy, OutputFormat, UnicodeForm};
+pub use filter_diff::filter_diff;
+pub use rechunk::rechunk;
+pub use summary::summary;
+use file::{
+    chunked::{self, ChunkLimits, ChunkedWriter},
+    reader::Reader,
+    writer::{CompressionLevels, Writer},
+};
+use filter_set::{FilterFileStats, ShardedFilterSet};
+use filters::{CurieRange, DropReason, WherePointer};
+use flate2::Compression;
+use output::{GroupedByPrefix, LargestPerPrefix, OutputSerializer, TabularSerializer};
+use progress::{ErrorLog, FailedLines, Progress};
+use provenance::{OutputMeta, ProvenanceHeader};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::{ffi::OsStr, fs, path::Path, process::ExitCode, time::Instant};
+use stats::{ExpectedCounts, FileRunReport, FileSummary, PrefixStats, RunReport};
+use transform::Transforms;
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
 
 const BUF_CAPACITY: usize = 32_000;
 
-#[derive(Serialize, Deserialize)]
+/// The default file name of the leftover filter nodes written to the output directory
+const NON_BABEL_NODES_NAME: &str = "NonBabelNodes.txt.gz";
+
+/// The name Babel records read from stdin are filtered under, and written to without `--stdout`
+const STDIN_NAME: &str = "stdin.txt";
+
+#[derive(Clone, Serialize, Deserialize)]
 struct BabelJson {
     curie: String,
     names: Vec<String>,
     types: Vec<String>,
     preferred_name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_name_length")]
     shortest_name_length: Option<usize>,
     taxa: Vec<String>,
 }
 
+/// Deserializes `shortest_name_length` from either an integer or an integral float like `12.0`,
+/// which some upstream Babel files contain
+fn deserialize_name_length<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(number) = Option::<serde_json::Number>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let length = match (number.as_u64(), number.as_f64()) {
+        (Some(length), _) => usize::try_from(length).ok(),
+        (None, Some(length)) if length >= 0.0 && length.fract() == 0.0 => Some(length as usize),
+        _ => None,
+    };
+    length.map(Some).ok_or_else(|| {
+        serde::de::Error::custom(format!("invalid name length {number}, expected a whole number"))
+    })
+}
+
+/// A `BabelJson` node along with any fields it doesn't model, used when re-serializing nodes
+#[derive(Serialize, Deserialize)]
+struct BabelRecord {
+    #[serde(flatten)]
+    node: BabelJson,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A filter file node, from a KGX node list
 #[derive(Serialize, Deserialize)]
-struct NodeListJson {
+pub struct NodeListJson {
     id: String,
     name: String,
     category: Vec<String>,
     equivalent_identifiers: Option<Vec<String>>,
+    /// Names of duplicate records merged into this one, with `merge_duplicate_filter_records`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    other_names: Vec<String>,
+    /// Carried into a synthesized NonBabelNodes node, since KGX node lists may record them (as
+    /// `in_taxon`)
+    #[serde(default, alias = "in_taxon", skip_serializing_if = "Vec::is_empty")]
+    taxa: Vec<String>,
+}
+
+impl NodeListJson {
+    /// A filter node with no equivalent identifiers or taxa, e.g. to pass to `filter_records`
+    pub fn new(id: impl Into<String>, name: impl Into<String>, category: Vec<String>) -> Self {
+        NodeListJson {
+            id: id.into(),
+            name: name.into(),
+            category,
+            equivalent_identifiers: None,
+            other_names: Vec::new(),
+            taxa: Vec::new(),
+        }
+    }
+
+    pub fn with_equivalent_identifiers(mut self, equivalent_identifiers: Vec<String>) -> Self {
+        self.equivalent_identifiers = Some(equivalent_identifiers);
+        self
+    }
+
+    pub fn with_taxa(mut self, taxa: Vec<String>) -> Self {
+        self.taxa = taxa;
+        self
+    }
 }
 
 pub fn run(args: Config) -> ExitCode {
     let start = Instant::now();
 
-    let babel_directory = args.babel_directory;
-    let filter_file = args.filter_file;
-    let output_directory = args.output_directory;
+    let progress = Progress::new(args.progress_to_stderr)
+        .with_quiet(args.quiet)
+        .with_progress(!args.no_progress)
+        .with_summary_to_stderr(args.stdout);
+    let separator = args.record_separator.unwrap_or(b'\n');
 
-    if !babel_directory.is_dir() {
-        eprintln!("The path provided to the Babel directory isn't a directory or doesn't exist");
-        return ExitCode::FAILURE;
-    }
-    if !filter_file.is_file() {
-        eprintln!("The path provided to the filter file isn't a file or doesn't exist");
-        return ExitCode::FAILURE;
-    }
-    if !output_directory.is_dir() {
-        eprintln!("The path provided to the output directory isn't a directory or doesn't exist");
+    let babel_directory = &args.babel_directory;
+    let filter_files = &args.filter_file;
+    let output_directory = &args.output_directory;
+
+    if let Err(e) = check_args(&args) {
+        eprintln!("{e}");
         return ExitCode::FAILURE;
     }
 
-    let mut filter_set: AHashMap<String, NodeListJson> = AHashMap::new();
+    let where_pointers = match args
+        .where_pointer
+        .iter()
+        .flatten()
+        .map(|arg| WherePointer::parse(arg))
+        .collect::<Result<Vec<WherePointer>, String>>()
     {
-        let mut num_removed: usize = 0;
+        Ok(where_pointers) => where_pointers,
+        Err(e) => {
+            eprintln!("Invalid JSON pointer predicate: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let numeric_ranges = match args
+        .curie_numeric_range
+        .iter()
+        .flatten()
+        .map(|arg| CurieRange::parse(arg))
+        .collect::<Result<Vec<CurieRange>, String>>()
+    {
+        Ok(numeric_ranges) => numeric_ranges,
+        Err(e) => {
+            eprintln!("Invalid curie numeric range: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let curie_pattern = match filter_set::compile_curie_pattern(&args) {
+        Ok(curie_pattern) => curie_pattern,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let expected_counts = match args.expected_counts {
+        Some(ref path) => match ExpectedCounts::load(path, args.tolerance) {
+            Ok(expected_counts) => Some(expected_counts),
+            Err(e) => {
+                eprintln!("Error reading the expected counts file: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let curie_map = match args.curie_map {
+        Some(ref path) => match CurieMap::load(path) {
+            Ok(curie_map) => Some(curie_map),
+            Err(e) => {
+                eprintln!("Error reading the curie map: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let line_counts = match args.progress_from_counts {
+        Some(ref path) => match progress::load_line_counts(path) {
+            Ok(line_counts) => Some(line_counts),
+            Err(e) => {
+                eprintln!("Error reading the line counts: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let preferred_names = match args.preferred_name_in_file {
+        Some(ref path) => match filters::load_line_set(path) {
+            Ok(names) => Some(match args.normalize_unicode {
+                Some(form) => names.iter().map(|name| transform::normalize(name, form)).collect(),
+                None => names,
+            }),
+            Err(e) => {
+                eprintln!("Error reading the preferred names file: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    // there's no Babel directory to configure stdin
+    let output_formats = if args.stdin {
+        AHashMap::new()
+    } else {
+        match output::load_output_formats(babel_directory.join(output::FORMATS_FILE)) {
+            Ok(output_formats) => output_formats,
+            Err(e) => {
+                eprintln!("Error reading {}: {e}", output::FORMATS_FILE);
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+
+    let transforms = Transforms {
+        max_output_names: args.max_output_names,
+        dedup_taxa: args.dedup_taxa,
+        sort_names: args.sort_names,
+        strip_empty_fields: args.strip_empty_fields,
+        empty_taxa_null: args.empty_taxa_null,
+        normalize_unicode: args.normalize_unicode,
+        verify_roundtrip: args.verify_roundtrip,
+        preserve_key_order: args.preserve_key_order,
+    };
+
+    let cache = match args.filter_cache_dir {
+        Some(ref cache_dir) if !args.bloom_filter && !args.ids_only => {
+            match FilterCache::new(cache_dir, &args) {
+                Ok(cache) => Some(cache),
+                Err(e) => {
+                    eprintln!("Error reading filter file metadata, not using the filter cache: {e}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let failed_lines = match args.failed_lines_output {
+        Some(ref path) if !args.dry_run => match FailedLines::new(path) {
+            Ok(failed_lines) => Some(failed_lines),
+            Err(e) => {
+                eprintln!("Error creating the failed lines file: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => None,
+    };
+
+    let mut bloom_filter = None;
+    let mut id_set = None;
+    // the filter nodes read and excluded, unless the filter set came from the cache
+    let mut filter_counts = None;
+    let mut filter_set = if args.bloom_filter {
         let t0 = Instant::now();
-        let lines = Reader::new(filter_file, BUF_CAPACITY)
-            .expect("Error opening filter file")
-            .lines();
-        for (line_index, line) in lines.enumerate() {
-            if let Ok(node_json) = line {
-                match serde_json::from_str::<NodeListJson>(&node_json) {
-                    Ok(node) => {
-                        if let Some(ref exclude_cats) = args.exclude_category {
-                            if !has_excluded_category(node.category.iter(), &exclude_cats) {
-                                filter_set.insert(String::from(&node.id), node);
-                            } else {
-                                num_removed += 1;
-                            }
-                        } else {
-                            filter_set.insert(String::from(&node.id), node);
-                        }
+        let built = filter_set::build_bloom_filter(&args, failed_lines.as_ref());
+        let (bloom, filter_file_stats) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        progress.log(format_args!(
+            "Creating Bloom filter ({} bytes) took {:.2?}",
+            bloom.size_bytes(),
+            t0.elapsed()
+        ));
+        print_filter_file_stats(&filter_file_stats, progress);
+        filter_counts = Some((filter_file_stats.num_read, filter_file_stats.num_excluded));
+        bloom_filter = Some(bloom);
+        AHashMap::new()
+    } else if args.ids_only {
+        let t0 = Instant::now();
+        let built = filter_set::build_id_set(&args, failed_lines.as_ref());
+        let (ids, filter_file_stats) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        progress.log(format_args!(
+            "Creating id set of {} ids took {:.2?}",
+            ids.len(),
+            t0.elapsed()
+        ));
+        print_filter_file_stats(&filter_file_stats, progress);
+        filter_counts = Some((filter_file_stats.num_read, filter_file_stats.num_excluded));
+        id_set = Some(ids);
+        AHashMap::new()
+    } else {
+        match cache.as_ref().and_then(FilterCache::load) {
+            // the cache may have been built without a maximum, or with a larger one
+            Some(filter_set)
+                if args.max_filter_set_size.is_some_and(|max_size| filter_set.len() > max_size) =>
+            {
+                eprintln!(
+                    "The cached filter set has {} entries, more than the maximum of {}",
+                    filter_set.len(),
+                    args.max_filter_set_size.unwrap_or_default()
+                );
+                return ExitCode::FAILURE;
+            }
+            Some(filter_set) => {
+                progress.log(format_args!("Loaded filter set from cache"));
+                filter_set
+            }
+            None => {
+                let t0 = Instant::now();
+                let built = filter_set::build_filter_set(&args, failed_lines.as_ref());
+                let (filter_set, filter_file_stats) = match built {
+                    Ok(built) => built,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        return ExitCode::FAILURE;
                     }
-                    Err(e) => eprintln!("Parse error in filter file line {}: {e}", line_index + 1),
+                };
+                progress.log(format_args!(
+                    "Creating filter set took {:.2?}, read {} nodes from {} filter files into {} \
+                     ids",
+                    t0.elapsed(),
+                    filter_file_stats.num_read,
+                    filter_files.len(),
+                    filter_set.len()
+                ));
+                print_filter_file_stats(&filter_file_stats, progress);
+                filter_counts = Some((filter_file_stats.num_read, filter_file_stats.num_excluded));
+                if let Some(cache) = cache.as_ref().filter(|_| !args.dry_run) {
+                    if let Err(e) = cache.save(&filter_set) {
+                        eprintln!("Error writing filter cache: {e}");
+                    }
+                }
+                filter_set
+            }
+        }
+    };
+
+    if args.shrink_filter_set {
+        let (capacity_before, capacity_after) = filter_set::shrink_filter_set(&mut filter_set);
+        if args.filter_set_memory_report {
+            progress.log(format_args!(
+                "Shrank the filter set of {} nodes from a capacity of {} to {}",
+                filter_set.len(),
+                capacity_before,
+                capacity_after
+            ));
+        }
+    } else if args.filter_set_memory_report {
+        progress.log(format_args!(
+            "The filter set holds {} nodes with a capacity of {}",
+            filter_set.len(),
+            filter_set.capacity()
+        ));
+    }
+
+    // hashed once up front since every sidecar and header records the same filter files
+    let filter_file_crc32 = if args.meta_sidecar || args.provenance_header {
+        match provenance::files_crc32(filter_files) {
+            Ok(crc32) => Some(crc32),
+            Err(e) => {
+                eprintln!("Error hashing the filter files: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    // curies seeded from a previous output, so they're kept again even if the filter file dropped them
+    let seeded = match args.seed_filter_from_output {
+        Some(ref seed_directory) => match filter_set::seed_filter_set(
+            &mut filter_set,
+            seed_directory,
+            separator,
+        ) {
+            Ok(seeded) => {
+                progress.log(format_args!(
+                    "Seeded the filter set with {} curies from previous outputs",
+                    seeded.len()
+                ));
+                seeded
+            }
+            Err(e) => {
+                eprintln!("Error seeding the filter set from previous outputs: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => AHashSet::new(),
+    };
+
+    // applied after seeding so a blocklisted curie can't come back from a previous output
+    let excluded_curies = match args.exclude_curie_file {
+        Some(ref path) => match filters::load_line_set(path) {
+            Ok(excluded_curies) => {
+                let num_filter_ids = filter_set.len();
+                filter_set.retain(|curie, _| !excluded_curies.contains(curie));
+                progress.info(format_args!(
+                    "Removed {} filter ids on the curie blocklist",
+                    num_filter_ids - filter_set.len()
+                ));
+                Some(excluded_curies)
+            }
+            Err(e) => {
+                eprintln!("Error reading the curie blocklist: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let provenance_header = match filter_file_crc32 {
+        Some(ref filter_file_crc32) if args.provenance_header => {
+            match serde_json::to_string(&ProvenanceHeader::new(&args, filter_file_crc32)) {
+                Ok(header) => Some(header),
+                Err(e) => {
+                    eprintln!("Error serializing the provenance header: {e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let babel_inputs = match list_babel_inputs(&args, progress) {
+        Ok(babel_inputs) => babel_inputs,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // curies already in a previous output, which aren't written again in delta mode
+    let baseline_curies = match args.baseline_dir {
+        Some(ref baseline_dir) if args.only_new_curies => {
+            match filter_set::read_output_curies(baseline_dir, separator) {
+                Ok(curies) => {
+                    progress.log(format_args!("Read {} baseline curies", curies.len()));
+                    Some(curies)
+                }
+                Err(e) => {
+                    eprintln!("Error reading the baseline directory: {e}");
+                    return ExitCode::FAILURE;
                 }
-            } else {
-                eprintln!("Read error in filter file line {}", line_index + 1)
             }
         }
-        println!("Creating filter set took {:.2?}", t0.elapsed());
-        println!("{} nodes excluded", num_removed);
+        _ => None,
+    };
+
+    let drop_log = match args.debug_drop_reasons {
+        Some(ref path) if !args.dry_run => match Writer::new(path, BUF_CAPACITY) {
+            Ok(writer) => Some(Mutex::new(writer)),
+            Err(e) => {
+                eprintln!("Error creating the drop reasons file: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => None,
+    };
+
+    // the modes that don't hold the filter nodes, so leftovers can't be tracked
+    let untracked_mode = if bloom_filter.is_some() {
+        Some("Bloom filter mode")
+    } else if id_set.is_some() {
+        Some("ids-only mode")
+    } else {
+        None
+    };
+
+    let serializer = output::select_serializer(&transforms);
+    // seeded curies didn't come from the filter file, so they don't count towards its coverage
+    let num_seeded = |filter_set: &AHashMap<String, NodeListJson>| {
+        filter_set.keys().filter(|curie| seeded.contains(*curie)).count()
+    };
+    let filter_set_size = filter_set.len() - num_seeded(&filter_set);
+    let written_curies = args.global_dedup.then(|| Mutex::new(AHashSet::new()));
+    let sharded_filter_set = filter_set::shard_filter_set(&args, filter_set);
+    // files written to stdout at once would interleave their lines
+    let concurrency = if args.stdout { 1 } else { args.concurrency };
+    let context = FileContext {
+        progress,
+        bloom_filter: bloom_filter.as_ref(),
+        id_set: id_set.as_ref(),
+        curie_map: curie_map.as_ref(),
+        curie_pattern: curie_pattern.as_ref(),
+        line_counts: line_counts.as_ref(),
+        baseline_curies: baseline_curies.as_ref(),
+        preferred_names: preferred_names.as_ref(),
+        excluded_curies: excluded_curies.as_ref(),
+        written_curies: written_curies.as_ref(),
+        drop_log: drop_log.as_ref(),
+        failed_lines: failed_lines.as_ref(),
+        where_pointers: &where_pointers,
+        numeric_ranges: &numeric_ranges,
+        serializer: serializer.as_ref(),
+        provenance_header: provenance_header.as_deref(),
+        filter_file_crc32: filter_file_crc32
+            .as_deref()
+            .filter(|_| args.meta_sidecar && !args.dry_run),
+        output_formats: Some(&output_formats),
+        ..FileContext::new(&args, &sharded_filter_set, &transforms)
+    };
+    let input_names: Vec<String> = babel_inputs
+        .iter()
+        .map(|(babel_path, _)| babel_path.file_name().unwrap_or_default().to_string_lossy())
+        .map(String::from)
+        .collect();
+    let file_reports: Mutex<Vec<FileReport>> = Mutex::new(Vec::with_capacity(babel_inputs.len()));
+    // a file whose output can't be written is reported, and the rest are still filtered
+    let failed_files = Mutex::new(Vec::new());
+    // inputs skipped after a warning, whose earlier outputs mustn't be pruned
+    let skipped_files = Mutex::new(Vec::new());
+    pool::for_each_bounded(babel_inputs, concurrency, |(babel_path, fragments)| {
+        match filter_babel_file(&context, &babel_path, &fragments) {
+            Ok(Some(report)) => file_reports.lock().unwrap().push(report),
+            Ok(None) => skipped_files.lock().unwrap().push(babel_path),
+            Err(e) => {
+                eprintln!("Error writing the output of {:?}: {e}", babel_path);
+                failed_files.lock().unwrap().push(babel_path);
+            }
+        }
+    });
+    let mut file_reports = file_reports.into_inner().unwrap();
+    for report in &mut file_reports {
+        for id in report.matched_ids.drain() {
+            sharded_filter_set.remove_id(&id);
+        }
     }
+    let mut filter_set = sharded_filter_set.into_inner();
+    let num_leftovers = filter_set.len() - num_seeded(&filter_set);
 
-    for babel_file in fs::read_dir(babel_directory).unwrap() {
-        match babel_file {
-            Ok(f) => {
-                if f.path().is_file() {
-                    let t0 = Instant::now();
-                    let mut num_nodes: usize = 0;
-                    let mut num_kept: usize = 0;
+    let mut prefix_stats = args.per_prefix_stats.then(PrefixStats::default);
+    let mut distinct_types = args.count_distinct_types.then(AHashSet::new);
+    let mut file_counts: AHashMap<String, usize> = AHashMap::new();
+    let mut names_index = AHashSet::new();
+    let mut num_duplicates = 0;
+    let mut num_missing_category = 0;
+    let mut num_curie_regex_excluded = 0;
+    let mut num_repaired = 0;
+    let mut bytes_read = 0;
+    let mut written_paths = AHashSet::new();
+    let mut file_summaries = Vec::new();
+    for report in file_reports {
+        bytes_read += report.bytes_read;
+        written_paths.extend(report.output_paths);
+        num_duplicates += report.num_duplicates;
+        num_missing_category += report.num_missing_category;
+        num_curie_regex_excluded += report.num_curie_regex_excluded;
+        num_repaired += report.num_repaired;
+        if let (Some(stats), Some(file_stats)) = (prefix_stats.as_mut(), report.prefix_stats) {
+            stats.merge(file_stats);
+        }
+        if let (Some(types), Some(file_types)) = (distinct_types.as_mut(), report.distinct_types) {
+            types.extend(file_types);
+        }
+        names_index.extend(report.names_index);
+        if args.summary_table || args.report_json.is_some() {
+            file_summaries.push(FileSummary {
+                name: report.input_name.clone(),
+                num_read: report.num_nodes,
+                num_kept: report.num_kept,
+                duration: report.duration,
+            });
+        }
+        file_counts.insert(report.input_name, report.num_nodes);
+    }
 
-                    let mut output_file_path = Path::join(
-                        output_directory.as_std_path(),
-                        f.path().file_name().unwrap(), // should be safe to unwrap as we're checking is_file() above
-                    );
+    // the table is the one thing still printed with --quiet
+    if args.summary_table {
+        for line in stats::file_table(&mut file_summaries) {
+            println!("{line}");
+        }
+    }
 
-                    // force compressed/not compressed output if output_format arg is set
-                    match args.output_format {
-                        Some(OutputFormat::Plaintext) => {
-                            if output_file_path.extension() == Some(OsStr::new("gz")) {
-                                output_file_path = output_file_path.with_extension("")
-                            }
-                        }
-                        Some(OutputFormat::Gzipped) => {
-                            if output_file_path.extension() != Some(OsStr::new("gz")) {
-                                output_file_path = output_file_path.with_extension("gz")
-                            }
-                        }
-                        None => (),
-                    }
+    if args.global_dedup {
+        progress.info(format_args!(
+            "Suppressed {num_duplicates} duplicate curies already written by another file"
+        ));
+    }
+    if args.require_category {
+        progress.info(format_args!("Dropped {num_missing_category} Babel nodes with no types"));
+    }
+    if args.exclude_curie_regex.is_some() {
+        progress.info(format_args!(
+            "Dropped {num_curie_regex_excluded} Babel nodes whose curie matches the curie regex"
+        ));
+    }
+    if args.repair_json {
+        progress.info(format_args!("Repaired {num_repaired} malformed Babel lines"));
+    }
 
-                    let reader: Reader = Reader::new(f.path(), BUF_CAPACITY)
-                        .expect("Error opening file for reading");
-                    let mut writer: Writer = Writer::new(output_file_path.clone(), BUF_CAPACITY)
-                        .expect("Error creating file");
-
-                    for (line_index, line) in reader.lines().enumerate() {
-                        num_nodes += 1;
-                        if let Ok(node_json) = line {
-                            match serde_json::from_str::<BabelJson>(&node_json) {
-                                Ok(node) => {
-                                    if filter_set.remove(&node.curie).is_some() {
-                                        num_kept += 1;
-                                        writer.write_line(&node_json).expect("Error writing line");
-                                    }
-                                }
-                                Err(e) => eprint!("{e}"),
-                            }
-                        } else {
-                            eprintln!(
-                                "Something went wrong reading line {} of {:?}",
-                                line_index + 1,
-                                f.path()
-                            )
-                        }
-                    }
+    if let Some(names_index_path) = args.names_index.as_ref().filter(|_| !args.dry_run) {
+        match output::write_names_index(names_index_path, names_index) {
+            Ok(num_pairs) => {
+                progress.info(format_args!("Wrote {num_pairs} name/curie pairs to the names index"))
+            }
+            Err(e) => eprintln!("Error writing the names index: {e}"),
+        }
+    }
 
-                    println!(
-                        "Writing {:?} took {:.2?}, kept {}/{} nodes ({:.2}%)",
-                        output_file_path.file_name().unwrap_or_default(),
-                        t0.elapsed(),
-                        num_kept,
-                        num_nodes,
-                        (num_kept as f64 / num_nodes as f64) * 100.0
-                    );
+    // seeded curies came from Babel rather than the filter file, so there's nothing to synthesize
+    if !seeded.is_empty() {
+        let num_leftovers = filter_set.len();
+        filter_set.retain(|curie, _| !seeded.contains(curie));
+        progress.info(format_args!(
+            "{} seeded curies weren't found in Babel and won't be written to NonBabelNodes",
+            num_leftovers - filter_set.len()
+        ));
+    }
+
+    let mut leftover_ids_failed = false;
+    if let Some(leftovers_path) = args.leftovers_as_ids.as_ref().filter(|_| !args.dry_run) {
+        if let Some(mode) = untracked_mode {
+            progress.info(format_args!(
+                "Leftover ids aren't written in {mode} since they can't be tracked"
+            ));
+        } else {
+            let mut leftover_ids: Vec<String> = filter_set.keys().cloned().collect();
+            leftover_ids.sort_unstable();
+            match output::write_ids(leftovers_path, &leftover_ids) {
+                Ok(()) => {
+                    progress.info(format_args!(
+                        "Wrote {} leftover ids to {leftovers_path}",
+                        leftover_ids.len()
+                    ))
+                }
+                Err(e) => {
+                    eprintln!("Error writing the leftover ids: {e}");
+                    leftover_ids_failed = true;
                 }
             }
-            Err(error) => eprintln!("Error opening file in babel directory: {error}"),
         }
     }
 
-    // create a new file (NonBabelNodes.txt.gz) for all the extra nodes in the filter_set
-    let non_babel_nodes_path = Path::join(output_directory.as_std_path(), "./NonBabelNodes.txt.gz");
-    let mut nbn_writer =
-        Writer::new(non_babel_nodes_path, BUF_CAPACITY).expect("Error creating NonBabelNodes file");
-    let filter_set_size = filter_set.len();
-    for (curie, node_json) in filter_set {
-        let NodeListJson { name, category, .. } = node_json;
+    // only synthesize leftovers in the requested namespaces
+    if let Some(ref prefixes) = args.nonbabel_prefix {
+        let num_leftovers = filter_set.len();
+        filter_set.retain(|curie, _| has_prefix(curie, prefixes));
+        progress.info(format_args!(
+            "{} leftover nodes filtered out by NonBabelNodes prefix",
+            num_leftovers - filter_set.len()
+        ));
+    }
 
-        let types = category
-            .iter()
-            .map(|s| s.replace("biolink:", ""))
-            .collect::<Vec<String>>();
-
-        let converted_node = BabelJson {
-            curie,
-            names: vec![name.clone()],
-            types,
-            preferred_name: Some(name.clone()),
-            shortest_name_length: Some(name.len()),
-            taxa: vec![]
-        };
+    let mut failed_files = failed_files.into_inner().unwrap();
+    let mut num_non_babel_nodes = None;
+    if args.no_non_babel_nodes {
+        progress.log(format_args!("Not writing NonBabelNodes"));
+    } else if args.stdout {
+        progress.info(format_args!(
+            "NonBabelNodes isn't written with --stdout, so {} leftover nodes aren't written",
+            filter_set.len()
+        ));
+    } else if let Some(mode) = untracked_mode {
+        progress.info(format_args!(
+            "NonBabelNodes isn't written in {mode} since leftovers can't be tracked"
+        ));
+    } else if args.dry_run {
+        progress.info(format_args!(
+            "Dry run, so the {} leftover nodes aren't written to NonBabelNodes",
+            filter_set.len()
+        ));
+    } else {
+        let t0 = Instant::now();
+        match write_non_babel_nodes(filter_set, &args, &transforms, provenance_header.as_deref()) {
+            Ok(report) => {
+                progress.log(format_args!("Synthesizing NonBabelNodes took {:.2?}", t0.elapsed()));
+                progress.info(format_args!(
+                    "Wrote an extra {} nodes to {}",
+                    report.num_written,
+                    report.path.file_name().unwrap_or_default().to_string_lossy()
+                ));
+                // a renamed NonBabelNodes looks like any other output to --prune-orphans
+                written_paths.insert(report.path.clone());
+                num_non_babel_nodes = Some(report.num_written);
+                if report.num_outside_name_length > 0 {
+                    progress.info(format_args!(
+                        "{} leftover nodes weren't written to NonBabelNodes for their name length",
+                        report.num_outside_name_length
+                    ));
+                }
+            }
+            Err(e) => {
+                eprintln!("Error writing NonBabelNodes: {e}");
+                failed_files.push(PathBuf::from("NonBabelNodes"));
+            }
+        }
+    }
 
-        match serde_json::to_string(&converted_node) {
-            Ok(json_string) => { nbn_writer.write_line(&json_string).expect("Error writing line"); },
-            Err(e) => { eprintln!("Error converting a non babel node to a json line: {e}"); }
+    if args.prune_orphans && args.dry_run {
+        progress.info(format_args!("Not pruning orphaned outputs in a dry run"));
+    } else if args.prune_orphans && !failed_files.is_empty() {
+        progress.info(format_args!(
+            "Not pruning orphaned outputs since some outputs couldn't be written"
+        ));
+    } else if args.prune_orphans && !skipped_files.into_inner().unwrap().is_empty() {
+        progress.info(format_args!("Not pruning orphaned outputs since some inputs were skipped"));
+    } else if args.prune_orphans {
+        let side_outputs: Vec<&Utf8Path> = [
+            &args.leftovers_as_ids,
+            &args.report_json,
+            &args.names_index,
+            &args.debug_drop_reasons,
+            &args.failed_lines_output,
+        ]
+        .into_iter()
+        .filter_map(|path| path.as_deref())
+        .collect();
+        match output::prune_orphans(output_directory, &written_paths, &input_names, &side_outputs) {
+            Ok(pruned) => {
+                for path in &pruned {
+                    progress.info(format_args!("Pruned orphaned output {:?}", path));
+                }
+                progress.info(format_args!("Pruned {} orphaned outputs", pruned.len()));
+            }
+            Err(e) => {
+                eprintln!("Error pruning orphaned outputs: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(merge_directory) = args.merge_into.as_ref().filter(|_| !args.dry_run) {
+        let t0 = Instant::now();
+        match merge::merge_into(output_directory, merge_directory, args.on_conflict, separator) {
+            Ok(report) => {
+                progress.log(format_args!("Merging took {:.2?}", t0.elapsed()));
+                progress.info(format_args!(
+                    "Merged into {merge_directory}: {} nodes added, {} replaced, {} skipped as \
+                     already present",
+                    report.num_added, report.num_replaced, report.num_skipped
+                ));
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(stats) = prefix_stats.filter(|_| !args.quiet) {
+        progress.info(format_args!("Keep rate by curie prefix:"));
+        stats.print();
+    }
+
+    if let Some(types) = distinct_types {
+        progress.info(format_args!("{} distinct types in the kept nodes", types.len()));
+        if args.list_types {
+            let mut types: Vec<String> = types.into_iter().collect();
+            types.sort_unstable();
+            for node_type in types {
+                progress.info(format_args!("  {node_type}"));
+            }
+        }
+    }
+
+    if let Some(mode) = untracked_mode.filter(|_| args.report_coverage) {
+        progress.info(format_args!(
+            "Filter coverage isn't reported in {mode} since leftovers can't be tracked"
+        ));
+    } else if args.report_coverage {
+        match stats::coverage_percent(filter_set_size, num_leftovers) {
+            Some(percent) => {
+                progress.info(format_args!(
+                    "Filter coverage: {percent:.2}% of filter nodes were found in Babel"
+                ))
+            }
+            None => progress.info(format_args!("Filter coverage: the filter set is empty")),
+        }
+    }
+
+    let mut exit_code = ExitCode::SUCCESS;
+    if !failed_files.is_empty() {
+        failed_files.sort_unstable();
+        eprintln!("{} outputs couldn't be written: {:?}", failed_files.len(), failed_files);
+        exit_code = ExitCode::FAILURE;
+    }
+    if leftover_ids_failed {
+        exit_code = ExitCode::FAILURE;
+    }
+    if let Some(expected_counts) = expected_counts {
+        let discrepancies = expected_counts.discrepancies(&file_counts);
+        for discrepancy in &discrepancies {
+            eprintln!("Unexpected node count for {discrepancy}");
+        }
+        if !discrepancies.is_empty() && args.fail_on_error {
+            exit_code = ExitCode::FAILURE;
         }
     }
 
-    println!("Wrote an extra {filter_set_size} nodes to NonBabelNodes.txt.gz");
+    // a large share of leftovers usually means the filter file is stale
+    if let Some(max_fraction) = args.max_nonbabel_fraction {
+        let leftover_percent = stats::coverage_percent(filter_set_size, num_leftovers)
+            .map_or(0.0, |percent| 100.0 - percent);
+        if let Some(mode) = untracked_mode {
+            eprintln!("--max-nonbabel-fraction isn't checked in {mode}");
+        } else if leftover_percent > max_fraction {
+            eprintln!(
+                "{leftover_percent:.2}% of filter nodes weren't found in Babel, more than the \
+                allowed {max_fraction}%"
+            );
+            exit_code = ExitCode::FAILURE;
+        }
+    }
 
+    if let Some(Err(e)) = failed_lines.map(FailedLines::finish) {
+        eprintln!("Error writing the failed lines file: {e}");
+        exit_code = ExitCode::FAILURE;
+    }
+    if let Some(Err(e)) = drop_log.map(|drop_log| drop_log.into_inner().unwrap().finish()) {
+        eprintln!("Error writing the drop reasons file: {e}");
+        exit_code = ExitCode::FAILURE;
+    }
     let duration = start.elapsed();
-    println!("Program took {:.2?}", duration);
+    if args.report_throughput {
+        let num_nodes = file_counts.values().sum();
+        progress.info(format_args!(
+            "Read {:.2} MB and {num_nodes} Babel nodes, {}",
+            bytes_read as f64 / 1e6,
+            stats::throughput(bytes_read, num_nodes, duration)
+        ));
+    }
+    if let Some(ref report_path) = args.report_json {
+        file_summaries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let report = RunReport {
+            filter_nodes: filter_counts.map(|(num_read, _)| num_read),
+            num_excluded: filter_counts.map(|(_, num_excluded)| num_excluded),
+            files: file_summaries.iter().map(FileRunReport::from).collect(),
+            non_babel_nodes: num_non_babel_nodes,
+            duration_ms: duration.as_millis(),
+        };
+        if let Err(e) = report.write(report_path) {
+            eprintln!("Error writing the JSON report: {e}");
+            exit_code = ExitCode::FAILURE;
+        }
+    }
+    progress.info(format_args!("Program took {:.2?}", duration));
+
+    exit_code
+}
+
+/// Lists the Babel files to filter, each read from its fragments (usually just itself), or stdin
+///
+/// Returns `Err` with a message if the Babel directory can't be read or a fragment to concatenate
+/// isn't in it
+fn list_babel_inputs(
+    args: &Config,
+    progress: Progress,
+) -> Result<Vec<(PathBuf, Vec<PathBuf>)>, String> {
+    let babel_directory = &args.babel_directory;
+    let mut babel_files = Vec::new();
+    if !args.stdin {
+        let entries = fs::read_dir(babel_directory)
+            .map_err(|e| format!("Error reading the babel directory: {e}"))?;
+        for babel_file in entries {
+            match babel_file {
+                Ok(f) if f.file_name() == output::FORMATS_FILE => (),
+                Ok(f) if f.path().is_file() => babel_files.push(f.path()),
+                Ok(_) => (),
+                Err(error) => eprintln!("Error opening file in babel directory: {error}"),
+            }
+        }
+    }
+
+    // the listed fragments are filtered as one file, named after the first fragment without its
+    // part number if it has one
+    let mut babel_inputs: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    if let Some(fragment_names) = args.concat_inputs.as_deref().filter(|names| !names.is_empty()) {
+        let fragments: Vec<PathBuf> = fragment_names
+            .iter()
+            .map(|name| babel_directory.join(name).into_std_path_buf())
+            .collect();
+        if let Some(missing) = fragments.iter().find(|fragment| !babel_files.contains(fragment)) {
+            return Err(format!(
+                "The fragment {:?} to concatenate isn't in the Babel directory",
+                missing
+            ));
+        }
+        babel_files.retain(|babel_file| !fragments.contains(babel_file));
+        let first_name = fragment_names[0].as_str();
+        let name = chunked::unpart_name(first_name).unwrap_or_else(|| String::from(first_name));
+        babel_inputs.push((babel_directory.join(name).into_std_path_buf(), fragments));
+    }
+    // listed fragments are filtered whatever their name
+    let num_babel_files = babel_files.len();
+    babel_files.retain(|babel_file| {
+        let name = babel_file.file_name().unwrap_or_default().to_string_lossy();
+        match args.include_glob {
+            Some(ref globs) => globs.iter().any(|glob| filters::matches_glob(&name, glob)),
+            // hidden files such as .DS_Store are never Babel files
+            None => !name.starts_with('.'),
+        }
+    });
+    if babel_files.len() < num_babel_files {
+        progress.info(format_args!(
+            "Skipped {} files in the Babel directory that aren't Babel files",
+            num_babel_files - babel_files.len()
+        ));
+    }
+    babel_inputs.extend(
+        babel_files
+            .into_iter()
+            .map(|babel_file| (babel_file.clone(), vec![babel_file])),
+    );
+    // stdin is the only input, read as a file of that name with no fragments to open
+    if args.stdin {
+        babel_inputs.push((PathBuf::from(STDIN_NAME), Vec::new()));
+    }
+    Ok(babel_inputs)
+}
+
+/// Checks for options that can't be used together or values out of range, before anything is read
+///
+/// Returns `Err` with a message for the first problem found
+fn check_args(args: &Config) -> Result<(), String> {
+    if !args.stdin && !args.babel_directory.is_dir() {
+        return Err(String::from(
+            "The path provided to the Babel directory isn't a directory or doesn't exist",
+        ));
+    }
+    if args.filter_file.is_empty() {
+        return Err(String::from("No filter file was provided"));
+    }
+    if let Some(filter_file) = args.filter_file.iter().find(|filter_file| !filter_file.is_file()) {
+        return Err(format!(
+            "The path {filter_file} provided as a filter file isn't a file or doesn't exist"
+        ));
+    }
+    if !args.stdout && !args.output_directory.is_dir() {
+        return Err(String::from(
+            "The path provided to the output directory isn't a directory or doesn't exist",
+        ));
+    }
+    let stdin_conflict = [
+        ("--two-pass", args.two_pass),
+        ("--concat-inputs", args.concat_inputs.is_some()),
+    ]
+    .into_iter()
+    .find(|&(_, set)| set && args.stdin);
+    if let Some((flag, _)) = stdin_conflict {
+        return Err(format!("{flag} can't be used when reading Babel records from stdin"));
+    }
+    let stdout_conflict = [
+        ("--chunk-lines", args.chunk_lines.is_some()),
+        ("--chunk-bytes", args.chunk_bytes.is_some()),
+        ("--preallocate", args.preallocate.is_some()),
+        ("--output-subdir-per-file", args.output_subdir_per_file),
+        ("--meta-sidecar", args.meta_sidecar),
+        ("--prune-orphans", args.prune_orphans),
+        ("--merge-into", args.merge_into.is_some()),
+        ("--summary-table", args.summary_table),
+        ("--per-prefix-stats", args.per_prefix_stats),
+    ]
+    .into_iter()
+    .find(|&(_, set)| set && args.stdout);
+    if let Some((flag, _)) = stdout_conflict {
+        return Err(format!("{flag} can't be used when writing the output to stdout"));
+    }
+    // the listing of orphans doesn't look into the per-file subdirectories
+    if args.prune_orphans && args.output_subdir_per_file {
+        return Err(String::from("--prune-orphans can't be used with --output-subdir-per-file"));
+    }
+    if args.filter_tsv && args.filter_tolerant_parse {
+        return Err(String::from("--filter-tsv can't be used with --filter-tolerant-parse"));
+    }
+    if args.group_by_prefix && args.keep_largest_per_prefix {
+        return Err(String::from("--group-by-prefix can't be used with --keep-largest-per-prefix"));
+    }
+    if args.merge_into.as_ref().is_some_and(|merge_directory| !merge_directory.is_dir()) {
+        return Err(String::from(
+            "The path provided to merge into isn't a directory or doesn't exist",
+        ));
+    }
+
+    if args.bloom_filter && !(args.bloom_fp_rate > 0.0 && args.bloom_fp_rate < 1.0) {
+        return Err(String::from("The Bloom filter false-positive rate must be between 0 and 1"));
+    }
+    if let Some(level) = args.compression_level.filter(|&level| level > 9) {
+        return Err(format!("The gzip compression level must be between 0 and 9, but was {level}"));
+    }
+    if args.preserve_key_order && !cfg!(feature = "preserve-key-order") {
+        return Err(String::from(
+            "--preserve-key-order needs a build with the preserve-key-order feature enabled",
+        ));
+    }
+    Ok(())
+}
+
+/// Per-run state shared by every Babel file being filtered, possibly from several threads
+struct FileContext<'a> {
+    args: &'a Config,
+    progress: Progress,
+    bloom_filter: Option<&'a BloomFilter>,
+    /// The filter file ids, with `ids_only`
+    id_set: Option<&'a AHashSet<String>>,
+    curie_map: Option<&'a CurieMap>,
+    /// Drops the Babel curies it matches, with `--exclude-curie-regex`
+    curie_pattern: Option<&'a Regex>,
+    /// The number of lines in each Babel file, by file name, for progress reports
+    line_counts: Option<&'a AHashMap<String, usize>>,
+    baseline_curies: Option<&'a AHashSet<String>>,
+    preferred_names: Option<&'a AHashSet<String>>,
+    excluded_curies: Option<&'a AHashSet<String>>,
+    /// Curies written by any file so far, if deduplicating across files
+    written_curies: Option<&'a Mutex<AHashSet<String>>>,
+    /// `curie\treason` lines for each node that isn't kept
+    drop_log: Option<&'a Mutex<Writer>>,
+    failed_lines: Option<&'a FailedLines>,
+    /// Filter set nodes are removed as they're matched, so what's left at the end goes to
+    /// NonBabelNodes
+    filter_set: &'a ShardedFilterSet,
+    /// Leaves the filter set as it is while files are filtered, with each file returning the ids
+    /// it matched to be removed afterwards, with `--per-file-matches`. Files can then never see
+    /// each other's matches, so each keeps every node it matches however the files are scheduled.
+    per_file_matches: bool,
+    where_pointers: &'a [WherePointer],
+    numeric_ranges: &'a [CurieRange],
+    /// Transforms used to rewrite mapped curies; other nodes go through the serializer
+    transforms: &'a Transforms,
+    serializer: &'a dyn OutputSerializer,
+    provenance_header: Option<&'a str>,
+    /// Only set if a metadata sidecar should be written
+    filter_file_crc32: Option<&'a str>,
+    /// Per-file output formats from `formats.toml`, overriding `output_format`
+    output_formats: Option<&'a AHashMap<String, OutputFormat>>,
+}
 
-    ExitCode::SUCCESS
+impl<'a> FileContext<'a> {
+    /// A context that filters against `filter_set` with none of the options `run()` loads or
+    /// compiles up front, writing kept records verbatim and printing no progress
+    fn new(
+        args: &'a Config,
+        filter_set: &'a ShardedFilterSet,
+        transforms: &'a Transforms,
+    ) -> FileContext<'a> {
+        FileContext {
+            args,
+            progress: Progress::new(false),
+            bloom_filter: None,
+            id_set: None,
+            curie_map: None,
+            curie_pattern: None,
+            line_counts: None,
+            baseline_curies: None,
+            preferred_names: None,
+            excluded_curies: None,
+            written_curies: None,
+            drop_log: None,
+            failed_lines: None,
+            filter_set,
+            per_file_matches: args.per_file_matches,
+            where_pointers: &[],
+            numeric_ranges: &[],
+            transforms,
+            serializer: &output::VerbatimSerializer,
+            provenance_header: None,
+            filter_file_crc32: None,
+            output_formats: None,
+        }
+    }
+}
+
+/// What filtering a single Babel file found
+struct FileReport {
+    input_name: String,
+    num_nodes: usize,
+    num_kept: usize,
+    duration: Duration,
+    /// Bytes of Babel records read, after decompression
+    bytes_read: u64,
+    /// Nodes dropped because an earlier file already wrote their curie (with `--global-dedup`)
+    num_duplicates: usize,
+    /// Nodes dropped for having no types (with `--require-category`)
+    num_missing_category: usize,
+    /// Nodes dropped because their curie matches `--exclude-curie-regex`
+    num_curie_regex_excluded: usize,
+    /// Lines that only parsed once repaired (with `--repair-json`)
+    num_repaired: usize,
+    prefix_stats: Option<PrefixStats>,
+    /// Every type of the kept nodes (with `--count-distinct-types`)
+    distinct_types: Option<AHashSet<String>>,
+    /// Every file the output was written to, including chunk parts
+    output_paths: Vec<PathBuf>,
+    /// `(lowercased name, curie)` pairs of the kept nodes, if building a names index
+    names_index: AHashSet<(String, String)>,
+    /// Filter ids of the matched nodes, with `FileContext::per_file_matches`
+    matched_ids: AHashSet<String>,
+}
+
+/// The curies written to one output, to find any that are written more than once
+#[derive(Default)]
+struct FileCuries {
+    curies: AHashSet<String>,
+    /// Lines whose curie was already written
+    num_duplicates: usize,
+    /// The first few duplicated curies
+    examples: Vec<String>,
+}
+
+impl FileCuries {
+    fn record(&mut self, curie: &str) {
+        if self.curies.insert(String::from(curie)) {
+            return;
+        }
+        self.num_duplicates += 1;
+        if self.examples.len() < 5 && !self.examples.iter().any(|example| example == curie) {
+            self.examples.push(String::from(curie));
+        }
+    }
 }
 
-fn has_excluded_category<'a, I>(set: I, exclude_set: &Vec<String>) -> bool
+/// Filters one Babel file into the output directory, removing the nodes it matches from the
+/// filter set. The file named `babel_path` is read from `fragments`, one after another, which is
+/// usually just `babel_path` itself. Returns `Ok(None)`, after printing a warning, if the file
+/// can't be opened (e.g. it was deleted after the Babel directory was listed) or its output can't
+/// be created.
+///
+/// Returns `Err` if writing the output fails partway, leaving it incomplete
+fn filter_babel_file<P>(
+    context: &FileContext,
+    babel_path: &Path,
+    fragments: &[P],
+) -> io::Result<Option<FileReport>>
 where
-    I: IntoIterator<Item = &'a String>,
+    P: AsRef<Path>,
 {
-    if exclude_set.is_empty() {
-        return false;
+    let args = context.args;
+    let separator = args.record_separator.unwrap_or(b'\n');
+    let t0 = Instant::now();
+
+    let mut output_directory = args.output_directory.as_std_path().to_path_buf();
+    if args.output_subdir_per_file {
+        output_directory.push(babel_path.file_stem().unwrap_or_default());
+        // a dry run doesn't create anything
+        if !args.dry_run {
+            if let Err(e) = fs::create_dir_all(&output_directory) {
+                eprintln!(
+                    "Warning: skipping {:?}, its output directory {:?} couldn't be created: {e}",
+                    babel_path, output_directory
+                );
+                return Ok(None);
+            }
+        }
+    }
+    let mut output_file_path = Path::join(
+        &output_directory,
+        babel_path.file_name().unwrap(), // should be safe to unwrap as only files are filtered
+    );
+
+    // force the output's compression if output_format arg is set, or formats.toml sets it for
+    // this file
+    let file_name = babel_path.file_name().unwrap_or_default().to_string_lossy();
+    let format = context
+        .output_formats
+        .and_then(|formats| formats.get(file_name.as_ref()).copied())
+        .or(args.output_format);
+    if let Some(format) = format {
+        output_file_path = output::with_format_extension(&output_file_path, format);
+    }
+
+    // the file may have been removed since the Babel directory was listed
+    let reader = if args.stdin {
+        Reader::from_stdin(BUF_CAPACITY)
+    } else {
+        Reader::concat(
+            fragments,
+            BUF_CAPACITY,
+            args.decode_buffer,
+            args.read_ahead,
+            args.decode_threads,
+        )
+    };
+    let reader: Reader = match reader {
+        Ok(reader) => reader.with_separator(separator),
+        Err(e) => {
+            eprintln!("Warning: skipping {:?}, it couldn't be opened: {e}", babel_path);
+            return Ok(None);
+        }
+    };
+
+    // count-only pass so the kept percentage's denominator excludes malformed lines
+    let num_valid = if args.two_pass {
+        match count_valid_nodes(fragments, separator, args.repair_json) {
+            Ok(num_valid) => Some(num_valid),
+            Err(e) => {
+                eprintln!("Warning: skipping {:?}, it couldn't be read: {e}", babel_path);
+                return Ok(None);
+            }
+        }
+    } else {
+        None
+    };
+
+    // a dry run only matches, so it never creates the output
+    let mut writer = if args.dry_run {
+        None
+    } else if args.stdout {
+        Some(ChunkedWriter::to_stdout(BUF_CAPACITY).with_separator(separator))
+    } else {
+        let levels = compression_levels(args);
+        let limits = ChunkLimits {
+            lines: args.chunk_lines,
+            bytes: args.chunk_bytes,
+        };
+        let writer = ChunkedWriter::with_levels(&output_file_path, limits, BUF_CAPACITY, levels)
+            .and_then(|writer| writer.with_preallocation(args.preallocate));
+        match writer {
+            Ok(writer) => Some(writer.with_separator(separator)),
+            Err(e) => {
+                eprintln!(
+                    "Warning: skipping {:?}, its output {:?} couldn't be created: {e}",
+                    babel_path, output_file_path
+                );
+                return Ok(None);
+            }
+        }
+    };
+    // tabular outputs start with their column header instead of the JSON provenance header
+    let tabular = format.and_then(|format| {
+        TabularSerializer::for_format(format, &args.list_delimiter, context.transforms)
+    });
+    if let Some(ref mut writer) = writer {
+        if let Some(ref tabular) = tabular {
+            writer.set_header(tabular.header()?)?;
+        } else if let Some(header) = context.provenance_header {
+            writer.set_header(String::from(header))?;
+        }
+    }
+
+    // the summary table replaces the per-file lines
+    let file_progress = context.progress.with_quiet(args.quiet || args.summary_table);
+    let mut lines = reader.lines();
+    let filtered = match writer {
+        Some(ref mut writer) => filter_lines(
+            context,
+            babel_path,
+            &mut lines,
+            tabular.as_ref(),
+            file_progress,
+            |line| writer.write_line_bytes(line).map(|_| ()),
+        ),
+        None => filter_lines(
+            context,
+            babel_path,
+            &mut lines,
+            tabular.as_ref(),
+            file_progress,
+            |_| Ok(()),
+        ),
+    }?;
+    let FilteredLines {
+        num_nodes,
+        num_kept,
+        num_duplicates,
+        num_missing_category,
+        num_curie_regex_excluded,
+        num_repaired,
+        num_repeated,
+        prefix_stats,
+        distinct_types,
+        names_index,
+        matched_ids,
+    } = filtered;
+    let bytes_read = lines.bytes_read();
+
+    let (total, total_label) = match num_valid {
+        Some(num_valid) => (num_valid, "valid nodes"),
+        None => (num_nodes, "nodes"),
+    };
+    let elapsed = t0.elapsed();
+    let throughput = if args.report_throughput {
+        format!(", {}", stats::throughput(bytes_read, num_nodes, elapsed))
+    } else {
+        String::new()
+    };
+    file_progress.log(format_args!(
+        "{} {:?} took {:.2?}, kept {}/{} {} ({:.2}%){}",
+        if args.dry_run { "Matching" } else { "Writing" },
+        output_file_path.file_name().unwrap_or_default(),
+        elapsed,
+        num_kept,
+        total,
+        total_label,
+        stats::kept_percent(num_kept, total),
+        throughput
+    ));
+    if args.report_duplicates || args.keep_duplicates {
+        file_progress.log(format_args!(
+            "{} {num_repeated} lines of {:?} repeating a curie matched earlier in the file",
+            if args.keep_duplicates { "Kept" } else { "Skipped" },
+            babel_path.file_name().unwrap_or_default()
+        ));
+    }
+    let chunked = args.chunk_lines.is_some() || args.chunk_bytes.is_some();
+    if let Some(writer) = writer.as_ref().filter(|_| chunked) {
+        file_progress.log(format_args!(
+            "Split {:?} into {} parts",
+            output_file_path.file_name().unwrap_or_default(),
+            writer.parts()
+        ));
+    }
+
+    let output_paths = writer.as_ref().map_or_else(Vec::new, ChunkedWriter::paths);
+    // finishing the writer flushes it, so the mtime isn't bumped again by a later write
+    if let Some(writer) = writer {
+        writer.finish()?;
+    }
+    // a concatenated file takes its first fragment's mtime
+    if let Some(mtime_source) = fragments.first().filter(|_| args.preserve_mtime) {
+        for output_path in &output_paths {
+            if let Err(e) = provenance::copy_mtime(mtime_source, output_path) {
+                eprintln!("Error setting the modification time of {:?}: {e}", output_path);
+            }
+        }
+    }
+    let input_name = babel_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+
+    if let Some(filter_file_crc32) = context.filter_file_crc32 {
+        let meta = OutputMeta {
+            input_file: &input_name,
+            nodes_read: num_nodes,
+            nodes_kept: num_kept,
+            duration_ms: t0.elapsed().as_millis(),
+            filter_file: &args.filter_file,
+            filter_file_crc32,
+        };
+        if let Err(e) = meta.write(&output_file_path) {
+            eprintln!("Error writing metadata sidecar for {:?}: {e}", output_file_path);
+        }
     }
-    for cat in set {
-        for ex_cat in exclude_set.into_iter() {
-            if cat == ex_cat {
-                return true;
+
+    Ok(Some(FileReport {
+        input_name,
+        num_nodes,
+        num_kept,
+        duration: elapsed,
+        bytes_read,
+        num_duplicates,
+        num_missing_category,
+        num_curie_regex_excluded,
+        num_repaired,
+        prefix_stats,
+        distinct_types,
+        output_paths,
+        names_index,
+        matched_ids,
+    }))
+}
+
+/// What `filter_lines` found in the records it read
+struct FilteredLines {
+    num_nodes: usize,
+    num_kept: usize,
+    num_duplicates: usize,
+    num_missing_category: usize,
+    num_curie_regex_excluded: usize,
+    num_repaired: usize,
+    /// Lines repeating a curie matched earlier in the file
+    num_repeated: usize,
+    prefix_stats: Option<PrefixStats>,
+    distinct_types: Option<AHashSet<String>>,
+    names_index: AHashSet<(String, String)>,
+    matched_ids: AHashSet<String>,
+}
+
+/// A kept line on its way to the output, which `keep_largest_per_prefix` and `group_by_prefix`
+/// hold back until the end of the file
+#[derive(Default)]
+struct HeldLine {
+    line: Vec<u8>,
+    /// The names index pairs of the line's node
+    index_pairs: Vec<(String, String)>,
+    /// The node's own curie, for `global_dedup`
+    curie: String,
+    /// The curie the line is written with, for `assert_no_duplicates`
+    output_curie: String,
+}
+
+/// Filters the uncompressed Babel records of `input` against `filter_nodes`, writing each kept
+/// record to `output` followed by its separator. The filter nodes are admitted as if they had been
+/// read from a filter file, but the records are filtered without the options `run()` loads or
+/// compiles up front, like curie maps, blocklists, baselines and patterns, and nothing else is
+/// written: no header, leftovers or side outputs.
+///
+/// Returns the number of kept nodes and the number of nodes read, or `Err` if reading or writing
+/// fails, or if a `category_regex` or `exclude_curie_regex` pattern doesn't compile
+pub fn filter_records<I>(
+    args: &Config,
+    filter_nodes: I,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<(usize, usize)>
+where
+    I: IntoIterator<Item = NodeListJson>,
+{
+    let filter_set = filter_set::admit_filter_nodes(args, filter_nodes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let filter_set = filter_set::shard_filter_set(args, filter_set);
+    let transforms = Transforms::default();
+    let context = FileContext::new(args, &filter_set, &transforms);
+    // split like `Lines` splits a file
+    let separator = args.record_separator.unwrap_or(b'\n');
+    let mut records = input.split(separator).map(|record| {
+        let mut record = record?;
+        if separator == b'\n' && record.last() == Some(&b'\r') {
+            record.pop();
+        }
+        String::from_utf8(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    });
+    let filtered = filter_lines(
+        &context,
+        Path::new("input"),
+        &mut records,
+        None,
+        Progress::new(false),
+        |line| {
+            output.write_all(line)?;
+            output.write_all(&[separator])
+        },
+    )?;
+    Ok((filtered.num_kept, filtered.num_nodes))
+}
+
+/// Filters the Babel records of `lines` against the filter set, passing each kept record to
+/// `write_line` without its separator. This is the part of `filter_babel_file` that doesn't touch
+/// the filesystem, so it can be fed records from memory. `babel_path` only names the records in
+/// messages, and `tabular` is the tabular serializer if the output is tabular.
+///
+/// Returns `Err` if `write_line` fails, if `assert_no_duplicates` finds a repeated curie, or if
+/// a node doesn't read back as itself with `verify_roundtrip`
+fn filter_lines<I, F>(
+    context: &FileContext,
+    babel_path: &Path,
+    lines: &mut I,
+    tabular: Option<&TabularSerializer>,
+    file_progress: Progress,
+    mut write_line: F,
+) -> io::Result<FilteredLines>
+where
+    I: Iterator<Item = io::Result<String>>,
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    let args = context.args;
+    let file_name = babel_path.file_name().unwrap_or_default().to_string_lossy();
+    let serializer: &dyn OutputSerializer = match tabular {
+        Some(tabular) => tabular,
+        None => context.serializer,
+    };
+    let mut num_nodes: usize = 0;
+    let mut num_kept: usize = 0;
+    let mut num_duplicates: usize = 0;
+    let mut num_missing_category: usize = 0;
+    let mut num_curie_regex_excluded: usize = 0;
+    let mut num_repaired: usize = 0;
+
+    let mut largest_per_prefix = args.keep_largest_per_prefix.then(LargestPerPrefix::default);
+    let mut grouped_by_prefix = args.group_by_prefix.then(GroupedByPrefix::default);
+    let mut prefix_stats = args.per_prefix_stats.then(PrefixStats::default);
+    let mut distinct_types = args.count_distinct_types.then(AHashSet::<String>::new);
+    let mut names_index = AHashSet::new();
+    let mut file_curies = args.assert_no_duplicates.then(FileCuries::default);
+    // curies matched so far in this file, to tell a repeated curie from one that isn't wanted
+    let mut matched_curies =
+        (args.report_duplicates || args.keep_duplicates).then(AHashSet::<String>::new);
+    let mut num_repeated = 0;
+    // filter ids this file matched, if they're only removed from the filter set once every file
+    // is done
+    let mut matched_ids = context.per_file_matches.then(AHashSet::<String>::new);
+
+    let comment_prefixes = args.comment_prefix.as_deref().unwrap_or_default();
+    let required_types = args.require_all_types.as_deref().unwrap_or_default();
+    let mut errors = ErrorLog::new(args.max_errors_per_file);
+    // a held line can still be dropped, so its curie only counts as written once it is. Files
+    // filtered at once can both get this far with a curie, so the first to claim it writes it.
+    // Returns whether the line was written.
+    let mut write_held = |held: HeldLine| -> io::Result<bool> {
+        if let Some(written) = context.written_curies {
+            if !written.lock().unwrap().insert(held.curie) {
+                return Ok(false);
             }
         }
+        write_line(&held.line)?;
+        if let Some(ref mut file_curies) = file_curies {
+            file_curies.record(&held.output_curie);
+        }
+        names_index.extend(held.index_pairs);
+        Ok(true)
+    };
+    let mut line_progress = file_progress.lines(
+        file_name.to_string(),
+        args.progress_every,
+        // reports from files filtered at once would overwrite each other
+        args.concurrency <= 1,
+    )
+    .with_total(context.line_counts.and_then(|counts| counts.get(file_name.as_ref())).copied());
+    for (line_index, line) in lines.by_ref().enumerate() {
+        line_progress.tick(line_index + 1);
+        if matches!(line, Ok(ref node_json) if filters::is_comment(node_json, comment_prefixes)) {
+            continue;
+        }
+        if let Ok(mut node_json) = line {
+            num_nodes += 1;
+            match parse_babel_line(&node_json, args.repair_json) {
+                Ok((node, repaired)) => {
+                    if let Some(repaired) = repaired {
+                        num_repaired += 1;
+                        node_json = repaired;
+                    }
+                    let mapped_curie = context.curie_map.and_then(|map| map.get(&node.curie));
+                    let lookup_curie = mapped_curie.unwrap_or(&node.curie);
+                    let lowercase_curie;
+                    let member_key = if args.ignore_curie_case {
+                        lowercase_curie = lookup_curie.to_lowercase();
+                        &lowercase_curie
+                    } else {
+                        lookup_curie
+                    };
+                    // checked before the lookup so a dropped curie's filter node is a leftover
+                    let curie_excluded = context
+                        .curie_pattern
+                        .is_some_and(|pattern| pattern.is_match(&node.curie));
+                    // in Bloom filter and ids-only mode the map only holds seeded curies
+                    let in_id_filter = !curie_excluded
+                        && (context.bloom_filter.is_some_and(|bloom| bloom.contains(member_key))
+                            || context.id_set.is_some_and(|ids| ids.contains(member_key)));
+                    // a found node is claimed whether or not a later check drops it, so a dropped
+                    // node never comes back as a leftover
+                    let filter_id = (!curie_excluded && !in_id_filter)
+                        .then(|| context.filter_set.find(member_key))
+                        .flatten();
+                    let filter_id = match matched_ids.as_mut() {
+                        Some(ids) => filter_id.filter(|id| ids.insert(id.clone())),
+                        // only one of the files filtered at once removes it
+                        None => filter_id.filter(|id| context.filter_set.remove_id(id).is_some()),
+                    };
+                    let matched = in_id_filter || filter_id.is_some();
+                    let repeated = !matched
+                        && matched_curies
+                            .as_ref()
+                            .is_some_and(|curies| curies.contains(member_key));
+                    if repeated {
+                        num_repeated += 1;
+                    }
+                    let matched = matched || (repeated && args.keep_duplicates);
+                    if let Some(curies) = matched_curies.as_mut().filter(|_| matched) {
+                        curies.insert(String::from(member_key));
+                    }
+                    let drop_reason = if curie_excluded {
+                        num_curie_regex_excluded += 1;
+                        Some(DropReason::CurieRegex)
+                    } else if repeated && !matched {
+                        Some(DropReason::RepeatedCurie)
+                    } else if !matched {
+                        Some(DropReason::NotInFilterSet)
+                    } else if context.excluded_curies.is_some_and(|excluded| {
+                        excluded.contains(&node.curie) || excluded.contains(lookup_curie)
+                    }) {
+                        // Bloom filters and mapped curies can still match a blocklisted id
+                        Some(DropReason::Blocklisted)
+                    } else if context
+                        .baseline_curies
+                        .is_some_and(|baseline| baseline.contains(&node.curie))
+                    {
+                        Some(DropReason::InBaseline)
+                    } else if !filters::has_all_types(&node.types, required_types) {
+                        Some(DropReason::MissingRequiredType)
+                    } else if args.require_category && node.types.is_empty() {
+                        num_missing_category += 1;
+                        Some(DropReason::MissingCategory)
+                    } else if args.taxa.as_deref().is_some_and(|taxa| {
+                        !filters::has_wanted_taxon(&node.taxa, taxa, args.keep_untaxed)
+                    }) {
+                        Some(DropReason::Taxon)
+                    } else if !filters::in_numeric_range(
+                        &node.curie,
+                        context.numeric_ranges,
+                        args.keep_non_numeric,
+                    ) {
+                        Some(DropReason::NumericRange)
+                    } else if context.preferred_names.is_some_and(|names| {
+                        !node
+                            .preferred_name
+                            .as_ref()
+                            .is_some_and(|name| match args.normalize_unicode {
+                                Some(form) => names.contains(&transform::normalize(name, form)),
+                                None => names.contains(name),
+                            })
+                    }) {
+                        Some(DropReason::PreferredName)
+                    } else if !context.where_pointers.is_empty()
+                        && !filters::matches_all(context.where_pointers, &node_json)
+                    {
+                        Some(DropReason::WherePointer)
+                    } else if !filters::name_length_in_bounds(
+                        node.shortest_name_length
+                            .or_else(|| transform::shortest_name_length(&node.names)),
+                        args.min_name_length,
+                        args.max_name_length,
+                    ) {
+                        Some(DropReason::NameLength)
+                    } else if context
+                        .written_curies
+                        .is_some_and(|written| written.lock().unwrap().contains(&node.curie))
+                    {
+                        num_duplicates += 1;
+                        Some(DropReason::DuplicateCurie)
+                    } else {
+                        None
+                    };
+                    // the drop log is only diagnostic, so failing to write it doesn't fail the file
+                    if let (Some(reason), Some(drop_log)) = (drop_reason, context.drop_log) {
+                        let logged = drop_log
+                            .lock()
+                            .unwrap()
+                            .write_line(&format!("{}\t{}", node.curie, reason.as_str()))
+                            .map(|_| ());
+                        if let Err(e) = logged {
+                            errors.log(format_args!("Error writing the drop reasons file: {e}"));
+                        }
+                    }
+                    let kept = drop_reason.is_none();
+                    if let Some(types) = distinct_types.as_mut().filter(|_| kept) {
+                        for node_type in &node.types {
+                            if !types.contains(node_type) {
+                                types.insert(node_type.clone());
+                            }
+                        }
+                    }
+                    if kept {
+                        let output_line = match mapped_curie {
+                            Some(curie) if args.rewrite_mapped_curie => {
+                                serializer.serialize_as(&node, &node_json, curie)
+                            }
+                            _ => serializer.serialize(&node, &node_json),
+                        };
+                        let output_curie = match mapped_curie {
+                            Some(curie) if args.rewrite_mapped_curie => curie,
+                            _ => &node.curie,
+                        };
+                        match output_line {
+                            Ok(output_line) => {
+                                let index_pairs = if args.names_index.is_some() {
+                                    output::names_index_pairs(&node)
+                                } else {
+                                    Vec::new()
+                                };
+                                let held = HeldLine {
+                                    line: output_line,
+                                    index_pairs,
+                                    curie: node.curie.clone(),
+                                    output_curie: String::from(output_curie),
+                                };
+                                match (&mut largest_per_prefix, &mut grouped_by_prefix) {
+                                    (Some(largest), _) => {
+                                        largest.offer(&node.curie, node.names.len(), held)
+                                    }
+                                    (None, Some(grouped)) => grouped.push(&node.curie, held),
+                                    (None, None) if write_held(held)? => num_kept += 1,
+                                    (None, None) => num_duplicates += 1,
+                                }
+                            }
+                            // a node that doesn't survive its own round trip fails the whole file
+                            Err(e) if context.transforms.verify_roundtrip => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    format!("Error re-serializing {}: {e}", node.curie),
+                                ));
+                            }
+                            Err(e) => errors
+                                .log(format_args!("Error re-serializing {}: {e}", node.curie)),
+                        }
+                    }
+                    if let Some(ref mut stats) = prefix_stats {
+                        stats.record(&node.curie, kept);
+                    }
+                }
+                Err(e) => {
+                    errors.log(format_args!("{e}"));
+                    let recorded = context.failed_lines.map_or(Ok(()), |failed| {
+                        failed.record(&file_name, line_index + 1, &node_json)
+                    });
+                    if let Err(e) = recorded {
+                        errors.log(format_args!("Error writing the failed lines file: {e}"));
+                    }
+                }
+            }
+        } else {
+            errors.log(format_args!(
+                "Something went wrong reading line {} of {:?}",
+                line_index + 1,
+                babel_path
+            ))
+        }
+    }
+    line_progress.finish();
+    errors.finish(format_args!("{:?}", babel_path));
+
+    let largest_lines = largest_per_prefix.into_iter().flat_map(LargestPerPrefix::into_lines);
+    let grouped_lines = grouped_by_prefix.into_iter().flat_map(GroupedByPrefix::into_lines);
+    for held in largest_lines.chain(grouped_lines) {
+        if write_held(held)? {
+            num_kept += 1;
+        } else {
+            num_duplicates += 1;
+        }
+    }
+    if let Some(file_curies) = file_curies.filter(|file_curies| file_curies.num_duplicates > 0) {
+        return Err(io::Error::other(format!(
+            "{} lines repeat a curie already written, e.g. {:?}",
+            file_curies.num_duplicates, file_curies.examples
+        )));
+    }
+
+    Ok(FilteredLines {
+        num_nodes,
+        num_kept,
+        num_duplicates,
+        num_missing_category,
+        num_curie_regex_excluded,
+        num_repaired,
+        num_repeated,
+        prefix_stats,
+        distinct_types,
+        names_index,
+        matched_ids: matched_ids.unwrap_or_default(),
+    })
+}
+
+/// Creates a new file (NonBabelNodes.txt.gz unless `non_babel_nodes_name` is set, with the
+/// extension `output_format` calls for) for all the extra nodes left in the filter set, converted
+/// to Babel nodes, after the provenance header if there is one. Leftovers whose name is outside
+/// the name length bounds are skipped.
+///
+/// With `nonbabel_threads` above 1 the nodes are converted in parallel and held in memory until
+/// they're all converted, then written in order. With `stable_order` they're sorted by curie.
+///
+/// Returns `Err` if the file can't be created or written
+fn write_non_babel_nodes(
+    filter_set: AHashMap<String, NodeListJson>,
+    args: &Config,
+    transforms: &Transforms,
+    provenance_header: Option<&str>,
+) -> io::Result<NonBabelNodesReport> {
+    let filter_set_size = filter_set.len();
+    let tabular = args.output_format.and_then(|format| {
+        TabularSerializer::for_format(format, &args.list_delimiter, transforms)
+    });
+    let non_babel_nodes_name =
+        args.non_babel_nodes_name.as_deref().unwrap_or(NON_BABEL_NODES_NAME);
+    let mut non_babel_nodes_path = args.output_directory.as_std_path().join(non_babel_nodes_name);
+    if let Some(format) = args.output_format {
+        non_babel_nodes_path = output::with_format_extension(&non_babel_nodes_path, format);
+    }
+    let mut nbn_writer =
+        Writer::with_levels(&non_babel_nodes_path, BUF_CAPACITY, compression_levels(args))?
+            .with_separator(args.record_separator.unwrap_or(b'\n'));
+    if let Some(ref tabular) = tabular {
+        nbn_writer.write_line(&tabular.header()?)?;
+    } else if let Some(header) = provenance_header {
+        nbn_writer.write_line(header)?;
+    }
+
+    // the same name length bounds as Babel nodes, applied to the name the node is synthesized with
+    let mut leftovers: Vec<(String, NodeListJson)> = filter_set
+        .into_iter()
+        .filter(|(_, node)| {
+            filters::name_length_in_bounds(
+                (!node.name.is_empty()).then(|| node.name.chars().count()),
+                args.min_name_length,
+                args.max_name_length,
+            )
+        })
+        .collect();
+    let num_outside_name_length = filter_set_size - leftovers.len();
+    if args.stable_order {
+        leftovers.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    // a leftover that couldn't be converted isn't written, so it isn't counted
+    let mut num_written = 0;
+    let mut write_result = |json: serde_json::Result<String>| -> io::Result<()> {
+        match json {
+            Ok(json_string) => {
+                nbn_writer.write_line(&json_string)?;
+                num_written += 1;
+            }
+            Err(e) if transforms.verify_roundtrip => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            Err(e) => { eprintln!("Error converting a non babel node to a json line: {e}"); }
+        }
+        Ok(())
+    };
+    if args.nonbabel_threads > 1 {
+        let threads = args.nonbabel_threads;
+        for json in synthesize_in_parallel(leftovers, transforms, tabular.as_ref(), threads) {
+            write_result(json)?;
+        }
+    } else {
+        for (curie, node_json) in leftovers {
+            write_result(non_babel_node_json(curie, node_json, transforms, tabular.as_ref()))?;
+        }
+    }
+    nbn_writer.finish()?;
+
+    Ok(NonBabelNodesReport {
+        path: non_babel_nodes_path,
+        num_written,
+        num_outside_name_length,
+    })
+}
+
+/// What writing NonBabelNodes found
+struct NonBabelNodesReport {
+    path: PathBuf,
+    num_written: usize,
+    /// Leftovers skipped for a name outside `--min-name-length`/`--max-name-length`
+    num_outside_name_length: usize,
+}
+
+/// Converts a leftover filter node to a serialized Babel node, or to a row if `tabular` is set
+fn non_babel_node_json(
+    curie: String,
+    node_json: NodeListJson,
+    transforms: &Transforms,
+    tabular: Option<&TabularSerializer>,
+) -> serde_json::Result<String> {
+    let NodeListJson { name, category, equivalent_identifiers, other_names, taxa, .. } = node_json;
+
+    let types = category
+        .iter()
+        .map(|s| s.replace("biolink:", ""))
+        .collect::<Vec<String>>();
+
+    // a node without a name, like one from a TSV filter file, has no names rather than an empty one
+    let name = Some(name).filter(|name| !name.is_empty());
+    let names: Vec<String> = name.iter().cloned().chain(other_names).collect();
+    let converted_node = BabelJson {
+        curie,
+        shortest_name_length: transform::shortest_name_length(&names),
+        names,
+        types,
+        preferred_name: name,
+        taxa,
+    };
+    if let Some(tabular) = tabular {
+        // the row applies the transforms itself
+        return tabular
+            .row_as(&converted_node, &converted_node.curie)
+            .map_err(serde_json::Error::io);
+    }
+
+    // Babel nodes have no field for equivalents, so they're kept as an extra one
+    let mut extra = serde_json::Map::new();
+    if let Some(equivalents) = equivalent_identifiers.filter(|ids| !ids.is_empty()) {
+        extra.insert(String::from("equivalent_identifiers"), equivalents.into());
+    }
+    let converted_record = BabelRecord {
+        node: converted_node,
+        extra,
+    };
+    transforms.rewrite_record(converted_record)
+}
+
+/// Converts leftover filter nodes with `non_babel_node_json`, splitting them into one contiguous
+/// chunk per thread so the results keep the leftovers' order
+fn synthesize_in_parallel(
+    leftovers: Vec<(String, NodeListJson)>,
+    transforms: &Transforms,
+    tabular: Option<&TabularSerializer>,
+    threads: usize,
+) -> Vec<serde_json::Result<String>> {
+    let chunk_size = leftovers.len().div_ceil(threads.max(1)).max(1);
+    let mut leftovers = leftovers.into_iter();
+    let chunks: Vec<Vec<(String, NodeListJson)>> = std::iter::from_fn(|| {
+        let chunk: Vec<(String, NodeListJson)> = leftovers.by_ref().take(chunk_size).collect();
+        (!chunk.is_empty()).then_some(chunk)
+    })
+    .collect();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(|(curie, node_json)| {
+                            non_babel_node_json(curie, node_json, transforms, tabular)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+/// The compression levels configured for output files
+fn compression_levels(args: &Config) -> CompressionLevels {
+    CompressionLevels {
+        gzip: args.compression_level.map_or_else(Compression::default, Compression::new),
+        bzip2: args.compression_level.map_or_else(bzip2::Compression::default, |level| {
+            bzip2::Compression::new(level.max(1))
+        }),
+        zstd: args.zstd_level,
+    }
+}
+
+fn print_filter_file_stats(stats: &FilterFileStats, progress: Progress) {
+    progress.info(format_args!("{} nodes excluded", stats.num_excluded));
+    if stats.num_not_included > 0 {
+        progress.info(format_args!(
+            "{} nodes dropped for having none of the included categories",
+            stats.num_not_included
+        ));
+    }
+    if stats.num_missing_category > 0 {
+        progress.info(format_args!(
+            "{} nodes dropped for having no category",
+            stats.num_missing_category
+        ));
+    }
+    if stats.num_curie_regex_excluded > 0 {
+        progress.info(format_args!(
+            "{} nodes dropped for having an id matching the curie regex",
+            stats.num_curie_regex_excluded
+        ));
+    }
+    if stats.num_merged > 0 {
+        progress.info(format_args!(
+            "Merged {} duplicate filter records into the record with the same id",
+            stats.num_merged
+        ));
+    }
+    if stats.num_equivalents_fixed > 0 {
+        progress.info(format_args!(
+            "Removed self-references or duplicates from the equivalent identifiers of {} nodes",
+            stats.num_equivalents_fixed
+        ));
+    }
+}
+
+/// Parses a Babel line as a `BabelJson` node. If that fails and `repair` is set, the line's JSON
+/// glitches are repaired and it's parsed once more, returning the repaired line with the node.
+///
+/// Returns `Err` with the original parse error if the line (even repaired) isn't a node
+fn parse_babel_line(line: &str, repair: bool) -> serde_json::Result<(BabelJson, Option<String>)> {
+    let error = match serde_json::from_str::<BabelJson>(line) {
+        Ok(node) => return Ok((node, None)),
+        Err(e) => e,
+    };
+    match repair.then(|| repair::repair_line(line)).flatten() {
+        Some(repaired) => match serde_json::from_str::<BabelJson>(&repaired) {
+            Ok(node) => Ok((node, Some(repaired))),
+            Err(_) => Err(error),
+        },
+        None => Err(error),
+    }
+}
+
+/// Counts the records in a Babel file that read and parse successfully as a `BabelJson` node,
+/// including those that only parse once repaired if `repair` is set
+fn count_valid_nodes<P>(paths: &[P], separator: u8, repair: bool) -> io::Result<usize>
+where
+    P: AsRef<Path>,
+{
+    let num_valid = Reader::concat(paths, BUF_CAPACITY, None, None, false)?
+        .with_separator(separator)
+        .lines()
+        // like the filtering pass, carry on past a record that isn't UTF-8
+        .filter_map(Result::ok)
+        .filter(|line| parse_babel_line(line, repair).is_ok())
+        .count();
+    Ok(num_valid)
+}
+
+/// Returns true if the curie's prefix is one of `prefixes`. Prefixes may be given with or without
+/// the trailing `:`
+fn has_prefix(curie: &str, prefixes: &[String]) -> bool {
+    let prefix = stats::curie_prefix(curie);
+    prefixes
+        .iter()
+        .any(|p| p.strip_suffix(':').unwrap_or(p) == prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, io,
+        path::Path,
+        process::ExitCode,
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+
+    use ahash::{AHashMap, AHashSet};
+    use camino::Utf8PathBuf;
+    use tempfile::{tempdir, TempDir};
+
+    use super::{
+        count_valid_nodes, filter_babel_file, filter_lines, filter_records, run, BabelJson, Config,
+        FileContext, NodeListJson, OutputFormat, ShardedFilterSet, UnicodeForm,
+    };
+    use crate::file::{reader::Reader, writer::Writer};
+    use crate::output::OutputSerializer;
+    use crate::progress::Progress;
+    use crate::transform::Transforms;
+
+    /// A temporary babel directory, filter file and output directory for exercising `run`
+    struct Fixture {
+        dir: TempDir,
+        config: Config,
+    }
+
+    impl Fixture {
+        fn new(filter_lines: &[&str]) -> io::Result<Fixture> {
+            let dir = tempdir()?;
+            let root = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+            let config = Config {
+                babel_directory: root.join("babel"),
+                filter_file: vec![root.join("filter.jsonl")],
+                output_directory: root.join("output"),
+                ..Default::default()
+            };
+            fs::create_dir(&config.babel_directory)?;
+            fs::create_dir(&config.output_directory)?;
+            fs::write(&config.filter_file[0], join_lines(filter_lines))?;
+            Ok(Fixture { dir, config })
+        }
+
+        fn babel_file(&self, name: &str, lines: &[&str]) -> io::Result<()> {
+            fs::write(self.config.babel_directory.join(name), join_lines(lines))
+        }
+
+        fn run(&self) -> ExitCode {
+            run(self.config.clone())
+        }
+
+        fn output(&self, name: &str) -> Utf8PathBuf {
+            self.config.output_directory.join(name)
+        }
+    }
+
+    fn join_lines(lines: &[&str]) -> String {
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    fn read_lines<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+        Reader::new(path, 32_000)?.lines().collect()
+    }
+
+    fn curies<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+        let mut curies: Vec<String> = read_lines(path)?
+            .iter()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                String::from(value["curie"].as_str().unwrap())
+            })
+            .collect();
+        curies.sort();
+        Ok(curies)
+    }
+
+    #[test]
+    fn parses_integral_float_name_length() {
+        let node: BabelJson = serde_json::from_str(
+            r#"{"curie":"MONDO:1","names":["a"],"types":[],"shortest_name_length":12.0,"taxa":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(node.shortest_name_length, Some(12));
+
+        let node: BabelJson =
+            serde_json::from_str(r#"{"curie":"MONDO:1","names":[],"types":[],"taxa":[]}"#).unwrap();
+        assert_eq!(node.shortest_name_length, None);
+
+        assert!(serde_json::from_str::<BabelJson>(
+            r#"{"curie":"MONDO:1","names":[],"types":[],"shortest_name_length":1.5,"taxa":[]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn counts_only_valid_nodes() -> io::Result<()> {
+        let fixture = Fixture::new(&[])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene"],"taxa":[]}"#,
+                r#"{"curie":"NCBIGene:2","names":["b"],"types":["Gene"]"#,
+                r#"not json"#,
+                r#"{"curie":"NCBIGene:3","names":[],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+
+        let path = fixture.config.babel_directory.join("Gene.txt");
+        assert_eq!(count_valid_nodes(&[&path], b'\n', false)?, 2);
+
+        let lines = [
+            r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene"],"taxa":[]}"#.as_bytes(),
+            b"\xff\xfe",
+            r#"{"curie":"NCBIGene:3","names":[],"types":[],"taxa":[]}"#.as_bytes(),
+        ];
+        fs::write(&path, lines.join(&b'\n'))?;
+        assert_eq!(count_valid_nodes(&[&path], b'\n', false)?, 2);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn nonbabel_prefix_limits_leftovers() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"CHEBI:1","name":"c","category":["biolink:ChemicalEntity"]}"#,
+            r#"{"id":"HP:1","name":"d","category":["biolink:PhenotypicFeature"]}"#,
+            r#"{"id":"UMLS:1","name":"e","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#],
+        )?;
+        fixture.config.nonbabel_prefix = Some(vec![String::from("MONDO"), String::from("HP:")]);
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["HP:1", "MONDO:2"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn second_run_uses_filter_cache() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#],
+        )?;
+        let cache_dir = fixture.config.output_directory.with_file_name("cache");
+        fixture.config.filter_cache_dir = Some(cache_dir.clone());
+
+        fixture.run();
+        // the only file left is the complete cache, named after the filter files
+        let cache_files: Vec<_> = fs::read_dir(&cache_dir)?.collect::<io::Result<_>>()?;
+        assert_eq!(cache_files.len(), 1);
+        let cache_file = cache_files[0].path();
+        let cache_name = cache_file.file_name().unwrap().to_string_lossy();
+        assert!(cache_name.starts_with("filter.jsonl."));
+        assert!(cache_name.ends_with(".filter-cache.jsonl"));
+
+        // a node only present in the cache shows up if (and only if) the cache is used
+        let mut cache = fs::read_to_string(&cache_file)?;
+        cache.push_str(r#"{"id":"HP:1","name":"c","category":["biolink:PhenotypicFeature"]}"#);
+        cache.push('\n');
+        fs::write(&cache_file, cache)?;
+
+        fixture.run();
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["HP:1", "MONDO:2"]);
+
+        // changing the filter file invalidates the cache
+        let mut filter = fs::read_to_string(&fixture.config.filter_file[0])?;
+        filter.push_str(r#"{"id":"MONDO:3","name":"d","category":["biolink:Disease"]}"#);
+        filter.push('\n');
+        fs::write(&fixture.config.filter_file[0], filter)?;
+
+        fixture.run();
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:2", "MONDO:3"]);
+
+        // another list of filter files starting with the same file gets its own cache
+        let other_filter = fixture.config.output_directory.with_file_name("other.jsonl");
+        fs::write(&other_filter, "")?;
+        fixture.config.filter_file.push(other_filter);
+        fixture.run();
+        assert_eq!(fs::read_dir(&cache_dir)?.count(), 2);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn fails_on_unexpected_node_count() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene"],"taxa":[]}"#],
+        )?;
+        let expected_counts = fixture.config.output_directory.with_file_name("counts.json");
+        fs::write(&expected_counts, r#"{"Gene.txt": 5}"#)?;
+        fixture.config.expected_counts = Some(expected_counts);
+        fixture.config.tolerance = 10.0;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        fixture.config.fail_on_error = true;
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn fails_when_too_many_leftovers() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#],
+        )?;
+
+        fixture.config.max_nonbabel_fraction = Some(50.0);
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        fixture.config.max_nonbabel_fraction = Some(49.0);
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn parses_pretty_printed_filter_nodes() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            concat!(
+                "{\n",
+                r#"  "id": "MONDO:2","#,
+                "\n",
+                r#"  "name": "b","#,
+                "\n",
+                r#"  "category": ["biolink:Disease"]"#,
+                "\n}",
+            ),
+            r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]} {"id":"MONDO:4","#,
+            r#""name":"d","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.filter_tolerant_parse = true;
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1", "MONDO:2", "MONDO:3"]);
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:4"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn concatenates_fragments_into_one_output() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"b","category":["biolink:Gene"]}"#,
+            r#"{"id":"MONDO:1","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.part0002.txt",
+            &[r#"{"curie":"NCBIGene:1","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.babel_file(
+            "Gene.part0001.txt",
+            &[r#"{"curie":"NCBIGene:2","names":["b"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["c"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.config.concat_inputs =
+            Some(vec![String::from("Gene.part0001.txt"), String::from("Gene.part0002.txt")]);
+
+        fixture.run();
+
+        // in the order given rather than directory order
+        let lines = read_lines(fixture.output("Gene.txt"))?;
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("NCBIGene:2") && lines[1].contains("NCBIGene:1"));
+        assert!(!fixture.output("Gene.part0001.txt").exists());
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+
+        fixture.config.concat_inputs = Some(vec![String::from("Missing.txt")]);
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn only_filters_files_matching_include_glob() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.babel_file("README.md", &["# Babel"])?;
+        fixture.babel_file(".DS_Store", &["binary"])?;
+
+        fixture.config.include_glob = Some(vec![String::from("*.txt"), String::from("*.jsonl")]);
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert!(!fixture.output("README.md").exists());
+        assert!(!fixture.output(".DS_Store").exists());
+
+        // without a glob only hidden files are skipped
+        fixture.config.include_glob = None;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert!(fixture.output("README.md").exists());
+        assert!(!fixture.output(".DS_Store").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn keeps_nodes_with_wanted_taxon() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"b","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:3","name":"c","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":[],"taxa":["NCBITaxon:9606"]}"#,
+                r#"{"curie":"NCBIGene:2","names":["b"],"types":[],"taxa":["NCBITaxon:10090"]}"#,
+                r#"{"curie":"NCBIGene:3","names":["c"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.taxa = Some(vec![String::from("NCBITaxon:9606")]);
+
+        fixture.run();
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["NCBIGene:1"]);
+
+        fixture.config.keep_untaxed = true;
+        fixture.run();
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["NCBIGene:1", "NCBIGene:3"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn doesnt_write_nodes_of_unwanted_taxon_as_leftovers() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"b","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":[],"taxa":["NCBITaxon:9606"]}"#,
+                r#"{"curie":"NCBIGene:2","names":["b"],"types":[],"taxa":["NCBITaxon:10090"]}"#,
+            ],
+        )?;
+        fixture.config.taxa = Some(vec![String::from("NCBITaxon:9606")]);
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["NCBIGene:1"]);
+        // the mouse gene was found in Babel and dropped, so it isn't synthesized either
+        assert!(curies(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_leftovers_as_ids() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"HP:2","name":"b","category":["biolink:PhenotypicFeature"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+        let leftovers_path = fixture.config.output_directory.with_file_name("leftovers.txt");
+        fixture.config.leftovers_as_ids = Some(leftovers_path.clone());
+
+        fixture.run();
+
+        assert_eq!(fs::read_to_string(&leftovers_path)?, "HP:2\nMONDO:3\n");
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["HP:2", "MONDO:3"]);
+
+        // the leftovers file isn't a directory
+        fixture.config.leftovers_as_ids = Some(leftovers_path.join("leftovers.txt"));
+
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn keeps_only_included_categories() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"MONDO:1","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"PR:1","name":"c","category":["biolink:Protein","biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"d","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:1","names":["b"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"PR:1","names":["c"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.include_category =
+            Some(vec![String::from("biolink:Gene"), String::from("biolink:Protein")]);
+        // exclude wins over include
+        fixture.config.exclude_category = Some(vec![String::from("biolink:Protein")]);
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["NCBIGene:1"]);
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["NCBIGene:2"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn aborts_when_filter_set_is_too_large() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+
+        fixture.config.max_filter_set_size = Some(3);
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        fixture.config.max_filter_set_size = Some(2);
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        // a cache built without the maximum doesn't get around it
+        let cache_dir = fixture.config.output_directory.with_file_name("cache");
+        fixture.config.filter_cache_dir = Some(cache_dir);
+        fixture.config.max_filter_set_size = None;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        fixture.config.max_filter_set_size = Some(2);
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+        fixture.config.filter_cache_dir = None;
+
+        // nothing is written once the filter set is abandoned
+        fs::remove_dir_all(&fixture.config.output_directory)?;
+        fs::create_dir(&fixture.config.output_directory)?;
+        fixture.config.bloom_filter = true;
+        fixture.config.bloom_fp_rate = 0.001;
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+        assert!(!fixture.output("NonBabelNodes.txt.gz").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn rejects_out_of_range_compression_level() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[])?;
+
+        fixture.config.compression_level = Some(9);
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        fixture.config.compression_level = Some(10);
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn preserves_input_mtime() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#],
+        )?;
+        let input_path = fixture.config.babel_directory.join("Disease.txt");
+        let input_mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        fs::File::options().write(true).open(&input_path)?.set_modified(input_mtime)?;
+        fixture.config.preserve_mtime = true;
+
+        fixture.run();
+
+        assert_eq!(fs::metadata(fixture.output("Disease.txt"))?.modified()?, input_mtime);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn only_writes_curies_missing_from_baseline() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":["Disease"],"taxa":[]}"#,
+            ],
+        )?;
+        let baseline = fixture.config.output_directory.with_file_name("baseline");
+        fs::create_dir(&baseline)?;
+        fs::write(
+            baseline.join("Disease.txt"),
+            join_lines(&[r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#]),
+        )?;
+        fixture.config.baseline_dir = Some(baseline);
+        fixture.config.only_new_curies = true;
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:2"]);
+        assert!(curies(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_lowercased_names_index() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["Asthma","ASTHMA","Lung Disease"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["Lung disease"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["Dropped"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        let names_index = fixture.config.output_directory.with_file_name("names.tsv");
+        fixture.config.names_index = Some(names_index.clone());
+
+        fixture.run();
+
+        assert_eq!(
+            read_lines(names_index)?,
+            vec!["asthma\tMONDO:1", "lung disease\tMONDO:1", "lung disease\tMONDO:2"]
+        );
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn skips_comment_lines() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            "# generated filter",
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            "// MONDO:2 was removed",
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                "# Disease nodes",
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+            ],
+        )?;
+        let expected_counts = fixture.config.output_directory.with_file_name("counts.json");
+        fs::write(&expected_counts, r#"{"Disease.txt": 1}"#)?;
+        fixture.config.expected_counts = Some(expected_counts);
+        fixture.config.fail_on_error = true;
+        fixture.config.comment_prefix = Some(vec![String::from("#"), String::from("//")]);
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn parallel_synthesis_matches_sequential() -> io::Result<()> {
+        let filter_lines: Vec<String> = (0..100)
+            .map(|i| format!(r#"{{"id":"MONDO:{i}","name":"n{i}","category":["biolink:Disease"]}}"#))
+            .collect();
+        let filter_lines: Vec<&str> = filter_lines.iter().map(String::as_str).collect();
+        let mut fixture = Fixture::new(&filter_lines)?;
+        fixture.config.stable_order = true;
+
+        fixture.run();
+        let sequential = read_lines(fixture.output("NonBabelNodes.txt.gz"))?;
+
+        fixture.config.nonbabel_threads = 4;
+        fixture.run();
+        let parallel = read_lines(fixture.output("NonBabelNodes.txt.gz"))?;
+
+        assert_eq!(sequential.len(), 100);
+        assert_eq!(parallel, sequential);
+
+        fixture.dir.close()
+    }
+
+    /// Compares NonBabelNodes synthesis on one and several threads. Run with
+    /// `cargo test --release -- --ignored --nocapture non_babel_synthesis_throughput`
+    #[test]
+    #[ignore]
+    fn non_babel_synthesis_throughput() -> io::Result<()> {
+        let filter_lines: Vec<String> = (0..500_000)
+            .map(|i| format!(r#"{{"id":"MONDO:{i}","name":"n{i}","category":["biolink:Disease"]}}"#))
+            .collect();
+        let filter_lines: Vec<&str> = filter_lines.iter().map(String::as_str).collect();
+        let mut fixture = Fixture::new(&filter_lines)?;
+        fixture.config.progress_to_stderr = true;
+
+        for threads in [1, 4] {
+            fixture.config.nonbabel_threads = threads;
+            let t0 = std::time::Instant::now();
+            fixture.run();
+            println!("{threads} threads: whole run took {:.2?}", t0.elapsed());
+        }
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn logs_drop_reasons() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"b","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene","Protein"],"taxa":[]}"#,
+                r#"{"curie":"NCBIGene:2","names":["b"],"types":["Gene"],"taxa":[]}"#,
+                r#"{"curie":"NCBIGene:3","names":["c"],"types":["Gene"],"taxa":[]}"#,
+            ],
+        )?;
+        let drop_log = fixture.config.output_directory.with_file_name("drops.tsv");
+        fixture.config.debug_drop_reasons = Some(drop_log.clone());
+        fixture.config.require_all_types = Some(vec![String::from("Protein")]);
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["NCBIGene:1"]);
+        assert_eq!(
+            read_lines(drop_log)?,
+            vec!["NCBIGene:2\tmissing_required_type", "NCBIGene:3\tnot_in_filter_set"]
+        );
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn keeps_only_allowed_preferred_names() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["asthma"],"types":[],"preferred_name":"asthma","taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["gout"],"types":[],"preferred_name":"gout","taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["asthma"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        let preferred_names = fixture.config.output_directory.with_file_name("names.txt");
+        fs::write(&preferred_names, "asthma\n")?;
+        fixture.config.preferred_name_in_file = Some(preferred_names);
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn skips_babel_file_that_vanished() -> io::Result<()> {
+        let fixture = Fixture::new(&[])?;
+        let filter_set = ShardedFilterSet::new(AHashMap::new(), 1);
+        let transforms = Transforms::default();
+        let context = FileContext::new(&fixture.config, &filter_set, &transforms);
+        let missing = fixture.config.babel_directory.join("Gene.txt");
+
+        assert!(filter_babel_file(&context, missing.as_std_path(), &[&missing])?.is_none());
+        assert!(!fixture.output("Gene.txt").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn collects_distinct_types_of_kept_nodes() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease","Entity"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":["Entity"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":["Dropped"],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.count_distinct_types = true;
+        let path = fixture.config.babel_directory.join("Disease.txt");
+        let mut filter_set = AHashMap::new();
+        for curie in ["MONDO:1", "MONDO:2"] {
+            let node: NodeListJson =
+                serde_json::from_str(&format!(r#"{{"id":"{curie}","name":"a","category":[]}}"#))?;
+            filter_set.insert(String::from(curie), node);
+        }
+        let filter_set = ShardedFilterSet::new(filter_set, 1);
+        let transforms = Transforms::default();
+        let context = FileContext::new(&fixture.config, &filter_set, &transforms);
+
+        let report = filter_babel_file(&context, path.as_std_path(), &[&path])?.unwrap();
+        let mut types: Vec<String> = report.distinct_types.unwrap().into_iter().collect();
+        types.sort_unstable();
+        assert_eq!(types, vec!["Disease", "Entity"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn only_counts_lines_that_were_read() -> io::Result<()> {
+        let fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        let path = fixture.config.babel_directory.join("Disease.txt");
+        let node = r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#;
+        let mut contents = join_lines(&[node]).into_bytes();
+        // not UTF-8, so the line can't be read
+        contents.extend(b"\xff\xfe\n");
+        fs::write(&path, contents)?;
+        let empty = fixture.config.babel_directory.join("Gene.txt");
+        fs::write(&empty, "")?;
+        let filter_set = ShardedFilterSet::new(AHashMap::new(), 1);
+        let transforms = Transforms::default();
+        let context = FileContext::new(&fixture.config, &filter_set, &transforms);
+
+        let report = filter_babel_file(&context, path.as_std_path(), &[&path])?.unwrap();
+        assert_eq!(report.num_nodes, 1);
+        let report = filter_babel_file(&context, empty.as_std_path(), &[&empty])?.unwrap();
+        assert_eq!((report.num_nodes, report.num_kept), (0, 0));
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn filters_records_in_memory() -> io::Result<()> {
+        let filter_nodes = [
+            ("MONDO:1", "a", "biolink:Disease"),
+            ("MONDO:2", "b", "biolink:Disease"),
+            ("MONDO:3", "c", "biolink:Disease"),
+            ("MONDO:5", "e", "biolink:PhenotypicFeature"),
+        ]
+        .map(|(id, name, category)| NodeListJson::new(id, name, vec![String::from(category)]));
+        let config = Config {
+            require_category: true,
+            exclude_category: Some(vec![String::from("biolink:PhenotypicFeature")]),
+            ..Default::default()
+        };
+        let records = join_lines(&[
+            r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+            r#"{"curie":"MONDO:2","names":["b""#,
+            r#"{"curie":"MONDO:3","names":["c"],"types":[],"taxa":[]}"#,
+            r#"{"curie":"MONDO:4","names":["d"],"types":["Disease"],"taxa":[]}"#,
+            r#"{"curie":"MONDO:5","names":["e"],"types":["PhenotypicFeature"],"taxa":[]}"#,
+        ]);
+
+        let mut output = Vec::new();
+        let counts = filter_records(&config, filter_nodes, records.as_bytes(), &mut output)?;
+
+        // MONDO:2 is malformed, MONDO:3 has no types, MONDO:4 isn't in the filter set and
+        // MONDO:5's filter node has an excluded category
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            join_lines(&[r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#])
+        );
+        // the malformed line still counts towards the total
+        assert_eq!(counts, (1, 5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filters_records_by_equivalent_identifiers_in_memory() -> io::Result<()> {
+        let filter_node = NodeListJson::new("MONDO:1", "a", vec![String::from("biolink:Disease")])
+            .with_equivalent_identifiers(vec![String::from("DOID:1")])
+            .with_taxa(vec![String::from("NCBITaxon:9606")]);
+        let config = Config { match_equivalents: true, ..Default::default() };
+        let records = join_lines(&[
+            r#"{"curie":"DOID:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+            r#"{"curie":"DOID:2","names":["b"],"types":["Disease"],"taxa":[]}"#,
+        ]);
+
+        let mut output = Vec::new();
+        let counts = filter_records(&config, [filter_node], records.as_bytes(), &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            join_lines(&[r#"{"curie":"DOID:1","names":["a"],"types":["Disease"],"taxa":[]}"#])
+        );
+        assert_eq!(counts, (1, 2));
+
+        Ok(())
+    }
+
+    /// Fails every node the way a node that doesn't read back as itself fails
+    struct LossySerializer;
+
+    impl OutputSerializer for LossySerializer {
+        fn serialize(&self, node: &BabelJson, _raw: &str) -> serde_json::Result<Vec<u8>> {
+            Err(serde::ser::Error::custom(format!("{} didn't read back", node.curie)))
+        }
+
+        fn serialize_as(
+            &self,
+            node: &BabelJson,
+            raw: &str,
+            _curie: &str,
+        ) -> serde_json::Result<Vec<u8>> {
+            self.serialize(node, raw)
+        }
+    }
+
+    #[test]
+    fn fails_file_when_roundtrip_check_fails() -> io::Result<()> {
+        let fixture = Fixture::new(&[])?;
+        let mut filter_set = AHashMap::new();
+        let node: NodeListJson =
+            serde_json::from_str(r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#)?;
+        filter_set.insert(String::from("MONDO:1"), node);
+        let filter_set = ShardedFilterSet::new(filter_set, 1);
+        let mut filtered = Vec::new();
+        for verify_roundtrip in [false, true] {
+            let transforms = Transforms {
+                verify_roundtrip,
+                ..Default::default()
+            };
+            let context = FileContext {
+                per_file_matches: true,
+                serializer: &LossySerializer,
+                ..FileContext::new(&fixture.config, &filter_set, &transforms)
+            };
+            let records = join_lines(&[
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+            ]);
+            let mut lines = Reader::from_reader(io::Cursor::new(records), 32_000)?.lines();
+            let result = filter_lines(
+                &context,
+                Path::new("Disease.txt"),
+                &mut lines,
+                None,
+                Progress::new(false),
+                |_| Ok(()),
+            );
+            filtered.push(result.map(|filtered| filtered.num_kept));
+        }
+
+        // without the check the node is only skipped
+        assert_eq!(filtered[0].as_ref().ok(), Some(&0));
+        let error = filtered[1].as_ref().unwrap_err();
+        assert!(error.to_string().contains("MONDO:1 didn't read back"));
+
+        fixture.dir.close()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn continues_after_an_output_write_error() -> io::Result<()> {
+        let filter_lines: Vec<String> = (0..1000)
+            .map(|i| format!(r#"{{"id":"MONDO:{i}","name":"a","category":["biolink:Disease"]}}"#))
+            .chain([String::from(r#"{"id":"NCBIGene:1","name":"b","category":["biolink:Gene"]}"#)])
+            .collect();
+        let filter_lines: Vec<&str> = filter_lines.iter().map(String::as_str).collect();
+        let fixture = Fixture::new(&filter_lines)?;
+        // enough to overflow the write buffer, so the error isn't deferred to the final flush
+        let disease_lines: Vec<String> = (0..1000)
+            .map(|i| format!(r#"{{"curie":"MONDO:{i}","names":["a"],"types":[],"taxa":[]}}"#))
+            .collect();
+        let disease_lines: Vec<&str> = disease_lines.iter().map(String::as_str).collect();
+        fixture.babel_file("Disease.txt", &disease_lines)?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[r#"{"curie":"NCBIGene:1","names":["b"],"types":[],"taxa":[]}"#],
+        )?;
+        // every write to /dev/full fails as if the disk were full
+        std::os::unix::fs::symlink("/dev/full", fixture.output("Disease.txt"))?;
+
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["NCBIGene:1"]);
+        assert!(fixture.output("NonBabelNodes.txt.gz").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn reports_bytes_read_for_throughput() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[])?;
+        let lines = [
+            r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+            r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#,
+        ];
+        fixture.babel_file("Disease.txt", &lines)?;
+        fs::create_dir_all(&fixture.config.output_directory)?;
+        fixture.config.report_throughput = true;
+        let filter_set = ShardedFilterSet::new(AHashMap::new(), 1);
+        let transforms = Transforms::default();
+        let context = FileContext::new(&fixture.config, &filter_set, &transforms);
+        let babel_path = fixture.config.babel_directory.join("Disease.txt");
+
+        let report = filter_babel_file(&context, babel_path.as_std_path(), &[&babel_path])?;
+        let report = report.unwrap();
+
+        assert_eq!(report.num_nodes, 2);
+        assert_eq!(report.bytes_read, fs::metadata(&babel_path)?.len());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn filters_against_ids_only() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.ids_only = true;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert!(!fixture.output("NonBabelNodes.txt.gz").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_curie_once_across_files() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        let node = r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#;
+        fixture.babel_file("Disease.txt", &[node])?;
+        fixture.babel_file(
+            "DiseaseOrPhenotypicFeature.txt",
+            &[node, r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#],
+        )?;
+        // a Bloom filter keeps a node every time it appears, unlike the filter set
+        fixture.config.bloom_filter = true;
+        fixture.config.bloom_fp_rate = 0.001;
+        fixture.config.global_dedup = true;
+
+        fixture.run();
+
+        let mut written = curies(fixture.output("Disease.txt"))?;
+        written.extend(curies(fixture.output("DiseaseOrPhenotypicFeature.txt"))?);
+        written.sort();
+        assert_eq!(written, vec!["MONDO:1", "MONDO:2"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn counts_curie_as_written_only_once_it_is() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[])?;
+        fixture.config.global_dedup = true;
+        fixture.config.keep_largest_per_prefix = true;
+        let mut filter_set = AHashMap::new();
+        for curie in ["MONDO:1", "MONDO:2"] {
+            let node: NodeListJson =
+                serde_json::from_str(&format!(r#"{{"id":"{curie}","name":"a","category":[]}}"#))?;
+            filter_set.insert(String::from(curie), node);
+        }
+        let filter_set = ShardedFilterSet::new(filter_set, 1);
+        let transforms = Transforms::default();
+        let written_curies = Mutex::new(AHashSet::new());
+        let context = FileContext {
+            written_curies: Some(&written_curies),
+            per_file_matches: true,
+            ..FileContext::new(&fixture.config, &filter_set, &transforms)
+        };
+        let node = r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#;
+        let files = [
+            join_lines(&[node, r#"{"curie":"MONDO:2","names":["b","c"],"types":[],"taxa":[]}"#]),
+            join_lines(&[node]),
+        ];
+
+        let mut outputs = Vec::new();
+        for records in files {
+            let mut lines = Reader::from_reader(io::Cursor::new(records), 32_000)?.lines();
+            let mut output = Vec::new();
+            filter_lines(
+                &context,
+                Path::new("Disease.txt"),
+                &mut lines,
+                None,
+                Progress::new(false),
+                |line| {
+                    output.push(String::from_utf8(line.to_vec()).unwrap());
+                    Ok(())
+                },
+            )?;
+            outputs.push(output);
+        }
+
+        // the first file kept MONDO:2 instead of MONDO:1, so MONDO:1 isn't a duplicate in the
+        // second
+        assert_eq!(
+            outputs[0],
+            vec![r#"{"curie":"MONDO:2","names":["b","c"],"types":[],"taxa":[]}"#]
+        );
+        assert_eq!(outputs[1], vec![node]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn matches_preferred_names_after_normalizing() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[r#"{"id":"CHEBI:1","name":"a","category":[]}"#])?;
+        // a combining acute accent, where the preferred names file has a precomposed é
+        fixture.babel_file(
+            "SmallMolecule.txt",
+            &[r#"{"curie":"CHEBI:1","names":["cafe\u0301"],"types":[],"preferred_name":"cafe\u0301","taxa":[]}"#],
+        )?;
+        let preferred_names = fixture.config.output_directory.with_file_name("names.txt");
+        fs::write(&preferred_names, "caf\u{e9}\n")?;
+        fixture.config.preferred_name_in_file = Some(preferred_names);
+        fixture.config.normalize_unicode = Some(UnicodeForm::Nfc);
+
+        fixture.run();
+
+        let lines = read_lines(fixture.output("SmallMolecule.txt"))?;
+        assert_eq!(lines.len(), 1);
+        let node: serde_json::Value = serde_json::from_str(&lines[0])?;
+        assert_eq!(node["preferred_name"], "caf\u{e9}");
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn drops_nodes_without_category() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:3","name":"c","category":[]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":["Disease"],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.require_category = true;
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert!(read_lines(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn overrides_output_format_per_file() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[])?;
+        for name in ["Disease.txt.gz", "Gene.txt.gz", "Protein.txt.gz"] {
+            Writer::new(fixture.config.babel_directory.join(name), 32_000)?
+                .write_line(r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#)?;
+        }
+        fs::write(
+            fixture.config.babel_directory.join("formats.toml"),
+            "\"Disease.txt.gz\" = \"plaintext\"\n\"Gene.txt.gz\" = \"gzipped\"\n",
+        )?;
+        fixture.config.output_format = Some(OutputFormat::Plaintext);
+
+        fixture.run();
+
+        assert!(fixture.output("Disease.txt").is_file());
+        assert!(fixture.output("Gene.txt.gz").is_file());
+        assert!(!fixture.output("Gene.txt").exists());
+        // not in formats.toml, so the global format applies
+        assert!(fixture.output("Protein.txt").is_file());
+        assert!(!fixture.output("formats.toml").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+        let root = fixture.config.output_directory.with_file_name("");
+        let mut before: Vec<_> = fs::read_dir(&root)?.map(|entry| entry.unwrap().path()).collect();
+        before.sort();
+        fixture.config.dry_run = true;
+        fixture.config.meta_sidecar = true;
+        fixture.config.leftovers_as_ids = Some(root.join("leftovers.txt"));
+        fixture.config.names_index = Some(root.join("names.tsv"));
+        fixture.config.debug_drop_reasons = Some(root.join("drops.tsv"));
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(fs::read_dir(&fixture.config.output_directory)?.count(), 0);
+        let mut after: Vec<_> = fs::read_dir(&root)?.map(|entry| entry.unwrap().path()).collect();
+        after.sort();
+        assert_eq!(after, before);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn repairs_malformed_lines() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a",],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[],"score":NaN}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":[],"taxa":[]"#,
+            ],
+        )?;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert!(curies(fixture.output("Disease.txt"))?.is_empty());
+
+        fixture.config.repair_json = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        // the unterminated object can't be repaired
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1", "MONDO:2"]);
+        let lines = read_lines(fixture.output("Disease.txt"))?;
+        assert!(lines[1].ends_with(r#""score":null}"#));
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn skips_non_babel_nodes() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+        let leftovers_path = fixture.config.output_directory.with_file_name("leftovers.txt");
+        fixture.config.leftovers_as_ids = Some(leftovers_path.clone());
+        fixture.config.no_non_babel_nodes = true;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert!(!fixture.output("NonBabelNodes.txt.gz").exists());
+        // leftovers are still tracked
+        assert_eq!(read_lines(&leftovers_path)?, vec!["MONDO:2"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn fails_on_duplicate_output_curies() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"NCBIGene:1","name":"b","category":["biolink:Gene"]}"#,
+        ])?;
+        // ids-only mode keeps a Babel node every time it appears
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[r#"{"curie":"NCBIGene:1","names":["b"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.config.ids_only = true;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        fixture.config.assert_no_duplicates = true;
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+        // the other files are still written
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["NCBIGene:1"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_a_subdirectory_per_file() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"NCBIGene:1","name":"b","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"c","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[r#"{"curie":"NCBIGene:1","names":["b"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.config.output_subdir_per_file = true;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Disease/Disease.txt"))?, vec!["MONDO:1"]);
+        assert_eq!(curies(fixture.output("Gene/Gene.txt"))?, vec!["NCBIGene:1"]);
+        assert!(!fixture.output("Disease.txt").exists());
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["NCBIGene:2"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn matches_equivalent_identifiers() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[concat!(
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"],"#,
+            r#""equivalent_identifiers":["DOID:1","UMLS:1"]}"#
+        )])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"DOID:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"UMLS:1","names":["a"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert!(curies(fixture.output("Disease.txt"))?.is_empty());
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:1"]);
+
+        fixture.config.match_equivalents = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        // the filter node is only matched once, and isn't a leftover
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["DOID:1"]);
+        assert!(curies(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn ignores_curie_case() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"mondo:0000001","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:0000001","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert!(curies(fixture.output("Disease.txt"))?.is_empty());
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["mondo:0000001"]);
+
+        fixture.config.ignore_curie_case = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:0000001"]);
+        assert!(curies(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.config.ids_only = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:0000001"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn merges_duplicate_filter_records() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:1","name":"b","category":["biolink:PhenotypicFeature"]}"#,
+        ])?;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        let lines = read_lines(fixture.output("NonBabelNodes.txt.gz"))?;
+        let node: serde_json::Value = serde_json::from_str(&lines[0])?;
+        assert_eq!(node["types"], serde_json::json!(["PhenotypicFeature"]));
+
+        fixture.config.merge_duplicate_filter_records = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        let lines = read_lines(fixture.output("NonBabelNodes.txt.gz"))?;
+        assert_eq!(lines.len(), 1);
+        let node: serde_json::Value = serde_json::from_str(&lines[0])?;
+        assert_eq!(node["types"], serde_json::json!(["Disease", "PhenotypicFeature"]));
+        assert_eq!(node["names"], serde_json::json!(["a", "b"]));
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn names_non_babel_nodes() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.config.non_babel_nodes_name = Some(String::from("PassTwoLeftovers.txt.gz"));
+        fixture.config.output_format = Some(OutputFormat::Plaintext);
+        fixture.config.prune_orphans = true;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("PassTwoLeftovers.txt"))?, vec!["MONDO:1"]);
+        assert!(!fixture.output("NonBabelNodes.txt.gz").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_tsv_rows() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"b","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[concat!(
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene","Entity"],"#,
+                r#""preferred_name":"a","taxa":["NCBITaxon:9606"]}"#
+            )],
+        )?;
+        fixture.config.output_format = Some(OutputFormat::Tsv);
+        fixture.config.list_delimiter = String::from(";");
+
+        fixture.run();
+
+        let header = "curie\tpreferred_name\ttypes\ttaxa";
+        assert_eq!(
+            read_lines(fixture.output("Gene.tsv"))?,
+            vec![header, "NCBIGene:1\ta\tGene;Entity\tNCBITaxon:9606"]
+        );
+        assert_eq!(
+            read_lines(fixture.output("NonBabelNodes.tsv"))?,
+            vec![header, "NCBIGene:2\tb\tGene\t"]
+        );
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn forces_zstd_output() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        Writer::new(fixture.config.babel_directory.join("Disease.txt.gz"), 32_000)?
+            .write_line(r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#)?;
+        fixture.config.output_format = Some(OutputFormat::Zstd);
+        fixture.config.zstd_level = 19;
+
+        fixture.run();
+
+        assert!(!fixture.output("Disease.txt.gz").exists());
+        assert_eq!(curies(fixture.output("Disease.txt.zst"))?, vec!["MONDO:1"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn keeps_curies_in_numeric_range() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:5","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:500","name":"b","category":["biolink:Gene"]}"#,
+            r#"{"id":"MONDO:500","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[
+                r#"{"curie":"NCBIGene:5","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"NCBIGene:500","names":["b"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:500","names":["c"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.curie_numeric_range = Some(vec![String::from("NCBIGene:1-100")]);
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Gene.txt"))?, vec!["MONDO:500", "NCBIGene:5"]);
+
+        fixture.config.curie_numeric_range = Some(vec![String::from("NCBIGene:100-1")]);
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn reads_filter_tsv() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            "#id\tcategory",
+            "MONDO:1\tbiolink:Disease",
+            "MONDO:2\tbiolink:Disease",
+            "NCBIGene:1\tbiolink:Gene",
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"NCBIGene:1","names":["b"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        // the columns default to the first two
+        fixture.config.filter_tsv = true;
+        fixture.config.comment_prefix = Some(vec![String::from("#")]);
+        fixture.config.exclude_category = Some(vec![String::from("biolink:Gene")]);
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        let leftovers = read_lines(fixture.output("NonBabelNodes.txt.gz"))?;
+        assert_eq!(leftovers.len(), 1);
+        let leftover: serde_json::Value = serde_json::from_str(&leftovers[0]).unwrap();
+        assert_eq!(leftover["curie"], "MONDO:2");
+        assert_eq!(leftover["types"], serde_json::json!(["Disease"]));
+        assert_eq!(leftover["names"], serde_json::json!([]));
+        assert_eq!(leftover["preferred_name"], serde_json::Value::Null);
+        assert_eq!(leftover["shortest_name_length"], serde_json::Value::Null);
+
+        fixture.config.filter_tolerant_parse = true;
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn reads_several_filter_files() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        let second_filter_file = fixture.config.filter_file[0].with_file_name("filter2.jsonl");
+        fs::write(
+            &second_filter_file,
+            join_lines(&[
+                r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+                r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+            ]),
+        )?;
+        fixture.config.filter_file.push(second_filter_file.clone());
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1", "MONDO:3"]);
+        // the id in both files is a single leftover
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:2"]);
+
+        fixture.config.filter_file.push(second_filter_file.with_file_name("missing.jsonl"));
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn excludes_categories_by_regex() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"PANTHER:1","name":"b","category":["biolink:GeneFamily"]}"#,
+            r#"{"id":"MONDO:1","name":"c","category":["biolink:Disease"]}"#,
+            r#"{"id":"HGNC:1","name":"d","category":["biolink:NotAGene"]}"#,
+        ])?;
+        fixture.config.exclude_category = Some(vec![String::from("biolink:Gene.*")]);
+        fixture.config.category_regex = true;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        // patterns have to match the whole category
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["HGNC:1", "MONDO:1"]);
+
+        fixture.config.exclude_category = Some(vec![String::from("biolink:(Gene")]);
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn excludes_curies_by_regex() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"OBSOLETE.MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"OBSOLETE.MONDO:3","names":["c"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        let drop_log = fixture.config.output_directory.with_file_name("drops.tsv");
+        fixture.config.debug_drop_reasons = Some(drop_log.clone());
+        fixture.config.exclude_curie_regex = Some(String::from(r"^OBSOLETE\."));
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        // dropped from the filter set too, so it isn't synthesized
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:2"]);
+        assert_eq!(read_lines(drop_log)?, vec!["OBSOLETE.MONDO:3\tcurie_regex"]);
+
+        fixture.config.exclude_curie_regex = Some(String::from("OBSOLETE("));
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_failed_lines() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":"#,
+            ],
+        )?;
+        let failed_path = fixture.config.output_directory.with_file_name("failed.tsv");
+        fixture.config.failed_lines_output = Some(failed_path.clone());
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        let filter_name = fixture.config.filter_file[0].file_name().unwrap();
+        assert_eq!(
+            read_lines(failed_path)?,
+            vec![
+                format!("{filter_name}\t2\t{}", r#"{"id":"MONDO:2","name":"#),
+                format!("Disease.txt\t2\t{}", r#"{"curie":"MONDO:3","names":"#),
+            ]
+        );
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_json_report() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        let report_path = fixture.config.output_directory.with_file_name("report.json");
+        fixture.config.report_json = Some(report_path.clone());
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(report_path)?)?;
+        assert_eq!(report["filter_nodes"], 2);
+        assert_eq!(report["num_excluded"], 0);
+        assert_eq!(report["files"][0]["name"], "Disease.txt");
+        assert_eq!(report["files"][0]["total"], 2);
+        assert_eq!(report["files"][0]["kept"], 1);
+        assert_eq!(report["non_babel_nodes"], 1);
+        assert!(report["duration_ms"].is_u64());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn reports_or_keeps_repeated_curies() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:1","names":["a again"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        let drop_log = fixture.config.output_directory.with_file_name("drops.tsv");
+        fixture.config.debug_drop_reasons = Some(drop_log.clone());
+
+        fixture.config.report_duplicates = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert_eq!(
+            read_lines(&drop_log)?,
+            vec!["MONDO:2\tnot_in_filter_set", "MONDO:1\trepeated_curie"]
+        );
+
+        fixture.config.keep_duplicates = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        assert_eq!(read_lines(fixture.output("Disease.txt"))?.len(), 2);
+        assert_eq!(read_lines(&drop_log)?, vec!["MONDO:2\tnot_in_filter_set"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn excludes_blocklisted_curies() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:3","name":"c","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#,
+            ],
+        )?;
+        let blocklist = fixture.config.output_directory.with_file_name("blocklist.txt");
+        fs::write(&blocklist, "MONDO:2\nMONDO:3\n")?;
+        fixture.config.exclude_curie_file = Some(blocklist);
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert!(read_lines(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn keeps_curies_matched_in_several_files_once_unless_per_file() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        let node = r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#;
+        let names = ["Disease.txt", "Other.txt", "More.txt"];
+        for name in names {
+            fixture.babel_file(name, &[node, node])?;
+        }
+
+        // only one file claims MONDO:1, however many are filtered at once
+        for concurrency in [1, 3] {
+            fixture.config.concurrency = concurrency;
+            assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+            let mut num_kept = 0;
+            for name in names {
+                num_kept += curies(fixture.output(name))?.len();
+            }
+            assert_eq!(num_kept, 1);
+            assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:2"]);
+        }
+
+        fixture.config.per_file_matches = true;
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        // each file keeps its first MONDO:1 whichever file gets to it first
+        for name in names {
+            assert_eq!(curies(fixture.output(name))?, vec!["MONDO:1"]);
+        }
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:2"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_curie_once_across_concurrent_files() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:2","name":"b","category":["biolink:Disease"]}"#,
+        ])?;
+        let node = r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#;
+        fixture.babel_file("Disease.txt", &[node])?;
+        fixture.babel_file("Other.txt", &[node])?;
+        // every file would keep MONDO:1 without the dedup
+        fixture.config.per_file_matches = true;
+        fixture.config.global_dedup = true;
+        fixture.config.concurrency = 2;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        let mut written = curies(fixture.output("Disease.txt"))?;
+        written.extend(curies(fixture.output("Other.txt"))?);
+        assert_eq!(written, vec!["MONDO:1"]);
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:2"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn rejects_group_by_prefix_with_largest_per_prefix() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[])?;
+        fixture.config.group_by_prefix = true;
+        fixture.config.keep_largest_per_prefix = true;
+
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn prunes_orphaned_outputs() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:1","names":["a"],"types":[],"taxa":[]}"#],
+        )?;
+        // left by an earlier run over a Babel directory that also had Gene.txt
+        for stale in ["Disease.txt", "Disease.txt.gz", "Gene.txt", "Gene.txt.meta.json"] {
+            fs::write(fixture.output(stale), "{}\n")?;
+        }
+        fixture.config.prune_orphans = true;
+        fixture.config.names_index = Some(fixture.output("names.tsv"));
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        // named after a current input, just in another format
+        assert!(fixture.output("Disease.txt.gz").exists());
+        assert!(!fixture.output("Gene.txt").exists());
+        assert!(!fixture.output("Gene.txt.meta.json").exists());
+        assert!(fixture.output("NonBabelNodes.txt.gz").exists());
+        assert!(fixture.output("names.tsv").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_meta_sidecar() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene"],"taxa":[]}"#,
+                r#"{"curie":"NCBIGene:2","names":["b"],"types":["Gene"],"taxa":[]}"#,
+            ],
+        )?;
+        fixture.config.meta_sidecar = true;
+
+        fixture.run();
+
+        let meta: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(fixture.output("Gene.txt.meta.json"))?)?;
+        assert_eq!(meta["input_file"], "Gene.txt");
+        assert_eq!(meta["nodes_read"], 2);
+        assert_eq!(meta["nodes_kept"], 1);
+        assert!(meta["duration_ms"].is_u64());
+        assert_eq!(meta["filter_file"], serde_json::json!(fixture.config.filter_file));
+        assert_eq!(
+            meta["filter_file_crc32"],
+            crate::provenance::files_crc32(&fixture.config.filter_file)?
+        );
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn writes_provenance_header() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"NCBIGene:1","name":"a","category":["biolink:Gene"]}"#,
+            r#"{"id":"NCBIGene:2","name":"b","category":["biolink:Gene"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Gene.txt",
+            &[r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene"],"taxa":[]}"#],
+        )?;
+        fixture.config.provenance_header = true;
+
+        fixture.run();
+
+        for output in ["Gene.txt", "NonBabelNodes.txt.gz"] {
+            let lines = read_lines(fixture.output(output))?;
+            assert_eq!(lines.len(), 2);
+            let header: serde_json::Value = serde_json::from_str(&lines[0])?;
+            assert_eq!(header["_provenance"], true);
+            assert_eq!(header["tool_version"], env!("CARGO_PKG_VERSION"));
+            assert_eq!(header["filter_file"], serde_json::json!(fixture.config.filter_file));
+            assert_eq!(
+                header["filter_file_crc32"],
+                crate::provenance::files_crc32(&fixture.config.filter_file)?
+            );
+            assert!(header["timestamp"].as_str().is_some_and(|t| t.ends_with('Z')));
+            assert_eq!(header["config"]["provenance_header"], true);
+        }
+        assert!(!fixture.output("Gene.txt.meta.json").exists());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn strips_empty_fields_from_nonbabel_nodes() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[r#"{"id":"MONDO:1","name":"a","category":[]}"#])?;
+        fixture.config.strip_empty_fields = true;
+
+        fixture.run();
+
+        let lines = read_lines(fixture.output("NonBabelNodes.txt.gz"))?;
+        let node: serde_json::Value = serde_json::from_str(&lines[0])?;
+        assert_eq!(node["curie"], "MONDO:1");
+        assert!(node.get("types").is_none());
+        assert!(node.get("taxa").is_none());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn carries_filter_fields_into_nonbabel_nodes() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            concat!(
+                r#"{"id":"MONDO:1","name":"Ménière","category":["biolink:Disease"],"#,
+                r#""equivalent_identifiers":["MONDO:1","DOID:1"],"in_taxon":["NCBITaxon:9606"]}"#
+            ),
+            r#"{"id":"MONDO:1","name":"Mén","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.config.merge_duplicate_filter_records = true;
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+        let lines = read_lines(fixture.output("NonBabelNodes.txt.gz"))?;
+        let node: serde_json::Value = serde_json::from_str(&lines[0])?;
+        assert_eq!(node["names"], serde_json::json!(["Ménière", "Mén"]));
+        // in characters, of the shorter merged name
+        assert_eq!(node["shortest_name_length"], 3);
+        assert_eq!(node["taxa"], serde_json::json!(["NCBITaxon:9606"]));
+        assert_eq!(node["equivalent_identifiers"], serde_json::json!(["MONDO:1", "DOID:1"]));
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn applies_name_length_bounds_to_nonbabel_nodes() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"asthma","category":[]}"#,
+            r#"{"id":"MONDO:2","name":"a very long disease name","category":[]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[r#"{"curie":"MONDO:3","names":["a very long disease name"],"types":[],"taxa":[]}"#],
+        )?;
+        fixture.config.max_name_length = Some(10);
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("NonBabelNodes.txt.gz"))?, vec!["MONDO:1"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn matches_babel_curies_through_curie_map() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"DOID:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":["Disease"],"taxa":[]}"#,
+            ],
+        )?;
+        let curie_map = fixture.config.output_directory.with_file_name("map.tsv");
+        fs::write(&curie_map, "MONDO:1\tDOID:1\nMONDO:2\tDOID:2\n")?;
+        fixture.config.curie_map = Some(curie_map);
+
+        fixture.run();
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1"]);
+        assert!(curies(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.config.rewrite_mapped_curie = true;
+        fixture.run();
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["DOID:1"]);
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn seeds_filter_set_from_previous_output() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":["Disease"],"taxa":[]}"#,
+            ],
+        )?;
+        let previous = fixture.config.output_directory.with_file_name("previous");
+        fs::create_dir(&previous)?;
+        fs::write(
+            previous.join("Disease.txt"),
+            join_lines(&[
+                r#"{"curie":"MONDO:2","names":["b"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:4","names":["d"],"types":["Disease"],"taxa":[]}"#,
+            ]),
+        )?;
+        fs::write(
+            previous.join("NonBabelNodes.txt"),
+            join_lines(&[r#"{"curie":"HP:1","names":["e"],"types":[],"taxa":[]}"#]),
+        )?;
+        fixture.config.seed_filter_from_output = Some(previous);
+
+        fixture.run();
+
+        assert_eq!(curies(fixture.output("Disease.txt"))?, vec!["MONDO:1", "MONDO:2"]);
+        assert!(curies(fixture.output("NonBabelNodes.txt.gz"))?.is_empty());
+
+        fixture.dir.close()
+    }
+
+    #[test]
+    fn leaves_seeded_curies_out_of_leftover_fraction() -> io::Result<()> {
+        let mut fixture = Fixture::new(&[
+            r#"{"id":"MONDO:1","name":"a","category":["biolink:Disease"]}"#,
+            r#"{"id":"MONDO:9","name":"z","category":["biolink:Disease"]}"#,
+        ])?;
+        fixture.babel_file(
+            "Disease.txt",
+            &[
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:2","names":["b"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":["Disease"],"taxa":[]}"#,
+            ],
+        )?;
+        let previous = fixture.config.output_directory.with_file_name("previous");
+        fs::create_dir(&previous)?;
+        fs::write(
+            previous.join("Disease.txt"),
+            join_lines(&[
+                r#"{"curie":"MONDO:2","names":["b"],"types":["Disease"],"taxa":[]}"#,
+                r#"{"curie":"MONDO:3","names":["c"],"types":["Disease"],"taxa":[]}"#,
+            ]),
+        )?;
+        fixture.config.seed_filter_from_output = Some(previous);
+        // one of the two filter nodes is a leftover, but only one of four with the seeded curies
+        fixture.config.max_nonbabel_fraction = Some(40.0);
+
+        assert_eq!(fixture.run(), ExitCode::FAILURE);
+
+        fixture.config.max_nonbabel_fraction = Some(60.0);
+
+        assert_eq!(fixture.run(), ExitCode::SUCCESS);
+
+        fixture.dir.close()
     }
-    false
 }
@@ -0,0 +1,277 @@
+use ahash::AHashSet;
+use camino::Utf8Path;
+use std::{fs, io, path::Path};
+
+use crate::file::{reader::Reader, writer::Writer};
+use crate::filter_set;
+use crate::{BabelJson, ConflictPolicy, BUF_CAPACITY};
+
+/// Counts from merging an output directory into an existing one
+#[derive(Default, Debug, PartialEq)]
+pub struct MergeReport {
+    /// New nodes whose curie wasn't already in the existing directory
+    pub num_added: usize,
+    /// Existing nodes replaced by a new node with the same curie (`keep-new`)
+    pub num_replaced: usize,
+    /// New nodes dropped because their curie was already present (`keep-existing`)
+    pub num_skipped: usize,
+}
+
+/// Merges the Babel-derived output files of `output_directory` into the same-named files of
+/// `merge_directory`, creating any that don't exist yet. A curie that's already somewhere in
+/// `merge_directory` is a conflict, resolved by `policy`. NonBabelNodes and metadata sidecars
+/// aren't merged. Every curie in `merge_directory` is held in memory while merging.
+///
+/// Files are rewritten through a temporary file and renamed into place, so gzipped outputs stay
+/// single-member. With `ConflictPolicy::Error`, nothing is written if there's any conflict.
+///
+/// Returns `Err` with a message if there's a conflict under `ConflictPolicy::Error` or a file
+/// can't be read or written
+pub fn merge_into(
+    output_directory: &Utf8Path,
+    merge_directory: &Utf8Path,
+    policy: ConflictPolicy,
+    separator: u8,
+) -> Result<MergeReport, String> {
+    let existing = filter_set::read_output_curies(merge_directory, separator)
+        .map_err(|e| format!("Error reading {merge_directory}: {e}"))?;
+
+    let new_files = filter_set::output_files(output_directory)
+        .map_err(|e| format!("Error reading {output_directory}: {e}"))?;
+    let mut conflicts = AHashSet::new();
+    for path in &new_files {
+        let lines = Reader::new(path, BUF_CAPACITY)
+            .map_err(|e| format!("Error reading {:?}: {e}", path))?
+            .with_separator(separator)
+            .lines();
+        for line in lines {
+            let line = line.map_err(|e| format!("Error reading {:?}: {e}", path))?;
+            match line_curie(&line) {
+                Some(curie) if existing.contains(&curie) => {
+                    conflicts.insert(curie);
+                }
+                _ => (),
+            }
+        }
+    }
+    drop(existing);
+
+    if matches!(policy, ConflictPolicy::Error) && !conflicts.is_empty() {
+        let mut examples: Vec<&String> = conflicts.iter().collect();
+        examples.sort();
+        examples.truncate(5);
+        return Err(format!(
+            "{} curies are already in {merge_directory}, e.g. {:?}",
+            conflicts.len(),
+            examples
+        ));
+    }
+
+    let mut report = MergeReport::default();
+    let mut merged_names = AHashSet::new();
+    for path in &new_files {
+        let file_name = path.file_name().unwrap_or_default();
+        merged_names.insert(file_name.to_os_string());
+        merge_file(
+            &merge_directory.as_std_path().join(file_name),
+            Some(path),
+            &conflicts,
+            policy,
+            separator,
+            &mut report,
+        )
+        .map_err(|e| format!("Error merging {:?}: {e}", path))?;
+    }
+
+    // replaced curies may live in existing files that have no new counterpart
+    if matches!(policy, ConflictPolicy::KeepNew) && !conflicts.is_empty() {
+        let existing_files = filter_set::output_files(merge_directory)
+            .map_err(|e| format!("Error reading {merge_directory}: {e}"))?;
+        for path in existing_files {
+            if !merged_names.contains(path.file_name().unwrap_or_default()) {
+                merge_file(&path, None, &conflicts, policy, separator, &mut report)
+                    .map_err(|e| format!("Error merging into {:?}: {e}", path))?;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Rewrites `target` (which may not exist yet) with its existing lines followed by those of
+/// `source`, dropping conflicting lines from whichever side `policy` says to drop
+fn merge_file(
+    target: &Path,
+    source: Option<&Path>,
+    conflicts: &AHashSet<String>,
+    policy: ConflictPolicy,
+    separator: u8,
+    report: &mut MergeReport,
+) -> io::Result<()> {
+    // prefixing the name keeps the `.gz` extension the writer looks at
+    let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp = target.with_file_name(format!(".merging.{file_name}"));
+
+    let target_exists = target.is_file();
+    let mut writer = Writer::new(&temp, BUF_CAPACITY)?.with_separator(separator);
+    if target_exists {
+        for line in Reader::new(target, BUF_CAPACITY)?.with_separator(separator).lines() {
+            let line = line?;
+            let conflicting = matches!(policy, ConflictPolicy::KeepNew)
+                && line_curie(&line).is_some_and(|curie| conflicts.contains(&curie));
+            if conflicting {
+                report.num_replaced += 1;
+            } else {
+                writer.write_line(&line)?;
+            }
+        }
+    }
+
+    if let Some(source) = source {
+        for line in Reader::new(source, BUF_CAPACITY)?.with_separator(separator).lines() {
+            let line = line?;
+            // the existing file already starts with its own provenance header
+            if target_exists && line.starts_with(r#"{"_provenance":true"#) {
+                continue;
+            }
+            let conflicting = line_curie(&line).is_some_and(|curie| conflicts.contains(&curie));
+            if conflicting && matches!(policy, ConflictPolicy::KeepExisting) {
+                report.num_skipped += 1;
+                continue;
+            }
+            if !conflicting {
+                report.num_added += 1;
+            }
+            writer.write_line(&line)?;
+        }
+    }
+    writer.finish()?;
+
+    fs::rename(&temp, target)
+}
+
+/// Returns the curie of an output line, or `None` if it isn't a Babel node
+fn line_curie(line: &str) -> Option<String> {
+    serde_json::from_str::<BabelJson>(line)
+        .ok()
+        .map(|node| node.curie)
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use std::{fs, io};
+    use tempfile::{tempdir, TempDir};
+
+    use super::{merge_into, MergeReport};
+    use crate::ConflictPolicy;
+
+    /// An existing output directory and a new one that both have MONDO:1
+    fn directories() -> io::Result<(TempDir, Utf8PathBuf, Utf8PathBuf)> {
+        let dir = tempdir()?;
+        let root = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let (existing, output) = (root.join("existing"), root.join("output"));
+        fs::create_dir(&existing)?;
+        fs::create_dir(&output)?;
+        fs::write(
+            existing.join("Disease.txt"),
+            concat!(
+                r#"{"curie":"MONDO:1","names":["old"],"types":[],"taxa":[]}"#,
+                "\n",
+                r#"{"curie":"MONDO:2","names":["b"],"types":[],"taxa":[]}"#,
+                "\n",
+            ),
+        )?;
+        fs::write(
+            output.join("Disease.txt"),
+            concat!(
+                r#"{"curie":"MONDO:1","names":["new"],"types":[],"taxa":[]}"#,
+                "\n",
+                r#"{"curie":"MONDO:3","names":["c"],"types":[],"taxa":[]}"#,
+                "\n",
+            ),
+        )?;
+        fs::write(
+            output.join("Gene.txt"),
+            concat!(r#"{"curie":"NCBIGene:1","names":["d"],"types":[],"taxa":[]}"#, "\n"),
+        )?;
+        Ok((dir, existing, output))
+    }
+
+    /// The `(curie, first name)` of each node in a file, in order
+    fn nodes(path: Utf8PathBuf) -> io::Result<Vec<(String, String)>> {
+        Ok(fs::read_to_string(path)?
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                (
+                    String::from(value["curie"].as_str().unwrap()),
+                    String::from(value["names"][0].as_str().unwrap()),
+                )
+            })
+            .collect())
+    }
+
+    fn node(curie: &str, name: &str) -> (String, String) {
+        (String::from(curie), String::from(name))
+    }
+
+    #[test]
+    fn keeps_existing_nodes_on_conflict() -> io::Result<()> {
+        let (dir, existing, output) = directories()?;
+
+        let report = merge_into(&output, &existing, ConflictPolicy::KeepExisting, b'\n').unwrap();
+
+        assert_eq!(
+            report,
+            MergeReport {
+                num_added: 2,
+                num_replaced: 0,
+                num_skipped: 1
+            }
+        );
+        assert_eq!(
+            nodes(existing.join("Disease.txt"))?,
+            vec![node("MONDO:1", "old"), node("MONDO:2", "b"), node("MONDO:3", "c")]
+        );
+        assert_eq!(nodes(existing.join("Gene.txt"))?, vec![node("NCBIGene:1", "d")]);
+
+        dir.close()
+    }
+
+    #[test]
+    fn keeps_new_nodes_on_conflict() -> io::Result<()> {
+        let (dir, existing, output) = directories()?;
+
+        let report = merge_into(&output, &existing, ConflictPolicy::KeepNew, b'\n').unwrap();
+
+        assert_eq!(
+            report,
+            MergeReport {
+                num_added: 2,
+                num_replaced: 1,
+                num_skipped: 0
+            }
+        );
+        assert_eq!(
+            nodes(existing.join("Disease.txt"))?,
+            vec![node("MONDO:2", "b"), node("MONDO:1", "new"), node("MONDO:3", "c")]
+        );
+
+        dir.close()
+    }
+
+    #[test]
+    fn fails_on_conflict_without_writing() -> io::Result<()> {
+        let (dir, existing, output) = directories()?;
+        let before = fs::read_to_string(existing.join("Disease.txt"))?;
+
+        let result = merge_into(&output, &existing, ConflictPolicy::Error, b'\n');
+
+        assert!(result.unwrap_err().contains("MONDO:1"));
+        assert_eq!(fs::read_to_string(existing.join("Disease.txt"))?, before);
+        assert!(!existing.join("Gene.txt").exists());
+
+        dir.close()
+    }
+}
@@ -0,0 +1,512 @@
+use ahash::{AHashMap, AHashSet};
+use camino::Utf8Path;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::file::{reader::Reader, writer::Writer};
+use crate::filter_set;
+use crate::provenance::OutputMeta;
+use crate::stats::curie_prefix;
+use crate::transform::Transforms;
+use crate::{BabelJson, OutputFormat, BUF_CAPACITY};
+
+/// The name of the optional file in the Babel directory that overrides `--output-format` for
+/// individual input files
+pub const FORMATS_FILE: &str = "formats.toml";
+
+/// Encodes a kept Babel node as an output line, without the trailing newline. Compression is up
+/// to the `Writer`, so serializers only decide what a line looks like.
+pub trait OutputSerializer: Sync {
+    /// Serializes a node given both its parsed form and the raw line it was parsed from
+    fn serialize(&self, node: &BabelJson, raw: &str) -> serde_json::Result<Vec<u8>>;
+
+    /// Like `serialize`, but writes `curie` in place of the node's own curie
+    fn serialize_as(
+        &self,
+        node: &BabelJson,
+        raw: &str,
+        curie: &str,
+    ) -> serde_json::Result<Vec<u8>>;
+}
+
+/// Writes kept lines exactly as they were read
+pub struct VerbatimSerializer;
+
+impl OutputSerializer for VerbatimSerializer {
+    fn serialize(&self, _node: &BabelJson, raw: &str) -> serde_json::Result<Vec<u8>> {
+        Ok(raw.as_bytes().to_vec())
+    }
+
+    /// Replaces only the line's curie. The other keys keep their order only with the
+    /// `preserve-key-order` feature; without it every key comes out sorted.
+    fn serialize_as(
+        &self,
+        _node: &BabelJson,
+        raw: &str,
+        curie: &str,
+    ) -> serde_json::Result<Vec<u8>> {
+        let mut object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(raw)?;
+        object.insert(String::from("curie"), serde_json::Value::from(curie));
+        serde_json::to_vec(&object)
+    }
+}
+
+/// Re-serializes kept lines after applying the transforms
+pub struct TransformingSerializer<'a> {
+    pub transforms: &'a Transforms,
+}
+
+impl OutputSerializer for TransformingSerializer<'_> {
+    fn serialize(&self, _node: &BabelJson, raw: &str) -> serde_json::Result<Vec<u8>> {
+        self.transforms.rewrite_line(raw).map(String::into_bytes)
+    }
+
+    fn serialize_as(
+        &self,
+        _node: &BabelJson,
+        raw: &str,
+        curie: &str,
+    ) -> serde_json::Result<Vec<u8>> {
+        self.transforms.rewrite_line_as(raw, curie).map(String::into_bytes)
+    }
+}
+
+/// Writes kept nodes as CSV or TSV rows of curie, preferred name, types and taxa, with the types
+/// and taxa joined by `list_delimiter`. Transforms are applied to the node before it's written.
+pub struct TabularSerializer<'a> {
+    delimiter: u8,
+    list_delimiter: &'a str,
+    transforms: &'a Transforms,
+}
+
+impl<'a> TabularSerializer<'a> {
+    /// The columns of every row, in order
+    pub const COLUMNS: [&'static str; 4] = ["curie", "preferred_name", "types", "taxa"];
+
+    /// Returns the serializer for a tabular format, or `None` if `format` writes JSON lines
+    pub fn for_format(
+        format: OutputFormat,
+        list_delimiter: &'a str,
+        transforms: &'a Transforms,
+    ) -> Option<TabularSerializer<'a>> {
+        let delimiter = match format {
+            OutputFormat::Csv => b',',
+            OutputFormat::Tsv => b'\t',
+            OutputFormat::Gzipped
+            | OutputFormat::Bzip2
+            | OutputFormat::Plaintext
+            | OutputFormat::Zstd => return None,
+        };
+        Some(TabularSerializer {
+            delimiter,
+            list_delimiter,
+            transforms,
+        })
+    }
+
+    /// Returns the header row
+    pub fn header(&self) -> io::Result<String> {
+        self.record(&TabularSerializer::COLUMNS)
+    }
+
+    /// Returns a node's row with `curie` in place of its own curie
+    pub fn row_as(&self, node: &BabelJson, curie: &str) -> io::Result<String> {
+        let transformed;
+        let node = if self.transforms.is_empty() {
+            node
+        } else {
+            let mut node = node.clone();
+            self.transforms.apply(&mut node);
+            transformed = node;
+            &transformed
+        };
+        self.record(&[
+            curie,
+            node.preferred_name.as_deref().unwrap_or_default(),
+            &node.types.join(self.list_delimiter),
+            &node.taxa.join(self.list_delimiter),
+        ])
+    }
+
+    /// Quotes and joins the fields of one row, without the trailing newline
+    fn record(&self, fields: &[&str]) -> io::Result<String> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(Vec::new());
+        writer.write_record(fields)?;
+        let mut row = writer.into_inner().map_err(csv::IntoInnerError::into_error)?;
+        row.pop();
+        String::from_utf8(row).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl OutputSerializer for TabularSerializer<'_> {
+    fn serialize(&self, node: &BabelJson, raw: &str) -> serde_json::Result<Vec<u8>> {
+        self.serialize_as(node, raw, &node.curie)
+    }
+
+    fn serialize_as(
+        &self,
+        node: &BabelJson,
+        _raw: &str,
+        curie: &str,
+    ) -> serde_json::Result<Vec<u8>> {
+        self.row_as(node, curie)
+            .map(String::into_bytes)
+            .map_err(serde_json::Error::io)
+    }
+}
+
+/// Returns the serializer for a run: lines are copied verbatim unless there are transforms to
+/// apply
+pub fn select_serializer(transforms: &Transforms) -> Box<dyn OutputSerializer + '_> {
+    if transforms.is_empty() {
+        Box::new(VerbatimSerializer)
+    } else {
+        Box::new(TransformingSerializer { transforms })
+    }
+}
+
+/// Loads a TOML table of Babel file name to output format, e.g. `"Gene.txt.gz" = "plaintext"`.
+/// A missing file is an empty table. The file may be compressed, like the Babel files.
+///
+/// Returns `Err` with a message if the file can't be read or parsed
+pub fn load_output_formats<P>(path: P) -> Result<AHashMap<String, OutputFormat>, String>
+where
+    P: AsRef<Path>,
+{
+    let mut contents = String::new();
+    match Reader::new(path, BUF_CAPACITY) {
+        Ok(reader) => {
+            reader.into_inner().read_to_string(&mut contents).map_err(|e| e.to_string())?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(AHashMap::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    toml::from_str::<HashMap<String, OutputFormat>>(&contents)
+        .map(|formats| formats.into_iter().collect())
+        .map_err(|e| e.to_string())
+}
+
+/// Returns `path` with the compression extension `format` calls for (none, `.gz`, `.bz2` or
+/// `.zst`), or with its extension replaced by `.csv` or `.tsv` for tabular formats,
+/// replacing any compression extension it already has, e.g. `Gene.txt.gz` as zstd is
+/// `Gene.txt.zst`
+pub fn with_format_extension(path: &Path, format: OutputFormat) -> PathBuf {
+    let extension = path.extension().and_then(OsStr::to_str);
+    let is_compressed = matches!(extension, Some("gz" | "bz2" | "zst"));
+    let mut path = if is_compressed {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
+    let extension = match format {
+        OutputFormat::Plaintext => return path,
+        OutputFormat::Gzipped => ".gz",
+        OutputFormat::Bzip2 => ".bz2",
+        OutputFormat::Zstd => ".zst",
+        // rows aren't JSON lines, so they replace the input's extension too
+        OutputFormat::Csv => return path.with_extension("csv"),
+        OutputFormat::Tsv => return path.with_extension("tsv"),
+    };
+    path.as_mut_os_string().push(extension);
+    path
+}
+
+/// Writes ids to `path`, one per line
+///
+/// Returns `Err` if there is a problem writing the file
+pub fn write_ids<P: AsRef<Path>>(path: P, ids: &[String]) -> io::Result<()> {
+    let mut writer = Writer::new(path, BUF_CAPACITY)?;
+    for id in ids {
+        writer.write_line(id)?;
+    }
+    writer.finish()
+}
+
+/// Deletes the Babel-derived output files in `directory` that aren't in `written`, along with
+/// their metadata sidecars, returning the deleted output paths sorted. A file named after one of
+/// `input_names` (whatever its part number or format extension) is never deleted, and neither are
+/// the `side_outputs`, e.g. a names index written to the same directory.
+///
+/// Returns `Err` if the directory can't be read or a file can't be deleted
+pub fn prune_orphans(
+    directory: &Utf8Path,
+    written: &AHashSet<PathBuf>,
+    input_names: &[String],
+    side_outputs: &[&Utf8Path],
+) -> io::Result<Vec<PathBuf>> {
+    let input_stems: AHashSet<&str> = input_names.iter().map(|name| output_stem(name)).collect();
+    let side_outputs: Vec<PathBuf> =
+        side_outputs.iter().filter_map(|path| fs::canonicalize(path).ok()).collect();
+    let mut orphans: Vec<PathBuf> = filter_set::output_files(directory)?
+        .into_iter()
+        .filter(|path| !written.contains(path))
+        .filter(|path| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            !input_stems.contains(output_stem(&file_name))
+        })
+        .filter(|path| {
+            !fs::canonicalize(path).is_ok_and(|path| side_outputs.contains(&path))
+        })
+        .collect();
+    orphans.sort_unstable();
+    for orphan in &orphans {
+        fs::remove_file(orphan)?;
+        let sidecar = OutputMeta::sidecar_path(orphan);
+        if sidecar.is_file() {
+            fs::remove_file(sidecar)?;
+        }
+    }
+    Ok(orphans)
+}
+
+/// The part of an output's file name before any part number or extension, which is the same for
+/// every output of one input
+fn output_stem(file_name: &str) -> &str {
+    file_name.split_once('.').map_or(file_name, |(stem, _)| stem)
+}
+
+/// Returns a `(lowercased name, curie)` pair for each of a node's names
+pub fn names_index_pairs(node: &BabelJson) -> Vec<(String, String)> {
+    node.names
+        .iter()
+        .map(|name| (name.to_lowercase(), node.curie.clone()))
+        .collect()
+}
+
+/// Writes `lowercased_name\tcurie` lines, sorted, to the names index at `path`. Returns the
+/// number of lines written.
+///
+/// Returns `Err` if there is a problem writing the file
+pub fn write_names_index<P: AsRef<Path>>(
+    path: P,
+    pairs: AHashSet<(String, String)>,
+) -> io::Result<usize> {
+    let mut pairs: Vec<(String, String)> = pairs.into_iter().collect();
+    pairs.sort_unstable();
+    let mut writer = Writer::new(path, BUF_CAPACITY)?;
+    for (name, curie) in &pairs {
+        writer.write_line(&format!("{name}\t{curie}"))?;
+    }
+    writer.finish()?;
+    Ok(pairs.len())
+}
+
+/// Buffers a file's kept lines, keeping only the node with the most names for each curie prefix.
+/// This is a lossy deduplication: every other node sharing the prefix is dropped, and the whole
+/// file's largest nodes are held in memory until the file is done.
+#[derive(Default)]
+pub struct LargestPerPrefix<L = String> {
+    /// Prefixes in the order they were first seen, so output order is deterministic
+    prefixes: Vec<String>,
+    largest: AHashMap<String, (usize, L)>,
+}
+
+impl<L> LargestPerPrefix<L> {
+    /// Offers a kept line. It replaces the current line for its prefix only if it has strictly more
+    /// names, so ties keep the first node seen
+    pub fn offer(&mut self, curie: &str, num_names: usize, line: L) {
+        let prefix = curie_prefix(curie);
+        match self.largest.get_mut(prefix) {
+            Some(largest) => {
+                if num_names > largest.0 {
+                    *largest = (num_names, line);
+                }
+            }
+            None => {
+                self.prefixes.push(String::from(prefix));
+                self.largest.insert(String::from(prefix), (num_names, line));
+            }
+        }
+    }
+
+    /// Returns the kept line for each prefix, in the order the prefixes were first seen
+    pub fn into_lines(mut self) -> impl Iterator<Item = L> {
+        self.prefixes
+            .into_iter()
+            .filter_map(move |prefix| self.largest.remove(&prefix))
+            .map(|(_, line)| line)
+    }
+}
+
+/// Buffers a file's kept lines so they can be written grouped by curie prefix, with prefixes in
+/// the order they were first seen and lines in their input order within each prefix. Unlike
+/// `LargestPerPrefix` nothing is dropped, so every kept line of the file is held in memory until
+/// the file is done.
+#[derive(Default)]
+pub struct GroupedByPrefix<L = String> {
+    prefixes: Vec<String>,
+    groups: AHashMap<String, Vec<L>>,
+}
+
+impl<L> GroupedByPrefix<L> {
+    /// Adds a kept line to the end of its prefix's group
+    pub fn push(&mut self, curie: &str, line: L) {
+        let prefix = curie_prefix(curie);
+        match self.groups.get_mut(prefix) {
+            Some(group) => group.push(line),
+            None => {
+                self.prefixes.push(String::from(prefix));
+                self.groups.insert(String::from(prefix), vec![line]);
+            }
+        }
+    }
+
+    /// Returns every line, grouped by prefix in the order the prefixes were first seen
+    pub fn into_lines(mut self) -> impl Iterator<Item = L> {
+        self.prefixes
+            .into_iter()
+            .filter_map(move |prefix| self.groups.remove(&prefix))
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use std::{io, path::Path};
+
+    use super::{
+        load_output_formats, with_format_extension, GroupedByPrefix, LargestPerPrefix,
+        OutputSerializer, TabularSerializer, TransformingSerializer, VerbatimSerializer,
+    };
+    use crate::file::writer::Writer;
+    use crate::transform::Transforms;
+    use crate::{BabelJson, OutputFormat};
+
+    fn node(line: &str) -> BabelJson {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn swaps_compression_extension() {
+        let cases = [
+            ("Gene.txt.gz", OutputFormat::Zstd, "Gene.txt.zst"),
+            ("Gene.txt.zst", OutputFormat::Gzipped, "Gene.txt.gz"),
+            ("Gene.txt.zst", OutputFormat::Plaintext, "Gene.txt"),
+            ("Gene.txt", OutputFormat::Zstd, "Gene.txt.zst"),
+            ("Gene.txt.bz2", OutputFormat::Gzipped, "Gene.txt.gz"),
+            ("Gene.txt.gz", OutputFormat::Bzip2, "Gene.txt.bz2"),
+            ("Gene.txt.gz", OutputFormat::Gzipped, "Gene.txt.gz"),
+            ("Gene.txt.gz", OutputFormat::Csv, "Gene.csv"),
+            ("Gene.txt", OutputFormat::Tsv, "Gene.tsv"),
+        ];
+        for (path, format, expected) in cases {
+            assert_eq!(with_format_extension(Path::new(path), format), Path::new(expected));
+        }
+    }
+
+    #[test]
+    fn tabular_serializer_writes_quoted_rows() {
+        let transforms = Transforms::default();
+        let csv = TabularSerializer::for_format(OutputFormat::Csv, "|", &transforms).unwrap();
+        let line = concat!(
+            r#"{"curie":"MONDO:1","names":["a"],"preferred_name":"a, \"b\"","#,
+            r#""types":["Disease","Entity"],"taxa":[]}"#
+        );
+
+        assert_eq!(csv.header().unwrap(), "curie,preferred_name,types,taxa");
+        assert_eq!(
+            csv.serialize(&node(line), line).unwrap(),
+            br#"MONDO:1,"a, ""b""",Disease|Entity,"#
+        );
+        assert!(TabularSerializer::for_format(OutputFormat::Zstd, "|", &transforms).is_none());
+    }
+
+    #[test]
+    fn verbatim_serializer_copies_raw_line() {
+        let line = r#"{"curie":"MONDO:1", "names":["a"],"types":[],"taxa":[],"extra":1}"#;
+
+        assert_eq!(VerbatimSerializer.serialize(&node(line), line).unwrap(), line.as_bytes());
+    }
+
+    #[test]
+    fn serializers_write_mapped_curie() {
+        let transforms = Transforms::default();
+        let line = r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[],"extra":1}"#;
+        let node = node(line);
+        let tsv = TabularSerializer::for_format(OutputFormat::Tsv, "|", &transforms).unwrap();
+        let serializers: [&dyn OutputSerializer; 3] = [
+            &VerbatimSerializer,
+            &TransformingSerializer { transforms: &transforms },
+            &tsv,
+        ];
+
+        let outputs: Vec<Vec<u8>> = serializers
+            .iter()
+            .map(|serializer| serializer.serialize_as(&node, line, "DOID:1").unwrap())
+            .collect();
+
+        for output in &outputs[..2] {
+            let output: serde_json::Value = serde_json::from_slice(output).unwrap();
+            assert_eq!(output["curie"], json!("DOID:1"));
+            assert_eq!(output["extra"], json!(1));
+        }
+        assert_eq!(outputs[2], b"DOID:1\t\tDisease\t");
+    }
+
+    #[test]
+    fn transforming_serializer_applies_transforms() {
+        let transforms = Transforms {
+            max_output_names: Some(1),
+            ..Default::default()
+        };
+        let line = r#"{"curie":"MONDO:1","names":["a","b"],"types":[],"taxa":[],"extra":1}"#;
+
+        let output = TransformingSerializer { transforms: &transforms }
+            .serialize(&node(line), line)
+            .unwrap();
+
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output["names"], json!(["a"]));
+        assert_eq!(output["extra"], json!(1));
+    }
+
+    #[test]
+    fn keeps_node_with_most_names_per_prefix() {
+        let mut largest = LargestPerPrefix::default();
+        largest.offer("MONDO:1", 1, String::from("mondo one name"));
+        largest.offer("CHEBI:1", 2, String::from("chebi"));
+        largest.offer("MONDO:2", 3, String::from("mondo three names"));
+        largest.offer("MONDO:3", 3, String::from("mondo tie"));
+
+        let lines: Vec<String> = largest.into_lines().collect();
+
+        assert_eq!(lines, vec!["mondo three names", "chebi"]);
+    }
+
+    #[test]
+    fn groups_lines_by_prefix_in_input_order() {
+        let mut grouped = GroupedByPrefix::default();
+        for curie in ["MONDO:2", "CHEBI:9", "MONDO:1", "HP:1", "CHEBI:3"] {
+            grouped.push(curie, String::from(curie));
+        }
+
+        let lines: Vec<String> = grouped.into_lines().collect();
+
+        assert_eq!(lines, vec!["MONDO:2", "MONDO:1", "CHEBI:9", "CHEBI:3", "HP:1"]);
+    }
+
+    #[test]
+    fn loads_compressed_output_formats() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("formats.toml.gz");
+        assert!(load_output_formats(&path).unwrap().is_empty());
+
+        Writer::new(&path, 32_000)?.write_line(r#""Gene.txt.gz" = "plaintext""#)?;
+        let formats = load_output_formats(&path).unwrap();
+
+        assert_eq!(formats.len(), 1);
+        assert!(matches!(formats.get("Gene.txt.gz"), Some(OutputFormat::Plaintext)));
+
+        dir.close()
+    }
+}
@@ -0,0 +1,56 @@
+use std::{sync::Mutex, thread};
+
+/// Calls `f` on each item from up to `concurrency` scoped threads at once, so no more than
+/// `concurrency` items are being processed at any time. A concurrency of 0 is treated as 1.
+/// Items are started in order, but may finish in any order.
+///
+/// Panics if a call to `f` panics, once every thread has finished
+pub fn for_each_bounded<T, F>(items: Vec<T>, concurrency: usize, f: F)
+where
+    T: Send,
+    F: Fn(T) + Sync,
+{
+    let num_threads = concurrency.max(1).min(items.len());
+    let items = Mutex::new(items.into_iter());
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                // the lock guard is dropped at the end of this statement, before calling `f`
+                let Some(item) = items.lock().unwrap().next() else {
+                    break;
+                };
+                f(item);
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::for_each_bounded;
+
+    #[test]
+    fn runs_at_most_concurrency_items_at_once() {
+        let open = AtomicUsize::new(0);
+        let max_open = AtomicUsize::new(0);
+        let processed = AtomicUsize::new(0);
+
+        for_each_bounded((0..20).collect(), 3, |_: usize| {
+            let now_open = open.fetch_add(1, Ordering::SeqCst) + 1;
+            max_open.fetch_max(now_open, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            open.fetch_sub(1, Ordering::SeqCst);
+            processed.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(processed.into_inner(), 20);
+        let max_open = max_open.into_inner();
+        assert!(max_open <= 3, "{max_open} items processed at once");
+    }
+}
@@ -0,0 +1,329 @@
+use ahash::AHashMap;
+use std::{
+    fmt,
+    io::{self, IsTerminal, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::file::{reader::Reader, writer::Writer};
+use crate::BUF_CAPACITY;
+
+/// Prints progress messages, which go to stdout alongside the final summary or to stderr so that
+/// stdout only contains the summary. A quiet `Progress` prints neither.
+#[derive(Clone, Copy)]
+pub struct Progress {
+    to_stderr: bool,
+    quiet: bool,
+    /// Whether progress messages are printed at all, unlike summary lines
+    show_progress: bool,
+    /// Whether summary lines go to stderr too, when stdout is taken by the output
+    summary_to_stderr: bool,
+}
+
+impl Progress {
+    pub fn new(to_stderr: bool) -> Progress {
+        Progress {
+            to_stderr,
+            quiet: false,
+            show_progress: true,
+            summary_to_stderr: false,
+        }
+    }
+
+    /// Sets whether progress messages and summary lines are suppressed
+    pub fn with_quiet(mut self, quiet: bool) -> Progress {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Sets whether progress messages and line reports are printed. Summary lines still are.
+    pub fn with_progress(mut self, show_progress: bool) -> Progress {
+        self.show_progress = show_progress;
+        self
+    }
+
+    /// Sends summary lines to stderr as well as progress messages, so nothing is printed to stdout
+    pub fn with_summary_to_stderr(mut self, summary_to_stderr: bool) -> Progress {
+        self.summary_to_stderr = summary_to_stderr;
+        self.to_stderr |= summary_to_stderr;
+        self
+    }
+
+    /// Prints a line of the summary to stdout, e.g. `progress.info(format_args!("Kept {n}"))`
+    pub fn info(&self, message: fmt::Arguments) {
+        if self.quiet {
+            return;
+        }
+        if self.summary_to_stderr {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+    }
+
+    /// Prints a progress message, e.g. `progress.log(format_args!("Read {n} lines"))`
+    pub fn log(&self, message: fmt::Arguments) {
+        if self.quiet || !self.show_progress {
+            return;
+        }
+        if self.to_stderr {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+    }
+
+    /// Starts counting lines read from `name`, reporting every `interval` lines to the same
+    /// stream as other progress messages. Reports are rewritten in place if `in_place` is set
+    /// and that stream is a terminal. A quiet `Progress`, or one without progress messages, never
+    /// reports.
+    pub fn lines(&self, name: String, interval: usize, in_place: bool) -> LineProgress {
+        let interval = if self.quiet || !self.show_progress { 0 } else { interval };
+        let (out, is_terminal): (Box<dyn Write>, bool) = if self.to_stderr {
+            (Box::new(io::stderr()), io::stderr().is_terminal())
+        } else {
+            (Box::new(io::stdout()), io::stdout().is_terminal())
+        };
+        LineProgress::with_writer(out, name, interval, in_place && is_terminal)
+    }
+}
+
+/// Loads a TSV of `file name<TAB>line count` lines giving the number of lines in each Babel file,
+/// skipping blank lines. It may be compressed, like the Babel files.
+///
+/// Returns `Err` with a message if the file can't be read or a line isn't a name and a count
+pub fn load_line_counts<P: AsRef<Path>>(path: P) -> Result<AHashMap<String, usize>, String> {
+    let lines = Reader::new(path, BUF_CAPACITY).map_err(|e| e.to_string())?.lines();
+    let mut line_counts = AHashMap::new();
+    for (line_index, line) in lines.enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let count = line
+            .split_once('\t')
+            .and_then(|(name, count)| Some((name.trim(), count.trim().parse().ok()?)));
+        let Some((name, count)) = count else {
+            return Err(format!("line {} isn't a file name and a line count", line_index + 1));
+        };
+        line_counts.insert(String::from(name), count);
+    }
+    Ok(line_counts)
+}
+
+/// Periodically reports how many lines of a file have been read and how long that took, so a
+/// long file visibly makes progress. If the file's total line count is known, reports also give
+/// the percentage read. Reports go on their own lines unless they're rewritten in place, which
+/// only makes sense on a terminal.
+pub struct LineProgress<W: Write = Box<dyn Write>> {
+    out: W,
+    name: String,
+    interval: usize,
+    in_place: bool,
+    total: Option<usize>,
+    start: Instant,
+    reported: bool,
+}
+
+impl<W: Write> LineProgress<W> {
+    /// An interval of 0 never reports
+    pub fn with_writer(out: W, name: String, interval: usize, in_place: bool) -> LineProgress<W> {
+        LineProgress {
+            out,
+            name,
+            interval,
+            in_place,
+            total: None,
+            start: Instant::now(),
+            reported: false,
+        }
+    }
+
+    /// Sets the number of lines in the file, if it's known
+    pub fn with_total(mut self, total: Option<usize>) -> LineProgress<W> {
+        self.total = total;
+        self
+    }
+
+    /// Records that `num_lines` lines have been read so far, reporting it every `interval` lines
+    pub fn tick(&mut self, num_lines: usize) {
+        if self.interval == 0 || !num_lines.is_multiple_of(self.interval) {
+            return;
+        }
+        let elapsed = self.start.elapsed();
+        let read = match self.total {
+            // a stale count can be smaller than the file
+            Some(total) if total > 0 => format!(
+                "{num_lines} of {total} lines ({:.1}%)",
+                (num_lines as f64 / total as f64 * 100.0).min(100.0)
+            ),
+            _ => format!("{num_lines} lines"),
+        };
+        let _ = if self.in_place {
+            write!(self.out, "\r{}: read {read} in {elapsed:.2?}", self.name)
+                .and_then(|_| self.out.flush())
+        } else {
+            writeln!(self.out, "{}: read {read} in {elapsed:.2?}", self.name)
+        };
+        self.reported = true;
+    }
+
+    /// Ends an in-place report's line so the next message starts on its own line
+    pub fn finish(&mut self) {
+        if self.in_place && self.reported {
+            let _ = writeln!(self.out);
+        }
+    }
+}
+
+/// Prints the errors found while reading one file to stderr, going quiet after `limit` errors (if
+/// set) but still counting them
+pub struct ErrorLog<W: Write = io::Stderr> {
+    out: W,
+    limit: Option<usize>,
+    num_errors: usize,
+}
+
+impl ErrorLog {
+    pub fn new(limit: Option<usize>) -> ErrorLog {
+        ErrorLog::with_writer(io::stderr(), limit)
+    }
+}
+
+impl<W: Write> ErrorLog<W> {
+    pub fn with_writer(out: W, limit: Option<usize>) -> ErrorLog<W> {
+        ErrorLog {
+            out,
+            limit,
+            num_errors: 0,
+        }
+    }
+
+    /// Counts an error, printing it unless the limit has been reached
+    pub fn log(&mut self, message: fmt::Arguments) {
+        self.num_errors += 1;
+        if self.num_errors <= self.limit.unwrap_or(usize::MAX) {
+            let _ = writeln!(self.out, "{message}");
+        }
+    }
+
+    /// Prints how many errors in `source` weren't printed, if any
+    pub fn finish(&mut self, source: fmt::Arguments) {
+        let num_suppressed = self.limit.map_or(0, |limit| self.num_errors.saturating_sub(limit));
+        if num_suppressed > 0 {
+            let _ = writeln!(
+                self.out,
+                "...and {num_suppressed} more errors suppressed in {source} ({} in total)",
+                self.num_errors
+            );
+        }
+    }
+}
+
+/// Keeps the raw lines that failed to parse, from the filter files or the Babel files, as
+/// `file name<TAB>line number<TAB>line` lines
+pub struct FailedLines {
+    writer: Mutex<Writer>,
+}
+
+impl FailedLines {
+    /// Returns `Err` if there is a problem creating the file
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<FailedLines> {
+        Ok(FailedLines {
+            writer: Mutex::new(Writer::new(path, BUF_CAPACITY)?),
+        })
+    }
+
+    /// Writes `line`, the 1-based `line_number` of `source`, verbatim after its source
+    ///
+    /// Returns `Err` if there is a problem writing to the file
+    pub fn record(&self, source: &str, line_number: usize, line: &str) -> io::Result<()> {
+        self.writer
+            .lock()
+            .unwrap()
+            .write_line(&format!("{source}\t{line_number}\t{line}"))
+            .map(|_| ())
+    }
+
+    /// Returns `Err` if there is a problem writing the rest of the file
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.into_inner().unwrap().finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io};
+    use tempfile::tempdir;
+
+    use super::{load_line_counts, ErrorLog, LineProgress};
+    use crate::file::writer::Writer;
+
+    #[test]
+    fn stops_printing_errors_after_limit() {
+        let mut errors = ErrorLog::with_writer(Vec::new(), Some(2));
+        for i in 1..=5 {
+            errors.log(format_args!("bad line {i}"));
+        }
+        errors.finish(format_args!("Gene.txt"));
+
+        assert_eq!(
+            String::from_utf8(errors.out).unwrap(),
+            "bad line 1\nbad line 2\n...and 3 more errors suppressed in Gene.txt (5 in total)\n"
+        );
+    }
+
+    #[test]
+    fn reports_every_interval_lines() {
+        let name = String::from("Gene.txt");
+        let mut progress = LineProgress::with_writer(Vec::new(), name, 2, false);
+        for num_lines in 1..=5 {
+            progress.tick(num_lines);
+        }
+        progress.finish();
+
+        let out = String::from_utf8(progress.out).unwrap();
+        let reports: Vec<&str> = out.lines().collect();
+        assert_eq!(reports.len(), 2);
+        assert!(reports[0].starts_with("Gene.txt: read 2 lines in "));
+        assert!(reports[1].starts_with("Gene.txt: read 4 lines in "));
+    }
+
+    #[test]
+    fn reports_percentage_of_known_total() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("counts.tsv");
+        fs::write(&path, "Gene.txt.gz\t4\n\nDisease.txt.gz\t10\n")?;
+        let line_counts = load_line_counts(&path).unwrap();
+
+        let name = String::from("Gene.txt.gz");
+        let total = line_counts.get(&name).copied();
+        let mut progress = LineProgress::with_writer(Vec::new(), name, 2, false).with_total(total);
+        progress.tick(2);
+        progress.tick(4);
+        let name = String::from("Other.txt");
+        let total = line_counts.get(&name).copied();
+        let mut uncounted = LineProgress::with_writer(Vec::new(), name, 2, false).with_total(total);
+        uncounted.tick(2);
+
+        let out = String::from_utf8(progress.out).unwrap();
+        let reports: Vec<&str> = out.lines().collect();
+        assert!(reports[0].starts_with("Gene.txt.gz: read 2 of 4 lines (50.0%) in "));
+        assert!(reports[1].starts_with("Gene.txt.gz: read 4 of 4 lines (100.0%) in "));
+        let out = String::from_utf8(uncounted.out).unwrap();
+        assert!(out.starts_with("Other.txt: read 2 lines in "));
+
+        fs::write(&path, "Gene.txt.gz\tmany\n")?;
+        assert_eq!(
+            load_line_counts(&path).unwrap_err(),
+            "line 1 isn't a file name and a line count"
+        );
+
+        let path = dir.path().join("counts.tsv.gz");
+        Writer::new(&path, 32_000)?.write_line("Gene.txt.gz\t4")?;
+        assert_eq!(load_line_counts(&path).unwrap().get("Gene.txt.gz"), Some(&4));
+        dir.close()
+    }
+}
@@ -0,0 +1,157 @@
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Config;
+
+/// Computes the CRC32 checksum of the files' contents, one after another, formatted as 8 hex
+/// digits. For a single file this is just the file's checksum.
+///
+/// Returns `Err` if there is a problem reading a file
+pub fn files_crc32<P: AsRef<Path>>(paths: &[P]) -> io::Result<String> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    for path in paths {
+        let mut file = File::open(path)?;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+    }
+    Ok(format!("{:08x}", hasher.finalize()))
+}
+
+/// Sets the modification time of `path` to that of `source`
+///
+/// Returns `Err` if there is a problem reading `source`'s metadata or updating `path`
+pub fn copy_mtime<P: AsRef<Path>, Q: AsRef<Path>>(source: P, path: Q) -> io::Result<()> {
+    let modified = fs::metadata(source)?.modified()?;
+    File::options().write(true).open(path)?.set_modified(modified)
+}
+
+/// Per-output provenance written to a `.meta.json` sidecar next to the output file
+#[derive(Serialize)]
+pub struct OutputMeta<'a> {
+    pub input_file: &'a str,
+    pub nodes_read: usize,
+    pub nodes_kept: usize,
+    pub duration_ms: u128,
+    pub filter_file: &'a [Utf8PathBuf],
+    pub filter_file_crc32: &'a str,
+}
+
+impl OutputMeta<'_> {
+    /// Returns the sidecar path for an output file, i.e. the output path with `.meta.json` appended
+    pub fn sidecar_path<P: AsRef<Path>>(output_path: P) -> PathBuf {
+        let mut path = output_path.as_ref().as_os_str().to_os_string();
+        path.push(".meta.json");
+        PathBuf::from(path)
+    }
+
+    /// Writes the sidecar for `output_path`
+    ///
+    /// Returns `Err` if there is a problem writing the file
+    pub fn write<P: AsRef<Path>>(&self, output_path: P) -> io::Result<()> {
+        fs::write(
+            OutputMeta::sidecar_path(output_path),
+            serde_json::to_string_pretty(self)?,
+        )
+    }
+}
+
+/// A JSON line written at the top of each output file recording what produced it. Its
+/// `_provenance` field is always true so consumers can recognize and skip it.
+#[derive(Serialize)]
+pub struct ProvenanceHeader<'a> {
+    #[serde(rename = "_provenance")]
+    pub provenance: bool,
+    pub tool_version: &'static str,
+    pub filter_file: &'a [Utf8PathBuf],
+    pub filter_file_crc32: &'a str,
+    pub timestamp: String,
+    pub config: &'a Config,
+}
+
+impl ProvenanceHeader<'_> {
+    /// Creates the header for a run with the given config, timestamped now
+    pub fn new<'a>(config: &'a Config, filter_file_crc32: &'a str) -> ProvenanceHeader<'a> {
+        ProvenanceHeader {
+            provenance: true,
+            tool_version: env!("CARGO_PKG_VERSION"),
+            filter_file: &config.filter_file,
+            filter_file_crc32,
+            timestamp: utc_timestamp(SystemTime::now()),
+            config,
+        }
+    }
+}
+
+/// Just the flag of a provenance header, to recognize one among the lines of an output
+#[derive(Deserialize)]
+struct HeaderFlag {
+    #[serde(rename = "_provenance", default)]
+    provenance: bool,
+}
+
+/// Returns whether an output line is a provenance header, i.e. a JSON object whose `_provenance`
+/// field is true
+pub fn is_header(line: &str) -> bool {
+    serde_json::from_str::<HeaderFlag>(line).is_ok_and(|flag| flag.provenance)
+}
+
+/// Formats a time as a UTC ISO 8601 timestamp with second precision, e.g. `2023-10-24T17:03:09Z`
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{is_header, utc_timestamp};
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            utc_timestamp(UNIX_EPOCH + Duration::from_secs(1_709_210_096)),
+            "2024-02-29T12:34:56Z"
+        );
+    }
+
+    #[test]
+    fn recognizes_headers() {
+        assert!(is_header(r#"{"tool_version":"0.1.0", "_provenance": true}"#));
+        assert!(!is_header(r#"{"_provenance":false}"#));
+        assert!(!is_header(r#"{"curie":"MONDO:1","names":["_provenance"]}"#));
+        assert!(!is_header("not json"));
+    }
+}
@@ -0,0 +1,226 @@
+use camino::Utf8Path;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
+
+use crate::file::{
+    chunked::{part_path, unpart_name, ChunkedWriter},
+    reader::Reader,
+};
+use crate::provenance;
+use crate::BUF_CAPACITY;
+
+/// Splits every output file in `directory` into parts of `chunk_lines` lines without re-filtering.
+/// Files that are already split into parts are read back in order and re-split as one file, and
+/// each file keeps its compression since the parts keep its extensions. A provenance header is
+/// repeated at the top of every part. The original files are only removed once all their new parts
+/// are in place; if moving the parts into place fails, the staging directory is kept so none of
+/// the records are lost. Records are split on, and end with, `record_separator` (a newline by
+/// default).
+pub fn rechunk(directory: &Utf8Path, chunk_lines: usize, record_separator: Option<u8>) -> ExitCode {
+    let start = Instant::now();
+    let separator = record_separator.unwrap_or(b'\n');
+
+    if !directory.is_dir() {
+        eprintln!("The path provided to the output directory isn't a directory or doesn't exist");
+        return ExitCode::FAILURE;
+    }
+    if chunk_lines == 0 {
+        eprintln!("The number of lines per chunk must be greater than 0");
+        return ExitCode::FAILURE;
+    }
+
+    // group files by the name they were (or would be) split from
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error reading the output directory: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    for entry in entries {
+        match entry {
+            Ok(entry) => {
+                let path = entry.path();
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                if !path.is_file() || file_name.ends_with(".meta.json") {
+                    continue;
+                }
+                let base_name = unpart_name(&file_name).unwrap_or(file_name);
+                groups.entry(base_name).or_default().push(path);
+            }
+            Err(e) => eprintln!("Error opening file in output directory: {e}"),
+        }
+    }
+
+    let staging_directory = directory.join(".rechunk");
+    if let Err(e) = fs::create_dir_all(&staging_directory) {
+        eprintln!("Error creating the rechunk staging directory: {e}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut exit_code = ExitCode::SUCCESS;
+    // staged parts that couldn't be moved into place are the only copy of some records
+    let mut keep_staging = false;
+    for (base_name, mut inputs) in groups {
+        let t0 = Instant::now();
+        // part numbers are zero-padded, so sorting by name puts the parts in order
+        inputs.sort();
+        let staged_path = staging_directory.join(&base_name);
+        let result = rechunk_files(&inputs, staged_path.as_std_path(), chunk_lines, separator)
+            .and_then(|(num_lines, parts)| {
+                let mut moved = Vec::with_capacity(parts);
+                for part in 1..=parts {
+                    let staged_part = part_path(&staged_path, part);
+                    let file_name = staged_part.file_name().unwrap_or_default();
+                    let moved_part = directory.as_std_path().join(file_name);
+                    if let Err(e) = fs::rename(&staged_part, &moved_part) {
+                        keep_staging = true;
+                        return Err(e);
+                    }
+                    moved.push(moved_part);
+                }
+                // an input with the same name as a new part was already replaced by it
+                for input in inputs.iter().filter(|input| !moved.contains(input)) {
+                    fs::remove_file(input)?;
+                }
+                Ok((num_lines, parts))
+            });
+        match result {
+            Ok((num_lines, parts)) => println!(
+                "Rechunking {base_name} took {:.2?}, wrote {num_lines} lines to {parts} parts",
+                t0.elapsed()
+            ),
+            Err(e) => {
+                eprintln!("Error rechunking {base_name}: {e}");
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if keep_staging {
+        eprintln!(
+            "Kept the rechunk staging directory {staging_directory}, as it has parts that couldn't \
+             be moved into place"
+        );
+    } else if let Err(e) = fs::remove_dir_all(&staging_directory) {
+        eprintln!("Error removing the rechunk staging directory: {e}");
+    }
+
+    println!("Program took {:.2?}", start.elapsed());
+    exit_code
+}
+
+/// Writes the lines of each input, in order, to parts of `path`, starting every part with the
+/// provenance header the first input starts with, if any. The inputs are parts of one file, so
+/// any header they start with is dropped. Lines are split on and end with `separator`. Returns the
+/// number of lines (not counting headers) and the number of parts written.
+///
+/// Returns `Err` if an input can't be read or a part can't be written in full
+fn rechunk_files(
+    inputs: &[PathBuf],
+    path: &Path,
+    chunk_lines: usize,
+    separator: u8,
+) -> io::Result<(usize, usize)> {
+    let mut writer =
+        ChunkedWriter::new(path, Some(chunk_lines), BUF_CAPACITY)?.with_separator(separator);
+    let mut num_lines = 0;
+    for (input_index, input) in inputs.iter().enumerate() {
+        let lines = Reader::new(input, BUF_CAPACITY)?.with_separator(separator).lines();
+        for (line_index, line) in lines.enumerate() {
+            let line = line?;
+            if line_index == 0 && provenance::is_header(&line) {
+                if input_index == 0 {
+                    writer.set_header(line)?;
+                }
+                continue;
+            }
+            writer.write_line(&line)?;
+            num_lines += 1;
+        }
+    }
+    let parts = writer.parts();
+    writer.finish()?;
+    Ok((num_lines, parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use std::{fs, io, process::ExitCode};
+    use tempfile::tempdir;
+
+    use super::rechunk;
+
+    #[test]
+    fn rechunks_file_into_parts() -> io::Result<()> {
+        let dir = tempdir()?;
+        let directory = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        fs::write(directory.join("Gene.txt"), "a\nb\nc\nd\ne\n")?;
+        fs::write(directory.join("Disease.part0001.txt"), "f\ng\n")?;
+        fs::write(directory.join("Disease.part0002.txt"), "h\n")?;
+
+        assert_eq!(rechunk(&directory, 2, None), ExitCode::SUCCESS);
+
+        let mut names: Vec<String> = fs::read_dir(&directory)?
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "Disease.part0001.txt",
+                "Disease.part0002.txt",
+                "Gene.part0001.txt",
+                "Gene.part0002.txt",
+                "Gene.part0003.txt",
+            ]
+        );
+        assert_eq!(fs::read_to_string(directory.join("Disease.part0001.txt"))?, "f\ng\n");
+        assert_eq!(fs::read_to_string(directory.join("Disease.part0002.txt"))?, "h\n");
+        assert_eq!(fs::read_to_string(directory.join("Gene.part0003.txt"))?, "e\n");
+
+        dir.close()
+    }
+
+    #[test]
+    fn repeats_provenance_header_in_every_part() -> io::Result<()> {
+        let dir = tempdir()?;
+        let directory = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let header = r#"{"_provenance":true,"tool_version":"0.1.0"}"#;
+        fs::write(directory.join("Gene.part0001.txt"), format!("{header}\na\nb\n"))?;
+        fs::write(directory.join("Gene.part0002.txt"), format!("{header}\nc\n"))?;
+
+        assert_eq!(rechunk(&directory, 1, None), ExitCode::SUCCESS);
+
+        for (part, line) in [(1, "a"), (2, "b"), (3, "c")] {
+            assert_eq!(
+                fs::read_to_string(directory.join(format!("Gene.part000{part}.txt")))?,
+                format!("{header}\n{line}\n")
+            );
+        }
+        assert!(!directory.join(".rechunk").exists());
+
+        dir.close()
+    }
+
+    #[test]
+    fn rechunks_with_record_separator() -> io::Result<()> {
+        let dir = tempdir()?;
+        let directory = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        fs::write(directory.join("Gene.txt"), "a\nb\x1ec\x1ed\x1e")?;
+
+        assert_eq!(rechunk(&directory, 2, Some(0x1e)), ExitCode::SUCCESS);
+
+        assert_eq!(fs::read_to_string(directory.join("Gene.part0001.txt"))?, "a\nb\x1ec\x1e");
+        assert_eq!(fs::read_to_string(directory.join("Gene.part0002.txt"))?, "d\x1e");
+
+        dir.close()
+    }
+}
@@ -0,0 +1,99 @@
+/// Bare non-finite numbers some upstream Babel files contain, which aren't valid JSON
+const NON_FINITE: [&str; 3] = ["-Infinity", "Infinity", "NaN"];
+
+/// Repairs the JSON glitches a recurring upstream bug emits in Babel lines: trailing commas before
+/// a `}` or `]` are removed and bare `NaN`, `Infinity` and `-Infinity` are replaced with `null`.
+/// Strings are left untouched. This doesn't check that the result is valid JSON.
+///
+/// Returns `None` if there was nothing to repair
+pub fn repair_line(line: &str) -> Option<String> {
+    let bytes = line.as_bytes();
+    let mut repaired = String::with_capacity(line.len());
+    // bytes of `line` already copied into `repaired`, which is only 0 if nothing was repaired
+    let mut copied = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+            i += 1;
+            continue;
+        }
+
+        let repair = match byte {
+            b'"' => {
+                in_string = true;
+                None
+            }
+            b',' if matches!(next_non_whitespace(&bytes[i + 1..]), Some(b'}' | b']')) => {
+                Some((1, ""))
+            }
+            _ => NON_FINITE
+                .iter()
+                .find(|literal| bytes[i..].starts_with(literal.as_bytes()))
+                .map(|literal| (literal.len(), "null")),
+        };
+        match repair {
+            // every repaired token is ASCII, so `i` is always on a char boundary
+            Some((len, replacement)) => {
+                repaired.push_str(&line[copied..i]);
+                repaired.push_str(replacement);
+                i += len;
+                copied = i;
+            }
+            None => i += 1,
+        }
+    }
+
+    if copied == 0 {
+        return None;
+    }
+    repaired.push_str(&line[copied..]);
+    Some(repaired)
+}
+
+fn next_non_whitespace(bytes: &[u8]) -> Option<u8> {
+    bytes.iter().copied().find(|b| !b.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::repair_line;
+
+    #[test]
+    fn strips_trailing_commas() {
+        assert_eq!(
+            repair_line(r#"{"curie":"MONDO:1","names":["a",],"taxa":[ ] , }"#).as_deref(),
+            Some(r#"{"curie":"MONDO:1","names":["a"],"taxa":[ ]  }"#)
+        );
+    }
+
+    #[test]
+    fn nulls_non_finite_numbers() {
+        assert_eq!(
+            repair_line(r#"{"a":NaN,"b":[Infinity,-Infinity]}"#).as_deref(),
+            Some(r#"{"a":null,"b":[null,null]}"#)
+        );
+    }
+
+    #[test]
+    fn leaves_strings_alone() {
+        assert_eq!(repair_line(r#"{"names":["NaN,]","a\",}"]}"#), None);
+        assert_eq!(
+            repair_line(r#"{"names":["Infinity \\"],"a":NaN}"#).as_deref(),
+            Some(r#"{"names":["Infinity \\"],"a":null}"#)
+        );
+    }
+
+    #[test]
+    fn ignores_valid_lines() {
+        assert_eq!(repair_line(r#"{"curie":"MONDO:1","names":[],"taxa":[1.5, 2]}"#), None);
+    }
+}
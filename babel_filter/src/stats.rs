@@ -0,0 +1,375 @@
+use ahash::AHashMap;
+use serde::Serialize;
+use std::{collections::HashMap, fs, io, path::Path, time::Duration};
+
+/// Returns the prefix of a curie (the part before the first `:`), or the whole curie if it
+/// has no prefix
+pub fn curie_prefix(curie: &str) -> &str {
+    curie.split_once(':').map_or(curie, |(prefix, _)| prefix)
+}
+
+/// Returns the percentage of the filter set that was found in Babel, i.e. wasn't left over, or
+/// `None` if the filter set is empty
+pub fn coverage_percent(filter_set_size: usize, num_leftovers: usize) -> Option<f64> {
+    (filter_set_size > 0).then(|| {
+        filter_set_size.saturating_sub(num_leftovers) as f64 / filter_set_size as f64 * 100.0
+    })
+}
+
+/// Returns the percentage of `num_read` nodes that were kept, or 0 if none were read
+pub fn kept_percent(num_kept: usize, num_read: usize) -> f64 {
+    if num_read == 0 {
+        0.0
+    } else {
+        (num_kept as f64 / num_read as f64) * 100.0
+    }
+}
+
+/// Formats how many megabytes (10^6 bytes) and nodes were read per second over `elapsed`
+pub fn throughput(bytes_read: u64, num_nodes: usize, elapsed: Duration) -> String {
+    // a file can be read in well under the clock's resolution
+    let seconds = elapsed.as_secs_f64().max(1e-9);
+    format!(
+        "{:.2} MB/s, {:.0} nodes/s",
+        bytes_read as f64 / 1e6 / seconds,
+        num_nodes as f64 / seconds
+    )
+}
+
+#[derive(Default, Clone, Copy)]
+struct PrefixCount {
+    read: usize,
+    kept: usize,
+}
+
+impl PrefixCount {
+    fn keep_rate(&self) -> f64 {
+        if self.read == 0 {
+            0.0
+        } else {
+            self.kept as f64 / self.read as f64
+        }
+    }
+}
+
+/// Tallies of nodes read and kept, bucketed by curie prefix
+#[derive(Default)]
+pub struct PrefixStats {
+    counts: AHashMap<String, PrefixCount>,
+}
+
+impl PrefixStats {
+    /// Records a node read from a Babel file and whether it was kept
+    pub fn record(&mut self, curie: &str, kept: bool) {
+        let prefix = curie_prefix(curie);
+        let count = match self.counts.get_mut(prefix) {
+            Some(count) => count,
+            None => self.counts.entry(String::from(prefix)).or_default(),
+        };
+        count.read += 1;
+        if kept {
+            count.kept += 1;
+        }
+    }
+
+    /// Adds another set of tallies, e.g. from a different Babel file, to these
+    pub fn merge(&mut self, other: PrefixStats) {
+        for (prefix, other_count) in other.counts {
+            let count = self.counts.entry(prefix).or_default();
+            count.read += other_count.read;
+            count.kept += other_count.kept;
+        }
+    }
+
+    /// Returns `(prefix, read, kept)` rows sorted by lowest keep rate first, ties broken by prefix
+    pub fn rows(&self) -> Vec<(&str, usize, usize)> {
+        let mut rows: Vec<(&str, PrefixCount)> = self
+            .counts
+            .iter()
+            .map(|(prefix, count)| (prefix.as_str(), *count))
+            .collect();
+        rows.sort_by(|(a_prefix, a), (b_prefix, b)| {
+            a.keep_rate()
+                .total_cmp(&b.keep_rate())
+                .then_with(|| a_prefix.cmp(b_prefix))
+        });
+        rows.into_iter()
+            .map(|(prefix, count)| (prefix, count.read, count.kept))
+            .collect()
+    }
+
+    /// Prints a per-prefix keep-rate table, lowest keep rate first
+    pub fn print(&self) {
+        let rows = self.rows();
+        let width = rows
+            .iter()
+            .map(|(prefix, _, _)| prefix.len())
+            .max()
+            .unwrap_or(0)
+            .max("prefix".len());
+
+        println!("{:<width$}  {:>12}  {:>12}  {:>8}", "prefix", "read", "kept", "kept %");
+        for (prefix, read, kept) in rows {
+            println!(
+                "{:<width$}  {:>12}  {:>12}  {:>7.2}%",
+                prefix,
+                read,
+                kept,
+                (kept as f64 / read as f64) * 100.0
+            );
+        }
+    }
+}
+
+/// The `--report-json` summary of a run
+#[derive(Serialize)]
+pub struct RunReport {
+    /// Nodes read from the filter files, or `None` if the filter set came from the cache
+    pub filter_nodes: Option<usize>,
+    /// Filter nodes skipped for an excluded category, or `None` if the filter set came from the
+    /// cache
+    pub num_excluded: Option<usize>,
+    pub files: Vec<FileRunReport>,
+    /// Nodes written to NonBabelNodes, or `None` if it wasn't written
+    pub non_babel_nodes: Option<usize>,
+    pub duration_ms: u128,
+}
+
+/// One Babel file in a `RunReport`
+#[derive(Serialize)]
+pub struct FileRunReport {
+    pub name: String,
+    pub total: usize,
+    pub kept: usize,
+    pub duration_ms: u128,
+}
+
+impl RunReport {
+    /// Writes the report to `path` as a JSON object
+    ///
+    /// Returns `Err` if there is a problem writing the file
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl From<&FileSummary> for FileRunReport {
+    fn from(file: &FileSummary) -> FileRunReport {
+        FileRunReport {
+            name: file.name.clone(),
+            total: file.num_read,
+            kept: file.num_kept,
+            duration_ms: file.duration.as_millis(),
+        }
+    }
+}
+
+/// One Babel file's row of the `--summary-table` table
+pub struct FileSummary {
+    pub name: String,
+    pub num_read: usize,
+    pub num_kept: usize,
+    pub duration: Duration,
+}
+
+/// Returns the lines of a table with a header and one aligned row per file, sorted by file name
+pub fn file_table(files: &mut [FileSummary]) -> Vec<String> {
+    files.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    let width = files
+        .iter()
+        .map(|file| file.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("file".len());
+
+    let mut lines = vec![format!(
+        "{:<width$}  {:>12}  {:>12}  {:>8}  {:>10}",
+        "file", "read", "kept", "kept %", "duration"
+    )];
+    for file in files.iter() {
+        let percent = kept_percent(file.num_kept, file.num_read);
+        lines.push(format!(
+            "{:<width$}  {:>12}  {:>12}  {:>7.2}%  {:>10}",
+            file.name,
+            file.num_read,
+            file.num_kept,
+            percent,
+            format!("{:.2?}", file.duration)
+        ));
+    }
+    lines
+}
+
+/// Returns the lines of a table with a header and one aligned row of node counts per type, most
+/// nodes first
+pub fn type_table(type_counts: &AHashMap<String, usize>) -> Vec<String> {
+    let mut types: Vec<(&String, &usize)> = type_counts.iter().collect();
+    types.sort_unstable_by(|(a_type, a), (b_type, b)| b.cmp(a).then_with(|| a_type.cmp(b_type)));
+    let width = types
+        .iter()
+        .map(|(node_type, _)| node_type.len())
+        .max()
+        .unwrap_or(0)
+        .max("type".len());
+
+    let mut lines = vec![format!("{:<width$}  {:>12}", "type", "nodes")];
+    for (node_type, num_nodes) in types {
+        lines.push(format!("{:<width$}  {:>12}", node_type, num_nodes));
+    }
+    lines
+}
+
+/// Expected number of lines per Babel file name, used to catch truncated or duplicated inputs
+pub struct ExpectedCounts {
+    counts: AHashMap<String, usize>,
+    tolerance: f64,
+}
+
+impl ExpectedCounts {
+    /// Loads a JSON object mapping Babel file names to their expected line counts. A file's count
+    /// is allowed to differ by up to `tolerance` percent.
+    ///
+    /// Returns `Err` with a message if the file can't be read or isn't a JSON object of counts
+    pub fn load<P: AsRef<Path>>(path: P, tolerance: f64) -> Result<ExpectedCounts, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let counts: HashMap<String, usize> =
+            serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+        Ok(ExpectedCounts {
+            counts: counts.into_iter().collect(),
+            tolerance,
+        })
+    }
+
+    /// Compares the actual line counts per file name against the expected counts, returning a
+    /// description of each discrepancy (including expected files that weren't processed)
+    pub fn discrepancies(&self, actual: &AHashMap<String, usize>) -> Vec<String> {
+        let mut discrepancies: Vec<String> = self
+            .counts
+            .iter()
+            .filter_map(|(name, &expected)| {
+                let Some(&num_nodes) = actual.get(name) else {
+                    return Some(format!(
+                        "{name}: expected {expected} nodes but the file wasn't processed"
+                    ));
+                };
+                let percent = match expected {
+                    0 if num_nodes == 0 => 0.0,
+                    0 => f64::INFINITY,
+                    _ => num_nodes.abs_diff(expected) as f64 / expected as f64 * 100.0,
+                };
+                (percent > self.tolerance).then(|| {
+                    format!("{name}: expected {expected} nodes but read {num_nodes} ({percent:.2}% off)")
+                })
+            })
+            .collect();
+        discrepancies.sort();
+        discrepancies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::AHashMap;
+    use std::time::Duration;
+
+    use super::{
+        coverage_percent, curie_prefix, file_table, kept_percent, throughput, ExpectedCounts,
+        FileSummary, PrefixStats,
+    };
+
+    #[test]
+    fn splits_curie_prefix() {
+        assert_eq!(curie_prefix("NCBIGene:1017"), "NCBIGene");
+        assert_eq!(curie_prefix("no_prefix"), "no_prefix");
+    }
+
+    #[test]
+    fn computes_coverage_without_dividing_by_zero() {
+        assert_eq!(coverage_percent(4, 1), Some(75.0));
+        assert_eq!(coverage_percent(4, 4), Some(0.0));
+        assert_eq!(coverage_percent(0, 0), None);
+    }
+
+    #[test]
+    fn computes_kept_percent_without_dividing_by_zero() {
+        assert_eq!(kept_percent(1, 4), 25.0);
+        assert_eq!(kept_percent(0, 0), 0.0);
+    }
+
+    #[test]
+    fn computes_throughput() {
+        assert_eq!(
+            throughput(3_000_000, 1_000, Duration::from_secs(2)),
+            "1.50 MB/s, 500 nodes/s"
+        );
+        assert!(throughput(1, 1, Duration::ZERO).ends_with(" nodes/s"));
+    }
+
+    #[test]
+    fn tabulates_a_row_per_file() {
+        let mut files = vec![
+            FileSummary {
+                name: String::from("Gene.txt"),
+                num_read: 4,
+                num_kept: 1,
+                duration: Duration::from_millis(1500),
+            },
+            FileSummary {
+                name: String::from("Disease.txt"),
+                num_read: 0,
+                num_kept: 0,
+                duration: Duration::ZERO,
+            },
+        ];
+
+        let lines = file_table(&mut files);
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("file  "));
+        assert!(lines[1].starts_with("Disease.txt  "));
+        assert!(lines[1].contains("0.00%"));
+        assert!(lines[2].starts_with("Gene.txt     "));
+        assert!(lines[2].contains("25.00%") && lines[2].ends_with("1.50s"));
+        // every column lines up with the header
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+    }
+
+    #[test]
+    fn rows_sorted_by_lowest_keep_rate() {
+        let mut stats = PrefixStats::default();
+        stats.record("MONDO:1", true);
+        stats.record("MONDO:2", true);
+        stats.record("CHEBI:1", false);
+        stats.record("CHEBI:2", true);
+        stats.record("UMLS:1", false);
+
+        assert_eq!(
+            stats.rows(),
+            vec![("UMLS", 1, 0), ("CHEBI", 2, 1), ("MONDO", 2, 2)]
+        );
+    }
+
+    #[test]
+    fn reports_counts_outside_tolerance() {
+        let expected = ExpectedCounts {
+            counts: AHashMap::from([
+                (String::from("Gene.txt"), 100),
+                (String::from("Disease.txt"), 100),
+                (String::from("Missing.txt"), 10),
+            ]),
+            tolerance: 5.0,
+        };
+        let actual = AHashMap::from([
+            (String::from("Gene.txt"), 104),
+            (String::from("Disease.txt"), 90),
+        ]);
+
+        assert_eq!(
+            expected.discrepancies(&actual),
+            vec![
+                "Disease.txt: expected 100 nodes but read 90 (10.00% off)",
+                "Missing.txt: expected 10 nodes but the file wasn't processed",
+            ]
+        );
+    }
+}
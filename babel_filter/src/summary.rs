@@ -0,0 +1,183 @@
+use ahash::AHashMap;
+use camino::Utf8Path;
+use std::{fs, io, process::ExitCode, time::Instant};
+
+use crate::file::reader::Reader;
+use crate::provenance;
+use crate::stats::{self, FileSummary, PrefixStats};
+use crate::{BabelJson, BUF_CAPACITY};
+
+/// Counts read back from a directory of output files
+#[derive(Default)]
+struct Summary {
+    /// One row per output file, every node of which counts as both read and kept
+    files: Vec<FileSummary>,
+    prefix_stats: PrefixStats,
+    type_counts: AHashMap<String, usize>,
+    /// Lines that aren't a Babel node (other than provenance headers)
+    num_invalid: usize,
+}
+
+/// Prints node counts for every output file in `directory` without re-filtering, in the
+/// `--summary-table` format of a run, optionally broken down by curie prefix and by type.
+/// Metadata sidecars and provenance headers are skipped. Records are split on
+/// `record_separator` (a newline by default).
+pub fn summary(
+    directory: &Utf8Path,
+    per_prefix: bool,
+    per_type: bool,
+    record_separator: Option<u8>,
+) -> ExitCode {
+    let start = Instant::now();
+
+    if !directory.is_dir() {
+        eprintln!("The path provided to the output directory isn't a directory or doesn't exist");
+        return ExitCode::FAILURE;
+    }
+
+    let mut summary = match summarize(directory, record_separator.unwrap_or(b'\n')) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("Error reading the output directory: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for line in stats::file_table(&mut summary.files) {
+        println!("{line}");
+    }
+    if summary.num_invalid > 0 {
+        println!("{} lines weren't valid Babel nodes", summary.num_invalid);
+    }
+
+    if per_prefix {
+        println!("Keep rate by curie prefix:");
+        summary.prefix_stats.print();
+    }
+
+    if per_type {
+        println!("{} distinct types in the kept nodes", summary.type_counts.len());
+        for line in stats::type_table(&summary.type_counts) {
+            println!("{line}");
+        }
+    }
+
+    println!("Program took {:.2?}", start.elapsed());
+    ExitCode::SUCCESS
+}
+
+/// Reads every output file in `directory`, split into records on `separator`, tallying its nodes
+fn summarize(directory: &Utf8Path, separator: u8) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.path().is_file() || file_name.ends_with(".meta.json") {
+            continue;
+        }
+
+        let t0 = Instant::now();
+        let mut num_nodes = 0;
+        for line in Reader::new(entry.path(), BUF_CAPACITY)?.with_separator(separator).lines() {
+            let line = line?;
+            let Ok(node) = serde_json::from_str::<BabelJson>(&line) else {
+                if !provenance::is_header(&line) {
+                    summary.num_invalid += 1;
+                }
+                continue;
+            };
+            num_nodes += 1;
+            // every node in an output was kept
+            summary.prefix_stats.record(&node.curie, true);
+            for node_type in node.types {
+                *summary.type_counts.entry(node_type).or_default() += 1;
+            }
+        }
+        summary.files.push(FileSummary {
+            name: file_name,
+            num_read: num_nodes,
+            num_kept: num_nodes,
+            duration: t0.elapsed(),
+        });
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use std::{fs, io};
+    use tempfile::tempdir;
+
+    use super::summarize;
+    use crate::stats;
+
+    #[test]
+    fn summarizes_output_directory() -> io::Result<()> {
+        let dir = tempdir()?;
+        let directory = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            directory.join("Disease.txt"),
+            concat!(
+                r#"{"_provenance":true,"tool_version":"0.1.0"}"#,
+                "\n",
+                r#"{"curie":"MONDO:1","names":["a"],"types":["Disease"],"taxa":[]}"#,
+                "\n",
+                r#"{"curie":"DOID:1","names":["b"],"types":["Disease","Entity"],"taxa":[]}"#,
+                "\n",
+                "not json\n",
+            ),
+        )?;
+        fs::write(
+            directory.join("Gene.txt"),
+            concat!(r#"{"curie":"NCBIGene:1","names":["c"],"types":["Entity"],"taxa":[]}"#, "\n"),
+        )?;
+        fs::write(directory.join("Gene.txt.meta.json"), "{}")?;
+
+        let mut summary = summarize(&directory, b'\n')?;
+
+        summary.files.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let files: Vec<(&str, usize, usize)> =
+            summary.files.iter().map(|file| (&*file.name, file.num_read, file.num_kept)).collect();
+        assert_eq!(files, vec![("Disease.txt", 2, 2), ("Gene.txt", 1, 1)]);
+        assert_eq!(summary.num_invalid, 1);
+        assert_eq!(
+            summary.prefix_stats.rows(),
+            vec![("DOID", 1, 1), ("MONDO", 1, 1), ("NCBIGene", 1, 1)]
+        );
+        assert_eq!(summary.type_counts["Disease"], 2);
+        assert_eq!(summary.type_counts["Entity"], 2);
+        assert_eq!(
+            stats::type_table(&summary.type_counts),
+            vec![
+                format!("{:<7}  {:>12}", "type", "nodes"),
+                format!("{:<7}  {:>12}", "Disease", 2),
+                format!("{:<7}  {:>12}", "Entity", 2),
+            ]
+        );
+
+        dir.close()
+    }
+
+    #[test]
+    fn summarizes_with_record_separator() -> io::Result<()> {
+        let dir = tempdir()?;
+        let directory = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        fs::write(
+            directory.join("Gene.txt"),
+            concat!(
+                r#"{"curie":"NCBIGene:1","names":["a"],"types":["Gene"],"taxa":[]}"#,
+                "\x1e",
+                r#"{"curie":"NCBIGene:2","names":["b"],"types":["Gene"],"taxa":[]}"#,
+                "\x1e",
+            ),
+        )?;
+
+        let summary = summarize(&directory, 0x1e)?;
+
+        assert_eq!(summary.files[0].num_read, 2);
+        assert_eq!(summary.num_invalid, 0);
+
+        dir.close()
+    }
+}
@@ -0,0 +1,487 @@
+use ahash::AHashSet;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{BabelJson, BabelRecord, UnicodeForm};
+
+/// Rewrites applied to each kept Babel node. When any of these are set, kept nodes are
+/// re-serialized from their parsed form instead of being written verbatim, which is slower and
+/// doesn't preserve the original key order.
+#[derive(Default)]
+pub struct Transforms {
+    /// Keep at most this many names per node
+    pub max_output_names: Option<usize>,
+    /// Remove duplicate taxa, keeping the first occurrence
+    pub dedup_taxa: bool,
+    /// Sort names lexicographically, after truncating them to `max_output_names`
+    pub sort_names: bool,
+    /// Leave `names`, `types` and `taxa` out of the output when they're empty
+    pub strip_empty_fields: bool,
+    /// Write empty `taxa` as `null` instead of `[]` (overridden by `strip_empty_fields`)
+    pub empty_taxa_null: bool,
+    /// Normalize `names` and `preferred_name` to this Unicode normalization form
+    pub normalize_unicode: Option<UnicodeForm>,
+    /// Check that every serialized record reads back as the record it was serialized from. This
+    /// isn't a transform, so it doesn't stop kept lines from being written verbatim.
+    pub verify_roundtrip: bool,
+    /// Write rewritten fields back into each line's own JSON object instead of re-serializing the
+    /// node, so the other keys aren't reordered. The original key order is only kept with the
+    /// `preserve-key-order` feature; without it every key comes out sorted. This isn't a
+    /// transform either.
+    pub preserve_key_order: bool,
+}
+
+/// A `BabelRecord` with its empty list fields left out (`None`) or, for `taxa`, written as `null`
+/// (`Some(None)`), in the same field order
+#[derive(Serialize)]
+struct ReshapedRecord<'a> {
+    curie: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    names: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    types: Option<&'a [String]>,
+    preferred_name: &'a Option<String>,
+    shortest_name_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    taxa: Option<Option<&'a [String]>>,
+    #[serde(flatten)]
+    extra: &'a serde_json::Map<String, serde_json::Value>,
+}
+
+impl Transforms {
+    /// Returns true if no transforms are set, i.e. kept lines can be written verbatim
+    pub fn is_empty(&self) -> bool {
+        self.max_output_names.is_none()
+            && !self.dedup_taxa
+            && !self.sort_names
+            && !self.strip_empty_fields
+            && !self.empty_taxa_null
+            && self.normalize_unicode.is_none()
+    }
+
+    /// Applies each set transform to `node` in place
+    pub fn apply(&self, node: &mut BabelJson) {
+        if let Some(form) = self.normalize_unicode {
+            let mut changed_name = false;
+            for name in node.names.iter_mut() {
+                let normalized = normalize(name, form);
+                if normalized != *name {
+                    *name = normalized;
+                    changed_name = true;
+                }
+            }
+            if let Some(ref mut preferred_name) = node.preferred_name {
+                *preferred_name = normalize(preferred_name, form);
+            }
+            // compatibility forms can change a name's length, but the upstream length stands for
+            // names that are already normalized
+            if changed_name {
+                node.shortest_name_length = shortest_name_length(&node.names);
+            }
+        }
+        if let Some(max_names) = self.max_output_names {
+            if node.names.len() > max_names {
+                node.names.truncate(max_names);
+                node.shortest_name_length = shortest_name_length(&node.names);
+            }
+        }
+        if self.sort_names {
+            node.names.sort_unstable();
+            // sorting doesn't change it, but keep it consistent with the names written
+            node.shortest_name_length = shortest_name_length(&node.names);
+        }
+        if self.dedup_taxa {
+            let mut seen = AHashSet::with_capacity(node.taxa.len());
+            node.taxa.retain(|taxon| seen.insert(taxon.clone()));
+        }
+    }
+
+    /// Parses a Babel line, applies the transforms and serializes it again. Fields not modeled by
+    /// `BabelJson` are carried through unchanged.
+    pub fn rewrite_line(&self, line: &str) -> serde_json::Result<String> {
+        if self.preserve_key_order {
+            return self.rewrite_in_place(line, None);
+        }
+        self.rewrite_record(serde_json::from_str(line)?)
+    }
+
+    /// Like `rewrite_line`, but also replaces the node's curie
+    pub fn rewrite_line_as(&self, line: &str, curie: &str) -> serde_json::Result<String> {
+        if self.preserve_key_order {
+            return self.rewrite_in_place(line, Some(curie));
+        }
+        let mut record: BabelRecord = serde_json::from_str(line)?;
+        record.node.curie = String::from(curie);
+        self.rewrite_record(record)
+    }
+
+    /// Applies the transforms to a record and serializes it. With `verify_roundtrip`, the result
+    /// is checked against the record as it was before the transforms.
+    pub fn rewrite_record(&self, mut record: BabelRecord) -> serde_json::Result<String> {
+        let original = self.verify_roundtrip.then(|| record.node.clone());
+        self.apply(&mut record.node);
+        let line = self.serialize(&record)?;
+        if let Some(original) = original {
+            self.check_roundtrip(&original, &record, &line)?;
+        }
+        Ok(line)
+    }
+
+    /// Applies the transforms to a line's node and writes its fields back into the line's parsed
+    /// object, where they keep their position. A modeled field the line didn't have is only added
+    /// if it isn't `null`.
+    fn rewrite_in_place(&self, line: &str, curie: Option<&str>) -> serde_json::Result<String> {
+        let mut object: serde_json::Map<String, Value> = serde_json::from_str(line)?;
+        // the line is only parsed once, into the object the record is read from
+        let mut record: BabelRecord = serde_json::from_value(Value::Object(object.clone()))?;
+        if let Some(curie) = curie {
+            record.node.curie = String::from(curie);
+        }
+        let original = self.verify_roundtrip.then(|| record.node.clone());
+        self.apply(&mut record.node);
+
+        let node = &record.node;
+        let fields = [
+            ("curie", json!(node.curie)),
+            ("names", json!(node.names)),
+            ("types", json!(node.types)),
+            ("preferred_name", json!(node.preferred_name)),
+            ("shortest_name_length", json!(node.shortest_name_length)),
+            ("taxa", json!(node.taxa)),
+        ];
+        for (key, value) in fields {
+            // replacing an existing key's value leaves it where it was
+            if object.contains_key(key) || !value.is_null() {
+                object.insert(String::from(key), value);
+            }
+        }
+        if self.strip_empty_fields {
+            // unlike `remove`, `retain` doesn't move the last key into the gap
+            object.retain(|key, value| {
+                !(matches!(key.as_str(), "names" | "types" | "taxa")
+                    && value.as_array().is_some_and(Vec::is_empty))
+            });
+        } else if self.empty_taxa_null && node.taxa.is_empty() {
+            object.insert(String::from("taxa"), Value::Null);
+        }
+
+        let line = serde_json::to_string(&object)?;
+        if let Some(original) = original {
+            self.check_roundtrip(&original, &record, &line)?;
+        }
+        Ok(line)
+    }
+
+    /// Serializes a record, leaving out empty list fields if `strip_empty_fields` is set and
+    /// writing empty taxa as `null` if `empty_taxa_null` is set
+    ///
+    fn serialize(&self, record: &BabelRecord) -> serde_json::Result<String> {
+        if !self.strip_empty_fields && !self.empty_taxa_null {
+            return serde_json::to_string(record);
+        }
+        let node = &record.node;
+        let keep = |field: &[String]| !(self.strip_empty_fields && field.is_empty());
+        let taxa = match (node.taxa.is_empty(), self.strip_empty_fields) {
+            (true, true) => None,
+            (true, false) => Some(None),
+            (false, _) => Some(Some(node.taxa.as_slice())),
+        };
+        serde_json::to_string(&ReshapedRecord {
+            curie: &node.curie,
+            names: keep(&node.names).then_some(node.names.as_slice()),
+            types: keep(&node.types).then_some(node.types.as_slice()),
+            preferred_name: &node.preferred_name,
+            shortest_name_length: node.shortest_name_length,
+            taxa,
+            extra: &record.extra,
+        })
+    }
+
+    /// Returns `Err` if `line` doesn't parse back into `record`, or if it differs from `original`,
+    /// the node before the transforms, in a field none of the set transforms rewrites
+    fn check_roundtrip(
+        &self,
+        original: &BabelJson,
+        record: &BabelRecord,
+        line: &str,
+    ) -> serde_json::Result<()> {
+        let rewrites_names =
+            self.normalize_unicode.is_some() || self.max_output_names.is_some() || self.sort_names;
+        let source = |rewritten: bool| if rewritten { &record.node } else { original };
+        let expected = RoundtripRecord {
+            curie: original.curie.clone(),
+            names: source(rewrites_names).names.clone(),
+            types: original.types.clone(),
+            preferred_name: source(self.normalize_unicode.is_some()).preferred_name.clone(),
+            shortest_name_length: source(rewrites_names).shortest_name_length,
+            taxa: source(self.dedup_taxa).taxa.clone(),
+            extra: record.extra.clone(),
+        };
+        let read_back: RoundtripRecord = serde_json::from_str(line)?;
+        if read_back != expected {
+            return Err(serde::ser::Error::custom(format!(
+                "{} didn't read back as the record it was rewritten from: expected {:?}, read {:?}",
+                original.curie, expected, read_back
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A serialized record read back for `verify_roundtrip`. Empty lists may have been left out or,
+/// for `taxa`, written as `null`, so those read back as empty.
+#[derive(Deserialize, PartialEq, Debug)]
+struct RoundtripRecord {
+    curie: String,
+    #[serde(default, deserialize_with = "list_or_null")]
+    names: Vec<String>,
+    #[serde(default, deserialize_with = "list_or_null")]
+    types: Vec<String>,
+    preferred_name: Option<String>,
+    shortest_name_length: Option<usize>,
+    #[serde(default, deserialize_with = "list_or_null")]
+    taxa: Vec<String>,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+fn list_or_null<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(Option::<Vec<String>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Returns `text` in the given Unicode normalization form
+pub fn normalize(text: &str, form: UnicodeForm) -> String {
+    match form {
+        UnicodeForm::Nfc => text.nfc().collect(),
+        UnicodeForm::Nfkc => text.nfkc().collect(),
+    }
+}
+
+/// Returns the length, in characters, of the shortest name, or `None` if there are no names
+pub fn shortest_name_length(names: &[String]) -> Option<usize> {
+    names.iter().map(|name| name.chars().count()).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::Transforms;
+    use crate::{BabelRecord, UnicodeForm};
+
+    #[test]
+    fn truncates_names_and_recomputes_length() {
+        let transforms = Transforms {
+            max_output_names: Some(2),
+            ..Default::default()
+        };
+        let line = json!({
+            "curie": "MONDO:1",
+            "names": ["a long name", "medium", "x"],
+            "types": ["Disease"],
+            "preferred_name": "a long name",
+            "shortest_name_length": 1,
+            "taxa": [],
+            "extra": "kept"
+        })
+        .to_string();
+
+        let rewritten: Value = serde_json::from_str(&transforms.rewrite_line(&line).unwrap()).unwrap();
+
+        assert_eq!(rewritten["names"], json!(["a long name", "medium"]));
+        assert_eq!(rewritten["shortest_name_length"], json!(6));
+        assert_eq!(rewritten["extra"], json!("kept"));
+    }
+
+    #[test]
+    fn sorts_names() {
+        let transforms = Transforms {
+            sort_names: true,
+            ..Default::default()
+        };
+        let line = json!({
+            "curie": "MONDO:1",
+            "names": ["gout", "Asthma", "asthma", "arthritis"],
+            "types": [],
+            "taxa": []
+        })
+        .to_string();
+
+        let rewritten: Value = serde_json::from_str(&transforms.rewrite_line(&line).unwrap()).unwrap();
+
+        assert_eq!(rewritten["names"], json!(["Asthma", "arthritis", "asthma", "gout"]));
+        assert_eq!(rewritten["shortest_name_length"], json!(4));
+    }
+
+    #[test]
+    fn removes_duplicate_taxa_in_order() {
+        let transforms = Transforms {
+            dedup_taxa: true,
+            ..Default::default()
+        };
+        let line = json!({
+            "curie": "NCBIGene:1",
+            "names": ["a"],
+            "types": ["Gene"],
+            "taxa": ["NCBITaxon:9606", "NCBITaxon:10090", "NCBITaxon:9606"]
+        })
+        .to_string();
+
+        let rewritten: Value = serde_json::from_str(&transforms.rewrite_line(&line).unwrap()).unwrap();
+
+        assert_eq!(rewritten["taxa"], json!(["NCBITaxon:9606", "NCBITaxon:10090"]));
+    }
+
+    #[test]
+    fn strips_empty_fields() {
+        let transforms = Transforms {
+            strip_empty_fields: true,
+            ..Default::default()
+        };
+        let line = json!({
+            "curie": "MONDO:1",
+            "names": ["a"],
+            "types": [],
+            "taxa": [],
+            "extra": []
+        })
+        .to_string();
+
+        let rewritten: Value = serde_json::from_str(&transforms.rewrite_line(&line).unwrap()).unwrap();
+
+        assert_eq!(rewritten["names"], json!(["a"]));
+        assert!(rewritten.get("types").is_none());
+        assert!(rewritten.get("taxa").is_none());
+        assert_eq!(rewritten["extra"], json!([]));
+    }
+
+    #[test]
+    fn keeps_other_keys_in_place() {
+        let transforms = Transforms {
+            strip_empty_fields: true,
+            sort_names: true,
+            preserve_key_order: true,
+            ..Default::default()
+        };
+        let line = concat!(
+            r#"{"taxa":[],"curie":"MONDO:1","zeta":1,"#,
+            r#""names":["b","a"],"types":[],"alpha":2}"#
+        );
+
+        let rewritten = transforms.rewrite_line(line).unwrap();
+
+        let expected = concat!(
+            r#"{"curie":"MONDO:1","zeta":1,"names":["a","b"],"#,
+            r#""alpha":2,"shortest_name_length":1}"#
+        );
+        #[cfg(feature = "preserve-key-order")]
+        assert_eq!(rewritten, expected);
+        // serde_json sorts keys without its preserve_order feature
+        #[cfg(not(feature = "preserve-key-order"))]
+        assert_eq!(
+            serde_json::from_str::<Value>(&rewritten).unwrap(),
+            serde_json::from_str::<Value>(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn writes_empty_taxa_as_null() {
+        let transforms = Transforms {
+            empty_taxa_null: true,
+            ..Default::default()
+        };
+        let line = json!({"curie": "MONDO:1", "names": [], "types": [], "taxa": []}).to_string();
+
+        let rewritten = transforms.rewrite_line(&line).unwrap();
+
+        assert!(rewritten.contains(r#""names":[],"types":[]"#));
+        assert!(rewritten.contains(r#""taxa":null"#));
+        let rewritten: Value = serde_json::from_str(&rewritten).unwrap();
+        assert_eq!(rewritten["taxa"], Value::Null);
+    }
+
+    #[test]
+    fn normalizes_names() {
+        let transforms = Transforms {
+            normalize_unicode: Some(UnicodeForm::Nfc),
+            ..Default::default()
+        };
+        // "café" with a combining acute accent
+        let line = json!({
+            "curie": "CHEBI:1",
+            "names": ["cafe\u{301}"],
+            "types": [],
+            "preferred_name": "cafe\u{301}",
+            "taxa": []
+        })
+        .to_string();
+
+        let rewritten: Value = serde_json::from_str(&transforms.rewrite_line(&line).unwrap()).unwrap();
+
+        assert_eq!(rewritten["names"], json!(["caf\u{e9}"]));
+        assert_eq!(rewritten["preferred_name"], json!("caf\u{e9}"));
+        assert_eq!(rewritten["shortest_name_length"], json!(4));
+
+        let line = json!({
+            "curie": "CHEBI:1",
+            "names": ["caf\u{e9}"],
+            "types": [],
+            "shortest_name_length": 5,
+            "taxa": []
+        })
+        .to_string();
+
+        let rewritten = transforms.rewrite_line(&line).unwrap();
+
+        // nothing was normalized, so the length from upstream is kept
+        let rewritten: Value = serde_json::from_str(&rewritten).unwrap();
+        assert_eq!(rewritten["shortest_name_length"], json!(5));
+    }
+
+    #[test]
+    fn verifies_roundtrip() {
+        let transforms = Transforms {
+            strip_empty_fields: true,
+            empty_taxa_null: true,
+            verify_roundtrip: true,
+            ..Default::default()
+        };
+        let line = json!({"curie": "MONDO:1", "names": ["a"], "types": [], "taxa": [], "x": 1});
+        let record: BabelRecord = serde_json::from_value(line).unwrap();
+
+        // reshaped empty lists still read back as the same record
+        let serialized = transforms.serialize(&record).unwrap();
+        assert!(transforms.check_roundtrip(&record.node, &record, &serialized).is_ok());
+
+        // a lossy serialization that drops a name and an extra field is caught
+        let lossy = json!({"curie": "MONDO:1", "names": [], "types": []}).to_string();
+        let error = transforms.check_roundtrip(&record.node, &record, &lossy).unwrap_err();
+        assert!(error.to_string().contains("MONDO:1"));
+    }
+
+    #[test]
+    fn verifies_roundtrip_against_untransformed_node() {
+        let transforms = Transforms {
+            max_output_names: Some(1),
+            verify_roundtrip: true,
+            ..Default::default()
+        };
+        let line = r#"{"curie":"MONDO:1","names":["a","b"],"types":["Disease"],"taxa":["T:1"]}"#;
+
+        // fields a set transform rewrites may change
+        let rewritten = transforms.rewrite_line(line).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&rewritten).unwrap()["names"], json!(["a"]));
+
+        // a deliberately lossy transform that also drops the types and taxa is caught, even though
+        // its output reads back as the record it was serialized from
+        let original: BabelRecord = serde_json::from_str(line).unwrap();
+        let mut lossy: BabelRecord = serde_json::from_str(line).unwrap();
+        transforms.apply(&mut lossy.node);
+        lossy.node.types.clear();
+        lossy.node.taxa.clear();
+        let serialized = transforms.serialize(&lossy).unwrap();
+        let error = transforms.check_roundtrip(&original.node, &lossy, &serialized).unwrap_err();
+        assert!(error.to_string().contains("MONDO:1"));
+    }
+}
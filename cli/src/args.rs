@@ -1,5 +1,5 @@
 use camino::Utf8PathBuf;
-use clap::Parser;
+use clap::{ArgAction, Parser, Subcommand};
 
 /// This script takes a directory of Babel files (JSONL) and creates filtered versions
 /// in a new directory containing only the lines where the the json key (default `curie`)
@@ -8,21 +8,419 @@ use clap::Parser;
 /// Gzipped files will be automatically detected
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CliArgs {
-  /// The directory containing Babel JSONL files
-  pub babel_directory: Utf8PathBuf,
+  #[command(subcommand)]
+  pub command: Option<Command>,
+
+  /// The directory containing Babel JSONL files, or `-` to read one stream of Babel records,
+  /// compressed or not, from stdin. The stream is filtered as a file named stdin.txt
+  #[arg(required = true)]
+  pub babel_directory: Option<Utf8PathBuf>,
   
-  /// The path to the filter JSONL file to be used
-  pub filter_file: Utf8PathBuf,
+  /// The paths to the filter JSONL files to be used. Their nodes are read into one filter set, in
+  /// order, so an id in several files is only kept once
+  #[arg(required = true, num_args = 1..)]
+  pub filter_file: Option<Vec<Utf8PathBuf>>,
 
-  /// The directory to put the filtered JSONL output files
-  pub output_directory: Utf8PathBuf,
+  /// The directory to put the filtered JSONL output files, or `-` to write the kept records,
+  /// uncompressed, to stdout. Then the summary goes to stderr with the progress messages, Babel
+  /// files are filtered one at a time, and NonBabelNodes isn't written
+  #[arg(required = true)]
+  pub output_directory: Option<Utf8PathBuf>,
 
   /// Exclude nodes with these biolink categories from the output. Multiple categories 
   /// can be specified by using the flag again
   #[arg(short, long, value_name="CATEGORY")]
   pub exclude_category: Option<Vec<String>>,
 
+  /// Only keep nodes with at least one of these biolink categories. Multiple categories can be
+  /// specified by using the flag again. `--exclude-category` wins for nodes matching both
+  #[arg(long, value_name="CATEGORY")]
+  pub include_category: Option<Vec<String>>,
+
+  /// Compare filter file categories with `--exclude-category` and `--include-category` without
+  /// regard to case, so `biolink:gene` matches `biolink:Gene`
+  #[arg(long)]
+  pub ignore_category_case: bool,
+
+  /// Treat each `--exclude-category` as a regex that has to match a whole category, e.g.
+  /// `biolink:Gene.*` to exclude every category starting with `biolink:Gene`. The patterns are
+  /// compiled before the filter file is read, and the run fails if one doesn't compile
+  #[arg(long)]
+  pub category_regex: bool,
+
+  /// Only keep Babel nodes whose `types` include every one of these types. Unlike
+  /// `--exclude-category`, which drops a node matching ANY listed category, this requires ALL
+  /// listed types to be present. A `biolink:` prefix is ignored. Multiple types can be specified
+  /// by using the flag again
+  #[arg(long, value_name="TYPE")]
+  pub require_all_types: Option<Vec<String>>,
+
+  /// Drop Babel nodes with empty `types` and filter file nodes with an empty `category`. Unlike
+  /// the options above this only checks that some category is present, not which one
+  #[arg(long)]
+  pub require_category: bool,
+
+  /// Only keep Babel nodes whose `taxa` include one of these taxa, e.g. `NCBITaxon:9606`.
+  /// Multiple taxa can be specified by using the flag again. Nodes with no taxa are dropped
+  /// unless `--keep-untaxed` is set
+  #[arg(long, value_name="TAXON")]
+  pub taxa: Option<Vec<String>>,
+
+  /// With `--taxa`, keep Babel nodes that have no taxa at all, since many concepts legitimately
+  /// have none
+  #[arg(long)]
+  pub keep_untaxed: bool,
+
+  /// Only keep Babel nodes with this curie prefix if the numeric part after the prefix is in the
+  /// inclusive range, e.g. `NCBIGene:1-100000`; nodes with other prefixes are unaffected. Multiple
+  /// ranges can be specified by using the flag again, and a node is kept if it's in any range for
+  /// its prefix. Nodes whose local part isn't a number are dropped unless `--keep-non-numeric` is
+  /// set
+  #[arg(long, value_name="PREFIX:LO-HI")]
+  pub curie_numeric_range: Option<Vec<String>>,
+
+  /// With `--curie-numeric-range`, keep nodes with a ranged prefix whose local part isn't a number
+  #[arg(long)]
+  pub keep_non_numeric: bool,
+
+  /// A file of allowed preferred names, one per line. Babel nodes are only kept if they're in the
+  /// filter file and their `preferred_name` exactly matches one of these; nodes without a
+  /// `preferred_name` are dropped
+  #[arg(long, value_name="FILE")]
+  pub preferred_name_in_file: Option<Utf8PathBuf>,
+
+  /// A blocklist of curies, one per line, that are never kept or written to NonBabelNodes
+  /// regardless of the filter file. They're removed from the filter set once it's built, and
+  /// Babel nodes with a blocklisted curie are dropped as well
+  #[arg(long, value_name="FILE")]
+  pub exclude_curie_file: Option<Utf8PathBuf>,
+
+  /// Drop nodes whose curie matches this regex anywhere, unless it's anchored with `^` or `$`,
+  /// e.g. `^OBSOLETE\.` for a deprecated namespace. It's applied to the filter file ids as well as
+  /// to Babel curies, and how many nodes it dropped is printed
+  #[arg(long, value_name="PATTERN")]
+  pub exclude_curie_regex: Option<String>,
+
+  /// Write each curie at most once across all Babel files, dropping a node if an earlier file
+  /// already wrote its curie, and report how many duplicates were suppressed. Otherwise a matched
+  /// curie is already removed from the filter set, so this mostly matters with `--bloom-filter`,
+  /// `--ids-only`, `--per-file-matches` or seeded curies. Every written curie is held in memory
+  /// until the run ends. With `--concurrency` above 1 the file that writes a curie first depends
+  /// on how the files are scheduled
+  #[arg(long)]
+  pub global_dedup: bool,
+
+  /// Fail a Babel file if its output has any curie more than once, printing the file and the
+  /// duplicated curies. The check tracks the curies as they're written rather than reading the
+  /// output back, so every curie written to a file is held in memory until the file is done
+  #[arg(long)]
+  pub assert_no_duplicates: bool,
+
+  /// Print how many lines of each Babel file were skipped for repeating a curie matched earlier
+  /// in the same file. Only the first line with a curie is kept, since matching removes it from
+  /// the filter set; this only makes that visible
+  #[arg(long)]
+  pub report_duplicates: bool,
+
+  /// Keep every line of a Babel file that repeats a curie matched earlier in the same file,
+  /// instead of only the first, and print how many there were. The curies matched in each file
+  /// are held in memory until the file is done
+  #[arg(long, conflicts_with = "assert_no_duplicates")]
+  pub keep_duplicates: bool,
+
+  /// After filtering, merge the Babel-derived outputs into the same-named files of this existing
+  /// output directory (they're still written to the output directory too). NonBabelNodes isn't
+  /// merged. Every curie already in the directory is held in memory to detect conflicts
+  #[arg(long, value_name="DIR")]
+  pub merge_into: Option<Utf8PathBuf>,
+
+  /// What to do when a new node's curie is already in the `--merge-into` directory
+  #[arg(long, value_enum, default_value_t = ConflictPolicy::KeepExisting, value_name="POLICY")]
+  pub on_conflict: ConflictPolicy,
+
+  /// After filtering, delete the Babel-derived output files in the output directory that this run
+  /// didn't write, e.g. outputs of input files that have since been removed, along with their
+  /// metadata sidecars. NonBabelNodes, outputs named after a current input (e.g. in another
+  /// format) and this run's side outputs such as the names index are never pruned, and nothing is
+  /// pruned if an input was skipped or an output couldn't be written
+  #[arg(long, conflicts_with = "output_subdir_per_file")]
+  pub prune_orphans: bool,
+
+  /// Read and match every Babel file, printing how many nodes each would keep, without writing
+  /// anything: no outputs, NonBabelNodes, sidecars, leftover ids, names index, drop reasons or
+  /// filter cache. Pruning and merging are skipped too, so a filter can be checked before a long
+  /// export
+  #[arg(long)]
+  pub dry_run: bool,
+
+  /// Write each Babel file's output to its own subdirectory named after the file's stem, e.g.
+  /// `<output directory>/Disease/Disease.txt`, so consumers of different files don't contend on
+  /// one directory. NonBabelNodes is still written to the output directory itself
+  #[arg(long, conflicts_with = "merge_into")]
+  pub output_subdir_per_file: bool,
+
+  /// Stop printing read and parse errors after this many in the filter file or any one Babel file,
+  /// printing how many more were suppressed once the file is done
+  #[arg(long, value_name="N")]
+  pub max_errors_per_file: Option<usize>,
+
+  /// Retry Babel lines that don't parse once after repairing the JSON glitches a recurring
+  /// upstream bug emits: trailing commas are removed and bare `NaN`, `Infinity` and `-Infinity`
+  /// become `null`. Kept nodes are written with the repairs, and the number of repaired lines is
+  /// printed. Off by default, since a repair can change what a line means
+  #[arg(long)]
+  pub repair_json: bool,
+
+  /// Skip lines in the filter file and Babel files that start with this prefix, e.g. `#`.
+  /// Multiple prefixes can be specified by using the flag again
+  #[arg(long, value_name="PREFIX")]
+  pub comment_prefix: Option<Vec<String>>,
+
+  /// Only write leftover filter nodes with these curie prefixes to NonBabelNodes. Multiple
+  /// prefixes can be specified by using the flag again
+  #[arg(long, value_name="PREFIX")]
+  pub nonbabel_prefix: Option<Vec<String>>,
+
+  /// Also write the ids of the filter nodes left over after filtering, one per line and sorted,
+  /// to this file. `--nonbabel-prefix` and the name length bounds don't apply to this list
+  #[arg(long, value_name="FILE")]
+  pub leftovers_as_ids: Option<Utf8PathBuf>,
+
+  /// Only keep Babel nodes where the field at this JSON pointer equals the value, e.g.
+  /// `/meta/source=mondo`. Multiple predicates can be specified by using the flag again and must
+  /// all match. Note that this parses every Babel line a second time as untyped JSON, which is
+  /// noticeably slower
+  #[arg(long, value_name="POINTER=VALUE")]
+  pub where_pointer: Option<Vec<String>>,
+
+  /// Drop Babel nodes whose shortest name is shorter than this many characters. Also applies to
+  /// the name NonBabelNodes nodes are synthesized with, and drops nodes with no names
+  #[arg(long, value_name="N")]
+  pub min_name_length: Option<usize>,
+
+  /// Drop Babel nodes whose shortest name is longer than this many characters. Also applies to the
+  /// name NonBabelNodes nodes are synthesized with, and drops nodes with no names
+  #[arg(long, value_name="N")]
+  pub max_name_length: Option<usize>,
+
+  /// Trim each kept node to at most this many names, recomputing `shortest_name_length`. Unlike
+  /// dropping nodes, this keeps the node. Kept nodes are re-serialized rather than copied verbatim
+  #[arg(long, value_name="N")]
+  pub max_output_names: Option<usize>,
+
+  /// Remove duplicate taxa from kept and NonBabelNodes nodes, preserving order. Kept nodes are
+  /// re-serialized rather than copied verbatim
+  #[arg(long)]
+  pub dedup_taxa: bool,
+
+  /// Sort the names of kept and NonBabelNodes nodes lexicographically (by byte, so uppercase
+  /// first) for deterministic, diffable output. Kept nodes are re-serialized rather than copied
+  /// verbatim
+  #[arg(long)]
+  pub sort_names: bool,
+
+  /// Leave `names`, `types` and `taxa` out of kept and NonBabelNodes nodes when they're empty.
+  /// Kept nodes are re-serialized rather than copied verbatim
+  #[arg(long)]
+  pub strip_empty_fields: bool,
+
+  /// Write an empty `taxa` as `null` instead of `[]` in kept and NonBabelNodes nodes, for
+  /// consumers that treat "no taxa" differently from "taxa unknown". Kept nodes are re-serialized
+  /// rather than copied verbatim. `--strip-empty-fields` takes precedence and omits the field
+  #[arg(long)]
+  pub empty_taxa_null: bool,
+
+  /// Normalize the `names` and `preferred_name` of kept and NonBabelNodes nodes to this Unicode
+  /// form, recomputing `shortest_name_length`, so names that differ only in normalization (e.g.
+  /// combining accents in chemical names) compare equal. `--preferred-name-in-file` is matched
+  /// after normalizing both sides. Kept nodes are re-serialized rather than copied verbatim
+  #[arg(long, value_enum, value_name="FORM")]
+  pub normalize_unicode: Option<UnicodeForm>,
+
+  /// Self-test for the options that re-serialize nodes: parse every re-serialized node back and
+  /// check it matches the node as it was read, apart from the fields the options rewrite. A node
+  /// that doesn't fails its file and the run. Nodes copied verbatim aren't checked. This roughly
+  /// doubles the cost of re-serializing
+  #[arg(long)]
+  pub verify_roundtrip: bool,
+
+  /// Keep the original key order of nodes re-serialized by the other options, writing the
+  /// rewritten fields back in place instead of in a fixed order. Fields the rewrite removes are
+  /// dropped without moving the rest. This needs a build with the `preserve-key-order` feature
+  /// (`cargo build --features preserve-key-order`), and fails the run otherwise
+  #[arg(long)]
+  pub preserve_key_order: bool,
+
+  /// Lossy deduplication: of the kept nodes in each file, only write the one with the most names
+  /// for each curie prefix (ties keep the first seen), dropping every other node with that prefix.
+  /// Only use this when same-prefix nodes are variants of one entity. Kept nodes are buffered in
+  /// memory until each file is done
+  #[arg(long)]
+  pub keep_largest_per_prefix: bool,
+
+  /// Write each file's kept nodes grouped by curie prefix, so nodes with the same prefix are
+  /// contiguous. Prefixes are in the order they're first seen and nodes keep their input order
+  /// within a prefix. Every kept node of a file is buffered in memory until the file is done, so
+  /// this needs memory on the order of the largest output file. It can't be used with
+  /// `--keep-largest-per-prefix`, which already writes one node per prefix
+  #[arg(long, conflicts_with = "keep_largest_per_prefix")]
+  pub group_by_prefix: bool,
+
+  /// Store only a Bloom filter of the filter file ids instead of every filter node, using much less
+  /// memory at the cost of keeping some Babel nodes that aren't in the filter file (see
+  /// `--bloom-fp-rate`). Leftovers can't be tracked, so NonBabelNodes isn't written, a Babel node
+  /// is kept every time it appears, and `--filter-cache-dir` is ignored
+  #[arg(long)]
+  pub bloom_filter: bool,
+
+  /// The Bloom filter's false-positive rate, between 0 and 1
+  #[arg(long, default_value_t = 0.001, value_name="RATE")]
+  pub bloom_fp_rate: f64,
+
+  /// Store only the ids of the filter nodes instead of every filter node, using much less memory
+  /// while matching exactly. As with `--bloom-filter`, leftovers can't be tracked, so NonBabelNodes
+  /// isn't written, a Babel node is kept every time it appears, and `--filter-cache-dir` is ignored
+  #[arg(long, conflicts_with = "bloom_filter")]
+  pub ids_only: bool,
+
+  /// Split each output file into numbered parts of at most this many lines
+  #[arg(long, value_name="LINES")]
+  pub chunk_lines: Option<usize>,
+
+  /// Split each output file into numbered parts of roughly at most this many uncompressed bytes,
+  /// starting a new part before a line that would go over. A single line over the limit still
+  /// goes in one part. Can be combined with `--chunk-lines`, rolling over at whichever comes first
+  #[arg(long, value_name="BYTES")]
+  pub chunk_bytes: Option<usize>,
+
+  /// Extend each uncompressed output file (or part) to this many bytes before writing it, to
+  /// hint its size to the filesystem and reduce fragmentation, then truncate it to what was
+  /// written. This only sets the file's length, so filesystems with sparse files may not reserve
+  /// disk space for it. Ignored for compressed outputs
+  #[arg(long, value_name="BYTES")]
+  pub preallocate: Option<u64>,
+
+  /// Remove each filter node's own id and duplicates from its `equivalent_identifiers`,
+  /// reporting how many nodes were fixed
+  #[arg(long)]
+  pub validate_equivalents: bool,
+
+  /// Merge filter file records that share an id instead of keeping the last one: their categories
+  /// and names are combined, so the NonBabelNodes synthesized for the id has all of them. The
+  /// number of merged records is printed
+  #[arg(long)]
+  pub merge_duplicate_filter_records: bool,
+
+  /// Also match Babel nodes whose curie is one of a filter node's `equivalent_identifiers`. Each
+  /// filter node still matches at most one Babel node, through whichever of its curies comes
+  /// first, and isn't written to NonBabelNodes once matched
+  #[arg(long, conflicts_with_all = ["bloom_filter", "ids_only"])]
+  pub match_equivalents: bool,
+
+  /// Match filter ids and Babel curies case-insensitively, by lowercasing both entirely (the
+  /// local part as well as the prefix) before looking a curie up. Filter ids that only differ by
+  /// case collide, and only one of their nodes is kept
+  #[arg(long)]
+  pub ignore_curie_case: bool,
+
+  /// Read the filter file as a stream of JSON values instead of one per line, so pretty-printed
+  /// objects spanning several lines can be mixed with JSONL. This is slower than the line-based
+  /// parser, since serde_json reads the stream a byte at a time. `--comment-prefix` doesn't apply,
+  /// and a malformed value ends the filter file, as the stream can't resume after it
+  #[arg(long)]
+  pub filter_tolerant_parse: bool,
+
+  /// Read the filter file as tab-separated `id` and `category` columns instead of NodeList JSON,
+  /// so it doesn't have to be converted first. The category is used for `--exclude-category`,
+  /// `--include-category` and `--require-category`, and is the type NonBabelNodes are
+  /// synthesized with; they have no names. A header row has to be skipped with
+  /// `--comment-prefix`
+  #[arg(long, conflicts_with = "filter_tolerant_parse")]
+  pub filter_tsv: bool,
+
+  /// The 1-based column of the id in a `--filter-tsv` filter file
+  #[arg(long, default_value_t = 1, value_name="COLUMN")]
+  pub tsv_id_column: usize,
+
+  /// The 1-based column of the category in a `--filter-tsv` filter file
+  #[arg(long, default_value_t = 2, value_name="COLUMN")]
+  pub tsv_category_column: usize,
+
+  /// Release the filter set's excess capacity once it's built. This costs a rehash but lowers
+  /// memory use for the rest of the run
+  #[arg(long)]
+  pub shrink_filter_set: bool,
+
+  /// Report the filter set's size and capacity (before and after `--shrink-filter-set`)
+  #[arg(long)]
+  pub filter_set_memory_report: bool,
+
+  /// Cache the parsed filter set in this directory and reuse it on later runs as long as the
+  /// filter file's path, modification time and size (and the excluded categories) are unchanged
+  #[arg(long, value_name="DIR")]
+  pub filter_cache_dir: Option<Utf8PathBuf>,
+
+  /// A TSV of `from<TAB>to` lines mapping Babel curies to the equivalent ids used in the filter
+  /// file. The map only applies to the filter lookup: kept nodes are written with their original
+  /// Babel curie unless `--rewrite-mapped-curie` is set
+  #[arg(long, value_name="FILE")]
+  pub curie_map: Option<Utf8PathBuf>,
+
+  /// Write kept nodes matched through `--curie-map` with the mapped id as their curie. These
+  /// nodes are re-serialized rather than copied verbatim
+  #[arg(long)]
+  pub rewrite_mapped_curie: bool,
+
+  /// A JSON object mapping Babel file names to their expected line counts. A warning is printed
+  /// for each file whose count differs by more than `--tolerance`
+  #[arg(long, value_name="FILE")]
+  pub expected_counts: Option<Utf8PathBuf>,
+
+  /// Allowed difference from the expected line counts, in percent
+  #[arg(long, default_value_t = 0.0, value_name="PERCENT")]
+  pub tolerance: f64,
+
+  /// Exit with a failure status if any of the sanity checks (e.g. `--expected-counts`) fail
+  #[arg(long)]
+  pub fail_on_error: bool,
+
+  /// Write a `.meta.json` sidecar next to each output file with its read/kept counts, duration
+  /// and the filter file's checksum
+  #[arg(long)]
+  pub meta_sidecar: bool,
+
+  /// Set each output file's modification time to that of its Babel file
+  #[arg(long)]
+  pub preserve_mtime: bool,
+
+  /// Write a TSV of `lowercased_name<TAB>curie` lines for every name of every kept node, for
+  /// case-insensitive joins. The pairs are deduplicated in memory before writing, so the index
+  /// can be as large as the kept nodes' names themselves (gzipped if the path ends in `.gz`)
+  #[arg(long, value_name="FILE")]
+  pub names_index: Option<Utf8PathBuf>,
+
+  /// Start each output file with a JSON line marked `"_provenance": true` recording the tool
+  /// version, filter file checksum, timestamp and options used. Consumers should skip it
+  #[arg(long)]
+  pub provenance_header: bool,
+
+  /// A directory of previous outputs to compare against, see `--only-new-curies`
+  #[arg(long, value_name="DIR")]
+  pub baseline_dir: Option<Utf8PathBuf>,
+
+  /// Only keep nodes whose curie isn't in any `--baseline-dir` output, producing a delta of newly
+  /// appearing nodes. Nodes skipped this way still don't end up in NonBabelNodes
+  #[arg(long, requires = "baseline_dir")]
+  pub only_new_curies: bool,
+
+  /// Add the curies in a directory of previous outputs to the filter set, so nodes kept by an
+  /// earlier run are kept again. Seeded curies missing from Babel aren't written to NonBabelNodes
+  /// since they have no filter file record to synthesize them from
+  #[arg(long, value_name="DIR")]
+  pub seed_filter_from_output: Option<Utf8PathBuf>,
+
   /// The json key for the array of strings representing each node's categories
   #[arg(long, default_value_t = String::from("category"), value_name="KEY")]
   pub filter_file_category_key: String,
@@ -36,9 +434,206 @@ pub struct CliArgs {
   pub babel_identifier: String,
 
   /// Force format of all output files. If not set, output files will match their input files.
+  /// A `formats.toml` in the Babel directory mapping file names to formats (e.g.
+  /// `"Gene.txt.gz" = "plaintext"`) overrides this for the files it lists. The `csv` and `tsv`
+  /// formats write a header row and one row per node instead of JSON lines, replacing the
+  /// provenance header, and also apply to NonBabelNodes when set here
   #[clap(short='c', long, value_enum)]
   pub output_format: Option<OutputFormat>,
 
+  /// The delimiter that joins a node's types and taxa in `csv` and `tsv` output
+  #[arg(long, default_value_t = String::from("|"), value_name="DELIMITER")]
+  pub list_delimiter: String,
+
+  /// The gzip compression level for `.gz` outputs, from 0 (no compression) to 9 (smallest).
+  /// Defaults to flate2's default level, 6. It's also the level of `.bz2` outputs, where 0 is
+  /// the same as 1
+  #[arg(long, value_name="LEVEL")]
+  pub compression_level: Option<u32>,
+
+  /// The zstd compression level for `.zst` outputs, from 1 (fastest) to 22 (smallest)
+  #[arg(long, default_value_t = 3, value_name="LEVEL")]
+  pub zstd_level: i32,
+
+  /// Print a table of read/kept counts per curie prefix, sorted by lowest keep rate
+  #[arg(long)]
+  pub per_prefix_stats: bool,
+
+  /// Print the number of distinct types across all kept nodes. Every distinct type is held in
+  /// memory, which can be a lot on a huge type vocabulary.
+  #[arg(long)]
+  pub count_distinct_types: bool,
+
+  /// Also print every distinct type, sorted
+  #[arg(long, requires = "count_distinct_types")]
+  pub list_types: bool,
+
+  /// Fail the run if more than this percentage of the filter set wasn't found in Babel, which
+  /// usually means the filter file is stale
+  #[arg(long, value_name="PERCENT")]
+  pub max_nonbabel_fraction: Option<f64>,
+
+  /// Print the percentage of filter nodes that were found in Babel, i.e. didn't end up in
+  /// NonBabelNodes
+  #[arg(long)]
+  pub report_coverage: bool,
+
+  /// Print how many megabytes and Babel nodes were read per second, overall and for each Babel
+  /// file. Bytes are counted after decompression
+  #[arg(long)]
+  pub report_throughput: bool,
+
+  /// Count valid nodes in a first pass over each Babel file so the kept percentage excludes
+  /// malformed lines. Note that this reads (and decompresses) every Babel file twice
+  #[arg(long)]
+  pub two_pass: bool,
+
+  /// Debugging aid: write a `curie<TAB>reason` line to this file for every Babel node that isn't
+  /// kept, naming the first filter that rejected it. This is usually most of Babel, so expect a
+  /// large file. Nodes dropped by `--keep-largest-per-prefix` aren't logged
+  #[arg(long, value_name="FILE")]
+  pub debug_drop_reasons: Option<Utf8PathBuf>,
+
+  /// Debugging aid: write every filter file or Babel line that fails to parse to this file,
+  /// verbatim, as `file name<TAB>line number<TAB>line`. Filter files read with
+  /// `--filter-tolerant-parse` aren't split into lines, so their parse errors aren't written
+  #[arg(long, value_name="FILE")]
+  pub failed_lines_output: Option<Utf8PathBuf>,
+
+  /// Split Babel records on this byte instead of newlines, and end output records (including
+  /// NonBabelNodes) with it. Given as a single character or a hex escape such as `\x1e`. The
+  /// filter file is still read line by line
+  #[arg(long, value_name="BYTE", value_parser = parse_byte)]
+  pub record_separator: Option<u8>,
+
+  /// Print progress messages (timings and per-file results) to stderr, leaving only the final
+  /// summary on stdout. On by default; `--progress-to-stderr=false` prints them to stdout with
+  /// the summary
+  #[arg(
+    long,
+    value_name = "BOOL",
+    action = ArgAction::Set,
+    num_args = 0..=1,
+    default_value_t = true,
+    default_missing_value = "true"
+  )]
+  pub progress_to_stderr: bool,
+
+  /// Don't print progress messages or line count reports, only the final summary
+  #[arg(long)]
+  pub no_progress: bool,
+
+  /// Don't print a line per Babel file as it's filtered, and instead print a table of every
+  /// file's read and kept counts and duration once they're all done
+  #[arg(long)]
+  pub summary_table: bool,
+
+  /// Also write a JSON summary of the run to FILE: the filter nodes read and excluded, each Babel
+  /// file's read and kept counts and duration, the NonBabelNodes written and the total duration
+  #[arg(long, value_name = "FILE")]
+  pub report_json: Option<Utf8PathBuf>,
+
+  /// Don't print progress messages or the summary, only warnings, errors and the
+  /// `--summary-table` table if it's set
+  #[arg(long, short)]
+  pub quiet: bool,
+
+  /// Report how many lines of each Babel file have been read, and how long that took, every
+  /// this many lines. On a terminal the report is updated in place when filtering one file at a
+  /// time; otherwise each report is its own line. 0 turns the reports off
+  #[arg(long, default_value_t = 1_000_000, value_name="LINES")]
+  pub progress_every: usize,
+
+  /// A TSV of `file name<TAB>line count` lines giving the number of lines in each Babel file, so
+  /// progress reports can show the percentage read, which the size of a compressed file can't
+  /// give. Files without a count are reported by lines read alone
+  #[arg(long, value_name="FILE")]
+  pub progress_from_counts: Option<Utf8PathBuf>,
+
+  /// The maximum number of Babel files to filter at once. Each file being filtered holds a read
+  /// and a write buffer, so this bounds memory use and open files. A filter id matched by nodes
+  /// in several Babel files is still only kept by the first file to reach it, which above 1
+  /// depends on how the files are scheduled (see `--per-file-matches`)
+  #[arg(long, visible_alias = "threads", default_value_t = 1, value_name="FILES")]
+  pub concurrency: usize,
+
+  /// Keep a filter id matched by nodes in several Babel files in each of them, rather than only
+  /// in the first file to reach it. The filter set is only read while files are filtered and the
+  /// ids each file matched are removed from it afterwards, so the output doesn't depend on how
+  /// files are scheduled with `--concurrency`
+  #[arg(long)]
+  pub per_file_matches: bool,
+
+  /// Filter these Babel files as one file, reading them in the order given, so options that
+  /// work per file (such as `--chunk-lines` or `--keep-largest-per-prefix`) span all of them.
+  /// The output is named after the first file, without its part number if it has one (e.g.
+  /// `Gene.part0001.txt` is written to `Gene.txt`). Each file should end with a record
+  /// separator. Multiple files can be specified by using the flag again
+  #[arg(long, value_name="FILE_NAME")]
+  pub concat_inputs: Option<Vec<String>>,
+
+  /// Only filter the files in the Babel directory whose names match one of these globs, e.g.
+  /// `*.txt.gz`, where `*` matches any run of characters and `?` any one character. Other files
+  /// are skipped without being opened. Without a glob every file is filtered except hidden ones
+  /// such as `.DS_Store`. Files listed in `--concat-inputs` are filtered whatever their name.
+  /// Multiple globs can be specified by using the flag again
+  #[arg(long, value_name="GLOB")]
+  pub include_glob: Option<Vec<String>>,
+
+  /// Split the filter set into this many shards, each with its own lock. Files filtered at once
+  /// (see `--concurrency`) only take read locks on it, which don't wait on each other, and on a
+  /// single core 16 shards measured slower than 1. Only raise it after measuring a speedup on the
+  /// target machine
+  #[arg(long, default_value_t = 1, value_name="N")]
+  pub filter_set_shards: usize,
+
+  /// A safety limit against pointing at the wrong filter file: stop with an error, before
+  /// filtering anything, once the filter set would hold more than this many ids
+  #[arg(long, value_name="IDS")]
+  pub max_filter_set_size: Option<usize>,
+
+  /// Read each Babel file ahead on a background thread, queueing up to this many bytes. This
+  /// only helps on slow sequential storage such as spinning disks or network mounts
+  #[arg(long, value_name="BYTES")]
+  pub read_ahead: Option<usize>,
+
+  /// Convert leftover filter nodes to NonBabelNodes on this many threads. Above 1, every
+  /// converted node is held in memory until they're all converted. The speedup hasn't been
+  /// measured on more than one core (on one, 4 threads were slightly slower), so measure before
+  /// raising it
+  #[arg(long, default_value_t = 1, value_name="THREADS")]
+  pub nonbabel_threads: usize,
+
+  /// The file name of NonBabelNodes in the output directory, e.g. to keep several runs into the
+  /// same directory apart. `--output-format` still sets its extension. Defaults to
+  /// `NonBabelNodes.txt.gz`
+  #[arg(long, value_name="FILE_NAME")]
+  pub non_babel_nodes_name: Option<String>,
+
+  /// Don't write NonBabelNodes at all. Only the ids of the filter nodes are kept in memory then,
+  /// which saves memory, while leftovers are still tracked for `--leftovers-as-ids` and
+  /// `--report-coverage`
+  #[arg(long)]
+  pub no_non_babel_nodes: bool,
+
+  /// Write NonBabelNodes sorted by curie rather than in arbitrary order
+  #[arg(long)]
+  pub stable_order: bool,
+
+  /// Capacity of the buffer over each gzipped Babel file's decompressed stream, in bytes. The
+  /// default matches the compressed read buffer; larger values may help on some systems, so
+  /// measure before raising it
+  #[arg(long, default_value_t = 32_000, value_name="BYTES")]
+  pub decode_buffer: usize,
+
+  /// Decompress each gzipped Babel file on its own thread, separate from the thread parsing its
+  /// records. This only helps when there are spare cores, i.e. more cores than `--concurrency`
+  /// files being filtered at once; otherwise the threads just compete for the same cores. It has
+  /// only been measured on a single core, where the difference was within noise, so measure
+  /// before relying on it
+  #[arg(long)]
+  pub decode_threads: bool,
+
   /// read buffer capacity, in bytes
   #[arg(long, default_value_t = 32_000, value_name="BYTES")]
   pub read_buf_capacity: usize,
@@ -48,8 +643,98 @@ pub struct CliArgs {
   pub write_buf_capacity: usize,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+  /// Split existing output files into parts of `--chunk-lines` lines without re-filtering.
+  /// Files already split into parts are re-split as one file, keeping their compression
+  Rechunk {
+    /// The directory of output files to rechunk in place
+    directory: Utf8PathBuf,
+
+    /// The maximum number of lines per part
+    #[arg(long, value_name="LINES")]
+    chunk_lines: usize,
+
+    /// The byte the outputs were written to split records on, as with a run's
+    /// `--record-separator`. The parts end records with it too
+    #[arg(long, value_name="BYTE", value_parser = parse_byte)]
+    record_separator: Option<u8>,
+  },
+
+  /// Print node counts for existing output files without re-filtering, in the same table as
+  /// `--summary-table`. Metadata sidecars and provenance headers are skipped
+  Summary {
+    /// The directory of output files to summarize
+    directory: Utf8PathBuf,
+
+    /// Also print node counts by curie prefix
+    #[arg(long)]
+    per_prefix_stats: bool,
+
+    /// Also print node counts by type
+    #[arg(long)]
+    per_type_stats: bool,
+
+    /// The byte the outputs were written to split records on, as with a run's
+    /// `--record-separator`
+    #[arg(long, value_name="BYTE", value_parser = parse_byte)]
+    record_separator: Option<u8>,
+  },
+
+  /// Print how many ids were added, removed, and kept between two filter files
+  FilterDiff {
+    /// The current filter file
+    old: Utf8PathBuf,
+
+    /// The filter file to compare it to
+    new: Utf8PathBuf,
+
+    /// Write the added ids to this file, one per line
+    #[arg(long, value_name="FILE")]
+    added: Option<Utf8PathBuf>,
+
+    /// Write the removed ids to this file, one per line
+    #[arg(long, value_name="FILE")]
+    removed: Option<Utf8PathBuf>,
+  },
+}
+
+/// Parses a single ASCII character or a `\xHH`/`0xHH` hex escape as a byte
+fn parse_byte(arg: &str) -> Result<u8, String> {
+  let hex = arg.strip_prefix("\\x").or_else(|| arg.strip_prefix("0x"));
+  match (hex, arg.as_bytes()) {
+    (Some(hex), _) => u8::from_str_radix(hex, 16).map_err(|e| format!("invalid hex byte: {e}")),
+    (None, &[byte]) if byte.is_ascii() => Ok(byte),
+    _ => Err(String::from("expected a single ASCII character or a hex escape like \\x1e")),
+  }
+}
+
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum OutputFormat {
   Gzipped,
+  Bzip2,
   Plaintext,
+  Zstd,
+  /// Uncompressed comma-separated rows of curie, preferred name, types and taxa
+  Csv,
+  /// Uncompressed tab-separated rows of curie, preferred name, types and taxa
+  Tsv,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum UnicodeForm {
+  /// Canonical composition
+  Nfc,
+  /// Compatibility composition, which also folds e.g. ligatures and superscripts
+  Nfkc,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum ConflictPolicy {
+  /// Keep the node already in the directory and drop the new one
+  KeepExisting,
+  /// Replace the node already in the directory with the new one
+  KeepNew,
+  /// Fail the run without merging anything
+  Error,
 }
@@ -1,22 +1,161 @@
 mod args;
 
-use babel_filter;
 use clap::Parser;
 use std::process::ExitCode;
 
 fn main() -> ExitCode {
     let args = args::CliArgs::parse();
 
+    match args.command {
+        Some(args::Command::Rechunk { directory, chunk_lines, record_separator }) => {
+            return babel_filter::rechunk(&directory, chunk_lines, record_separator);
+        }
+        Some(args::Command::Summary {
+            directory,
+            per_prefix_stats,
+            per_type_stats,
+            record_separator,
+        }) => {
+            return babel_filter::summary(
+                &directory,
+                per_prefix_stats,
+                per_type_stats,
+                record_separator,
+            );
+        }
+        Some(args::Command::FilterDiff { old, new, added, removed }) => {
+            return babel_filter::filter_diff(&old, &new, added.as_deref(), removed.as_deref());
+        }
+        None => (),
+    }
+
+    // clap requires the positional arguments unless a subcommand is given
+    let babel_directory = args.babel_directory.unwrap();
+    let output_directory = args.output_directory.unwrap();
     let filter_args = babel_filter::Config {
-        babel_directory: args.babel_directory,
+        stdin: babel_directory == "-",
+        stdout: output_directory == "-",
+        babel_directory,
         exclude_category: args.exclude_category,
-        filter_file: args.filter_file,
-        output_directory: args.output_directory,
+        include_category: args.include_category,
+        ignore_category_case: args.ignore_category_case,
+        category_regex: args.category_regex,
+        filter_file: args.filter_file.unwrap(),
+        output_directory,
         output_format: match args.output_format {
             Some(args::OutputFormat::Gzipped) => Some(babel_filter::OutputFormat::Gzipped),
+            Some(args::OutputFormat::Bzip2) => Some(babel_filter::OutputFormat::Bzip2),
             Some(args::OutputFormat::Plaintext) => Some(babel_filter::OutputFormat::Plaintext),
+            Some(args::OutputFormat::Zstd) => Some(babel_filter::OutputFormat::Zstd),
+            Some(args::OutputFormat::Csv) => Some(babel_filter::OutputFormat::Csv),
+            Some(args::OutputFormat::Tsv) => Some(babel_filter::OutputFormat::Tsv),
             None => None,
         },
+        compression_level: args.compression_level,
+        zstd_level: args.zstd_level,
+        list_delimiter: args.list_delimiter,
+        per_prefix_stats: args.per_prefix_stats,
+        count_distinct_types: args.count_distinct_types,
+        list_types: args.list_types,
+        nonbabel_prefix: args.nonbabel_prefix,
+        leftovers_as_ids: args.leftovers_as_ids,
+        two_pass: args.two_pass,
+        where_pointer: args.where_pointer,
+        max_output_names: args.max_output_names,
+        dedup_taxa: args.dedup_taxa,
+        sort_names: args.sort_names,
+        filter_cache_dir: args.filter_cache_dir,
+        expected_counts: args.expected_counts,
+        tolerance: args.tolerance,
+        fail_on_error: args.fail_on_error,
+        meta_sidecar: args.meta_sidecar,
+        seed_filter_from_output: args.seed_filter_from_output,
+        progress_to_stderr: args.progress_to_stderr,
+        no_progress: args.no_progress,
+        summary_table: args.summary_table,
+        report_json: args.report_json,
+        quiet: args.quiet,
+        progress_every: args.progress_every,
+        keep_largest_per_prefix: args.keep_largest_per_prefix,
+        group_by_prefix: args.group_by_prefix,
+        bloom_filter: args.bloom_filter,
+        bloom_fp_rate: args.bloom_fp_rate,
+        ids_only: args.ids_only,
+        chunk_lines: args.chunk_lines,
+        chunk_bytes: args.chunk_bytes,
+        preallocate: args.preallocate,
+        validate_equivalents: args.validate_equivalents,
+        merge_duplicate_filter_records: args.merge_duplicate_filter_records,
+        match_equivalents: args.match_equivalents,
+        ignore_curie_case: args.ignore_curie_case,
+        filter_tolerant_parse: args.filter_tolerant_parse,
+        filter_tsv: args.filter_tsv,
+        tsv_id_column: Some(args.tsv_id_column),
+        tsv_category_column: Some(args.tsv_category_column),
+        provenance_header: args.provenance_header,
+        concurrency: args.concurrency,
+        per_file_matches: args.per_file_matches,
+        concat_inputs: args.concat_inputs,
+        include_glob: args.include_glob,
+        exclude_curie_regex: args.exclude_curie_regex,
+        filter_set_shards: args.filter_set_shards,
+        max_filter_set_size: args.max_filter_set_size,
+        strip_empty_fields: args.strip_empty_fields,
+        empty_taxa_null: args.empty_taxa_null,
+        verify_roundtrip: args.verify_roundtrip,
+        preserve_key_order: args.preserve_key_order,
+        normalize_unicode: args.normalize_unicode.map(|form| match form {
+            args::UnicodeForm::Nfc => babel_filter::UnicodeForm::Nfc,
+            args::UnicodeForm::Nfkc => babel_filter::UnicodeForm::Nfkc,
+        }),
+        report_coverage: args.report_coverage,
+        report_throughput: args.report_throughput,
+        curie_map: args.curie_map,
+        progress_from_counts: args.progress_from_counts,
+        rewrite_mapped_curie: args.rewrite_mapped_curie,
+        max_nonbabel_fraction: args.max_nonbabel_fraction,
+        preserve_mtime: args.preserve_mtime,
+        baseline_dir: args.baseline_dir,
+        only_new_curies: args.only_new_curies,
+        read_ahead: args.read_ahead,
+        decode_buffer: Some(args.decode_buffer),
+        decode_threads: args.decode_threads,
+        names_index: args.names_index,
+        comment_prefix: args.comment_prefix,
+        nonbabel_threads: args.nonbabel_threads,
+        non_babel_nodes_name: args.non_babel_nodes_name,
+        no_non_babel_nodes: args.no_non_babel_nodes,
+        stable_order: args.stable_order,
+        require_all_types: args.require_all_types,
+        require_category: args.require_category,
+        taxa: args.taxa,
+        keep_untaxed: args.keep_untaxed,
+        curie_numeric_range: args.curie_numeric_range,
+        keep_non_numeric: args.keep_non_numeric,
+        debug_drop_reasons: args.debug_drop_reasons,
+        failed_lines_output: args.failed_lines_output,
+        shrink_filter_set: args.shrink_filter_set,
+        filter_set_memory_report: args.filter_set_memory_report,
+        record_separator: args.record_separator,
+        preferred_name_in_file: args.preferred_name_in_file,
+        exclude_curie_file: args.exclude_curie_file,
+        global_dedup: args.global_dedup,
+        max_errors_per_file: args.max_errors_per_file,
+        repair_json: args.repair_json,
+        dry_run: args.dry_run,
+        output_subdir_per_file: args.output_subdir_per_file,
+        assert_no_duplicates: args.assert_no_duplicates,
+        report_duplicates: args.report_duplicates,
+        keep_duplicates: args.keep_duplicates,
+        min_name_length: args.min_name_length,
+        max_name_length: args.max_name_length,
+        merge_into: args.merge_into,
+        on_conflict: match args.on_conflict {
+            args::ConflictPolicy::KeepExisting => babel_filter::ConflictPolicy::KeepExisting,
+            args::ConflictPolicy::KeepNew => babel_filter::ConflictPolicy::KeepNew,
+            args::ConflictPolicy::Error => babel_filter::ConflictPolicy::Error,
+        },
+        prune_orphans: args.prune_orphans,
     };
 
     babel_filter::run(filter_args)
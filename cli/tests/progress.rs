@@ -0,0 +1,127 @@
+use std::{
+    fs, io,
+    path::Path,
+    process::{Command, Output},
+};
+
+use tempfile::tempdir;
+
+/// The Babel file of `run_cli`, with the one node in the filter file
+const DISEASE_NODE: &str =
+    "{\"curie\":\"MONDO:1\",\"names\":[\"a\"],\"types\":[\"Disease\"],\"taxa\":[]}\n";
+
+/// Runs the CLI over a Babel directory of `Disease.txt` with `babel_lines`, and a one-node filter
+/// file, in `dir` with `args`
+fn run_cli(dir: &Path, babel_lines: impl AsRef<[u8]>, args: &[&str]) -> io::Result<Output> {
+    let babel_directory = dir.join("babel");
+    let output_directory = dir.join("output");
+    let filter_file = dir.join("filter.jsonl");
+    fs::create_dir_all(&babel_directory)?;
+    fs::create_dir_all(&output_directory)?;
+    fs::write(
+        &filter_file,
+        "{\"id\":\"MONDO:1\",\"name\":\"a\",\"category\":[\"biolink:Disease\"]}\n",
+    )?;
+    fs::write(babel_directory.join("Disease.txt"), babel_lines)?;
+
+    Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg(&babel_directory)
+        .arg(&filter_file)
+        .arg(&output_directory)
+        .args(args)
+        .output()
+}
+
+/// Asserts that stdout has only the summary lines of a run
+fn assert_only_summary(output: &Output) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout_lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(stdout_lines.len(), 3, "unexpected stdout: {stdout}");
+    assert_eq!(stdout_lines[0], "0 nodes excluded");
+    assert_eq!(stdout_lines[1], "Wrote an extra 0 nodes to NonBabelNodes.txt.gz");
+    assert!(stdout_lines[2].starts_with("Program took"));
+}
+
+#[test]
+fn progress_to_stderr_keeps_stdout_for_summary() -> io::Result<()> {
+    let dir = tempdir()?;
+
+    for args in [&[][..], &["--progress-to-stderr"][..]] {
+        let output = run_cli(dir.path(), DISEASE_NODE, args)?;
+        assert!(output.status.success());
+
+        assert_only_summary(&output);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Creating filter set took"));
+        assert!(stderr.contains("Writing \"Disease.txt\""));
+    }
+
+    let output = run_cli(dir.path(), DISEASE_NODE, &["--progress-to-stderr=false"])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Creating filter set took"));
+    assert!(stdout.contains("0 nodes excluded"));
+
+    dir.close()
+}
+
+#[test]
+fn no_progress_prints_only_summary() -> io::Result<()> {
+    let dir = tempdir()?;
+
+    let output = run_cli(dir.path(), DISEASE_NODE, &["--no-progress"])?;
+    assert!(output.status.success());
+
+    assert_only_summary(&output);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    dir.close()
+}
+
+#[test]
+fn two_pass_reports_kept_of_valid_nodes() -> io::Result<()> {
+    let dir = tempdir()?;
+    let babel_lines = [
+        DISEASE_NODE,
+        "not json\n",
+        "{\"curie\":\"MONDO:2\",\"names\":[\"b\"],\"types\":[\"Disease\"],\"taxa\":[]}\n",
+    ]
+    .concat();
+
+    for (args, kept) in [
+        (&[][..], "kept 1/3 nodes (33.33%)"),
+        (&["--two-pass"][..], "kept 1/2 valid nodes (50.00%)"),
+    ] {
+        let output = run_cli(dir.path(), &babel_lines, args)?;
+        assert!(output.status.success());
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let report = stderr
+            .lines()
+            .find(|line| line.starts_with("Writing \"Disease.txt\" took "))
+            .unwrap_or_else(|| panic!("no report for Disease.txt: {stderr}"));
+        assert!(report.ends_with(kept), "unexpected report: {report}");
+    }
+
+    dir.close()
+}
+
+#[test]
+fn two_pass_counts_valid_nodes_past_invalid_utf8() -> io::Result<()> {
+    let dir = tempdir()?;
+    let other_node =
+        "{\"curie\":\"MONDO:2\",\"names\":[\"b\"],\"types\":[\"Disease\"],\"taxa\":[]}\n";
+    let babel_lines = [other_node.as_bytes(), b"\xff\xfe\n", DISEASE_NODE.as_bytes()].concat();
+
+    let output = run_cli(dir.path(), &babel_lines, &["--two-pass"])?;
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let report = stderr
+        .lines()
+        .find(|line| line.starts_with("Writing \"Disease.txt\" took "))
+        .unwrap_or_else(|| panic!("no report for Disease.txt: {stderr}"));
+    assert!(report.ends_with("kept 1/2 valid nodes (50.00%)"), "unexpected report: {report}");
+
+    dir.close()
+}
@@ -0,0 +1,50 @@
+use std::{
+    fs,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use tempfile::tempdir;
+
+#[test]
+fn filters_gzipped_stdin_to_stdout() -> io::Result<()> {
+    let dir = tempdir()?;
+    let filter_file = dir.path().join("filter.jsonl");
+    fs::write(
+        &filter_file,
+        "{\"id\":\"MONDO:1\",\"name\":\"a\",\"category\":[\"biolink:Disease\"]}\n",
+    )?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(
+        concat!(
+            "{\"curie\":\"MONDO:1\",\"names\":[\"a\"],\"types\":[\"Disease\"],\"taxa\":[]}\n",
+            "{\"curie\":\"MONDO:2\",\"names\":[\"b\"],\"types\":[\"Disease\"],\"taxa\":[]}\n",
+        )
+        .as_bytes(),
+    )?;
+    let babel_records = encoder.finish()?;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg("-")
+        .arg(&filter_file)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(&babel_records)?;
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stdout,
+        "{\"curie\":\"MONDO:1\",\"names\":[\"a\"],\"types\":[\"Disease\"],\"taxa\":[]}\n"
+    );
+    assert!(stderr.contains("Writing \"stdin.txt\""));
+    assert!(stderr.contains("Program took"));
+
+    dir.close()
+}